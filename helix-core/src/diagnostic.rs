@@ -1,12 +1,14 @@
 //! LSP diagnostic utility types.
+use serde::Deserialize;
 
-/// Describes the severity level of a [`Diagnostic`].
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// Describes the severity level of a [`Diagnostic`]. Ordered from least to most severe.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Severity {
-    Error,
-    Warning,
-    Info,
     Hint,
+    Info,
+    Warning,
+    Error,
 }
 
 /// A range of `char`s within the text.
@@ -16,6 +18,15 @@ pub struct Range {
     pub end: usize,
 }
 
+/// Corresponds to [`lsp_types::DiagnosticTag`](https://docs.rs/lsp-types/0.91.0/lsp_types/struct.DiagnosticTag.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticTag {
+    /// Unused or unnecessary code.
+    Unnecessary,
+    /// Deprecated or obsolete code.
+    Deprecated,
+}
+
 /// Corresponds to [`lsp_types::Diagnostic`](https://docs.rs/lsp-types/0.91.0/lsp_types/struct.Diagnostic.html)
 #[derive(Debug, Clone)]
 pub struct Diagnostic {
@@ -23,4 +34,9 @@ pub struct Diagnostic {
     pub line: usize,
     pub message: String,
     pub severity: Option<Severity>,
+    pub tags: Vec<DiagnosticTag>,
+    /// Whether the language server offers quick fixes for this diagnostic.
+    pub has_code_actions: bool,
+    /// Tool that reported the diagnostic, e.g. `rustc` or `clippy`.
+    pub source: Option<String>,
 }
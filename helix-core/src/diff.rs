@@ -53,10 +53,113 @@ pub fn compare_ropes(old: &Rope, new: &Rope) -> Transaction {
     )
 }
 
+/// How a line differs from an older version of the text, see [`compare_lines`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineDiff {
+    Added,
+    Modified,
+    /// Lines of the old text were removed right above this line.
+    Deleted,
+}
+
+/// Number of changed lines in the result of [`compare_lines`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffStat {
+    pub added: usize,
+    pub modified: usize,
+    /// Places where lines were deleted. The number of deleted lines isn't known.
+    pub deleted: usize,
+}
+
+impl DiffStat {
+    pub fn new(lines: &[(usize, LineDiff)]) -> Self {
+        let mut stat = Self::default();
+        for (_, diff) in lines {
+            match diff {
+                LineDiff::Added => stat.added += 1,
+                LineDiff::Modified => stat.modified += 1,
+                LineDiff::Deleted => stat.deleted += 1,
+            }
+        }
+        stat
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Compares `old` and `new` line by line and returns how the lines of `new` differ from `old`,
+/// sorted by line. Unchanged lines are left out.
+pub fn compare_lines(old: &Rope, new: &Rope) -> Vec<(usize, LineDiff)> {
+    let old_converted = old.to_string();
+    let new_converted = new.to_string();
+
+    // See `compare_ropes` for why a timeout is set.
+    let mut config = similar::TextDiff::configure();
+    config.timeout(std::time::Duration::from_secs(1));
+
+    let diff = config.diff_lines(&old_converted, &new_converted);
+
+    let mut lines: Vec<(usize, LineDiff)> = Vec::new();
+    for op in diff.ops() {
+        let (tag, _, new_range) = op.as_tag_tuple();
+        let kind = match tag {
+            similar::DiffTag::Equal => continue,
+            similar::DiffTag::Insert => LineDiff::Added,
+            similar::DiffTag::Replace => LineDiff::Modified,
+            similar::DiffTag::Delete => {
+                if lines.last().map(|&(line, _)| line) != Some(new_range.start) {
+                    lines.push((new_range.start, LineDiff::Deleted));
+                }
+                continue;
+            }
+        };
+        // A deletion right before changed lines is already covered by the change.
+        if lines.last().map(|&(line, _)| line) == Some(new_range.start) {
+            lines.pop();
+        }
+        lines.extend(new_range.map(|line| (line, kind)));
+    }
+    lines
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_compare_lines() {
+        let old = Rope::from("1\n2\n3\n4\n5\n");
+        let new = Rope::from("1\nX\n3\n5\nnew\n");
+        assert_eq!(
+            compare_lines(&old, &new),
+            vec![
+                (1, LineDiff::Modified),
+                (3, LineDiff::Deleted),
+                (4, LineDiff::Added)
+            ]
+        );
+        assert_eq!(compare_lines(&old, &old), vec![]);
+    }
+
+    #[test]
+    fn test_diff_stat() {
+        let old = Rope::from("1\n2\n3\n4\n5\n6\n");
+        let new = Rope::from("1\nX\n3\n5\nnew\nnewer\n6\n");
+        let stat = DiffStat::new(&compare_lines(&old, &new));
+        assert_eq!(
+            stat,
+            DiffStat {
+                added: 2,
+                modified: 1,
+                deleted: 1
+            }
+        );
+        assert!(!stat.is_empty());
+        assert!(DiffStat::new(&compare_lines(&old, &old)).is_empty());
+    }
+
     quickcheck::quickcheck! {
         fn test_compare_ropes(a: String, b: String) -> bool {
             let mut old = Rope::from(a);
@@ -2,7 +2,13 @@ use helix_core::{merge_toml_values, syntax};
 use helix_lsp::{lsp, util::lsp_pos_to_pos, LspProgressMap};
 use helix_view::{theme, Editor};
 
-use crate::{args::Args, compositor::Compositor, config::Config, job::Jobs, ui};
+use crate::{
+    args::Args,
+    compositor::Compositor,
+    config::Config,
+    job::{self, Jobs},
+    ui,
+};
 
 use log::{error, warn};
 
@@ -237,6 +243,26 @@ impl Application {
                     self.jobs.handle_callback(&mut self.editor, &mut self.compositor, callback);
                     self.render();
                 }
+                Some((id, version, blame)) = self.editor.blame_receiver.recv() => {
+                    // the blame of an outdated version would be on the wrong lines
+                    if let Some(doc) = self.editor.documents.get_mut(&id) {
+                        if doc.version() == version {
+                            doc.set_blame(blame);
+                        }
+                    }
+                    self.render();
+                }
+                Some((id, diff_base)) = self.editor.diff_base_receiver.recv() => {
+                    if let Some(doc) = self.editor.documents.get_mut(&id) {
+                        doc.set_diff_base(diff_base);
+                    }
+                }
+                Some((id, version, line_diffs)) = self.editor.line_diff_receiver.recv() => {
+                    if let Some(doc) = self.editor.documents.get_mut(&id) {
+                        doc.set_line_diffs(version, line_diffs);
+                    }
+                    self.render();
+                }
                 _ = &mut self.editor.idle_timer => {
                     // idle timeout
                     self.editor.clear_idle_timer();
@@ -275,6 +301,8 @@ impl Application {
         use crate::commands::{insert::idle_completion, Context};
         use helix_view::document::Mode;
 
+        self.request_document_symbols();
+
         if doc!(self.editor).mode != Mode::Insert || !self.config.editor.auto_completion {
             return;
         }
@@ -299,6 +327,81 @@ impl Application {
         self.render();
     }
 
+    /// Asks the language server for the symbols of the focused document if the symbol gutter
+    /// is enabled and the document changed since they were last requested.
+    fn request_document_symbols(&mut self) {
+        use helix_view::document::Symbol;
+
+        fn flatten(symbols: &mut Vec<Symbol>, symbol: lsp::DocumentSymbol) {
+            symbols.push(Symbol {
+                line: symbol.selection_range.start.line as usize,
+                kind: symbol.kind,
+            });
+            for child in symbol.children.into_iter().flatten() {
+                flatten(symbols, child);
+            }
+        }
+
+        if !self.editor.gutters.contains("symbols") {
+            return;
+        }
+        let doc = doc_mut!(self.editor);
+        let version = doc.version();
+        if doc.symbols_version() == Some(version) {
+            return;
+        }
+        let language_server = match doc.language_server() {
+            Some(language_server) if language_server.is_initialized() => language_server,
+            _ => return,
+        };
+        if matches!(
+            language_server.capabilities().document_symbol_provider,
+            None | Some(lsp::OneOf::Left(false))
+        ) {
+            // don't ask again until the document changes
+            doc.set_symbols(version, Vec::new());
+            return;
+        }
+        let future = language_server.document_symbols(doc.identifier());
+        let doc_id = doc.id();
+        // mark the symbols as requested, so that they aren't requested again in the meantime
+        doc.set_symbols(version, doc.symbols().to_vec());
+
+        self.jobs.callback(async move {
+            let json = future.await?;
+            let response: Option<lsp::DocumentSymbolResponse> = serde_json::from_value(json)?;
+            let call: job::Callback = Box::new(move |editor: &mut Editor, _| {
+                let doc = match editor.documents.get_mut(&doc_id) {
+                    Some(doc) => doc,
+                    None => return,
+                };
+                // the lines are off if the document changed in the meantime
+                if doc.version() != version {
+                    return;
+                }
+                let symbols = match response {
+                    Some(lsp::DocumentSymbolResponse::Flat(symbols)) => symbols
+                        .into_iter()
+                        .map(|symbol| Symbol {
+                            line: symbol.location.range.start.line as usize,
+                            kind: symbol.kind,
+                        })
+                        .collect(),
+                    Some(lsp::DocumentSymbolResponse::Nested(nested)) => {
+                        let mut symbols = Vec::new();
+                        for symbol in nested {
+                            flatten(&mut symbols, symbol);
+                        }
+                        symbols
+                    }
+                    None => Vec::new(),
+                };
+                doc.set_symbols(version, symbols);
+            });
+            Ok(call)
+        });
+    }
+
     pub fn handle_terminal_events(&mut self, event: Option<Result<Event, crossterm::ErrorKind>>) {
         let mut cx = crate::compositor::Context {
             editor: &mut self.editor,
@@ -371,17 +474,29 @@ impl Application {
                     }
                     Notification::PublishDiagnostics(params) => {
                         let path = params.uri.to_file_path().unwrap();
+                        // quick fixes are only asked for if a view of the document marks them
+                        let code_actions = self.editor.document_by_path(&path).is_some_and(|doc| {
+                            self.editor.tree.views().any(|(view, _)| {
+                                view.doc == doc.id() && view.gutter_config.diagnostics_code_actions
+                            })
+                        });
                         let doc = self.editor.document_by_path_mut(&path);
 
                         if let Some(doc) = doc {
                             let text = doc.text();
 
+                            let lsp_diagnostics = if code_actions {
+                                params.diagnostics.clone()
+                            } else {
+                                Vec::new()
+                            };
+
                             let diagnostics = params
                                 .diagnostics
                                 .into_iter()
                                 .filter_map(|diagnostic| {
                                     use helix_core::{
-                                        diagnostic::{Range, Severity::*},
+                                        diagnostic::{DiagnosticTag, Range, Severity::*},
                                         Diagnostic,
                                     };
                                     use lsp::DiagnosticSeverity;
@@ -424,13 +539,81 @@ impl Application {
                                                 severity => unimplemented!("{:?}", severity),
                                             },
                                         ),
+                                        tags: diagnostic
+                                            .tags
+                                            .unwrap_or_default()
+                                            .into_iter()
+                                            .filter_map(|tag| match tag {
+                                                lsp::DiagnosticTag::UNNECESSARY => {
+                                                    Some(DiagnosticTag::Unnecessary)
+                                                }
+                                                lsp::DiagnosticTag::DEPRECATED => {
+                                                    Some(DiagnosticTag::Deprecated)
+                                                }
+                                                _ => None,
+                                            })
+                                            .collect(),
+                                        has_code_actions: false,
+                                        source: diagnostic.source,
                                         // code
-                                        // source
                                     })
                                 })
                                 .collect();
 
                             doc.set_diagnostics(diagnostics);
+
+                            // ask for the quick fixes of all diagnostics in one request so the
+                            // gutter can mark them
+                            let language_server = doc.language_server();
+                            if let (Some(language_server), Some(start), Some(end)) = (
+                                language_server,
+                                lsp_diagnostics.iter().map(|d| d.range.start).min(),
+                                lsp_diagnostics.iter().map(|d| d.range.end).max(),
+                            ) {
+                                // actions that don't name their diagnostics can only be
+                                // attributed if a single one was asked about
+                                let sole = match lsp_diagnostics.as_slice() {
+                                    [diagnostic] => Some(diagnostic.clone()),
+                                    _ => None,
+                                };
+                                let future = language_server.code_actions(
+                                    doc.identifier(),
+                                    lsp::Range::new(start, end),
+                                    lsp::CodeActionContext {
+                                        diagnostics: lsp_diagnostics,
+                                        only: Some(vec![lsp::CodeActionKind::QUICKFIX]),
+                                    },
+                                );
+                                self.jobs.callback(async move {
+                                    let json = future.await?;
+                                    let response: Option<lsp::CodeActionResponse> =
+                                        serde_json::from_value(json)?;
+                                    let fixed: Vec<lsp::Diagnostic> = response
+                                        .into_iter()
+                                        .flatten()
+                                        .flat_map(|action| match action {
+                                            lsp::CodeActionOrCommand::CodeAction(action) => action
+                                                .diagnostics
+                                                .unwrap_or_else(|| sole.iter().cloned().collect()),
+                                            lsp::CodeActionOrCommand::Command(_) => {
+                                                sole.iter().cloned().collect()
+                                            }
+                                        })
+                                        .collect();
+                                    let call: job::Callback =
+                                        Box::new(move |editor: &mut Editor, _| {
+                                            if let Some(doc) = editor.document_by_path_mut(&path) {
+                                                for diagnostic in fixed {
+                                                    doc.mark_code_actions(
+                                                        diagnostic.range.start.line as usize,
+                                                        &diagnostic.message,
+                                                    );
+                                                }
+                                            }
+                                        });
+                                    Ok(call)
+                                });
+                            }
                         }
                     }
                     Notification::ShowMessage(params) => {
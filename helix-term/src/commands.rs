@@ -16,8 +16,8 @@ use helix_core::{
     Transaction,
 };
 use helix_view::{
-    clipboard::ClipboardType,
-    document::{Mode, SCRATCH_BUFFER_NAME},
+    clipboard::{ClipboardError, ClipboardType, ContentsReader, LinewiseMarker},
+    document::{Breakpoint, Mark, Mode, SavedEvent, SCRATCH_BUFFER_NAME},
     editor::{Action, Motion},
     input::KeyEvent,
     keyboard::KeyCode,
@@ -41,6 +41,10 @@ use crate::{
 
 use crate::job::{self, Job, Jobs};
 use futures_util::{FutureExt, StreamExt};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
 use std::{collections::HashSet, num::NonZeroUsize};
 use std::{fmt, future::Future};
 
@@ -124,7 +128,7 @@ fn align_view(doc: &Document, view: &mut View, align: Align) {
         .cursor(doc.text().slice(..));
     let line = doc.text().char_to_line(pos);
 
-    let height = view.inner_area().height as usize;
+    let height = view.inner_height();
 
     let relative = match align {
         Align::Center => height / 2,
@@ -343,6 +347,7 @@ impl MappableCommand {
         paste_clipboard_before, "Paste clipboard before selections",
         paste_primary_clipboard_after, "Paste primary clipboard after selections",
         paste_primary_clipboard_before, "Paste primary clipboard before selections",
+        paste_clipboard_history, "Pick an entry of the clipboard manager's history to paste after selections",
         indent, "Indent selection",
         unindent, "Unindent selection",
         format_selections, "Format selection",
@@ -813,7 +818,7 @@ fn goto_window(cx: &mut Context, align: Align) {
     let count = cx.count() - 1;
     let (view, doc) = current!(cx.editor);
 
-    let height = view.inner_area().height as usize;
+    let height = view.inner_height();
 
     // respect user given count if any
     // - 1 so we have at least one gap in the middle.
@@ -1261,9 +1266,9 @@ pub fn scroll(cx: &mut Context, offset: usize, direction: Direction) {
         return;
     }
 
-    let height = view.inner_area().height;
+    let height = view.inner_height();
 
-    let scrolloff = cx.editor.config.scrolloff.min(height as usize / 2);
+    let scrolloff = cx.editor.config.scrolloff.min(height / 2);
 
     view.offset.row = match direction {
         Forward => view.offset.row + offset,
@@ -1294,25 +1299,25 @@ pub fn scroll(cx: &mut Context, offset: usize, direction: Direction) {
 
 fn page_up(cx: &mut Context) {
     let view = view!(cx.editor);
-    let offset = view.inner_area().height as usize;
+    let offset = view.inner_height();
     scroll(cx, offset, Direction::Backward);
 }
 
 fn page_down(cx: &mut Context) {
     let view = view!(cx.editor);
-    let offset = view.inner_area().height as usize;
+    let offset = view.inner_height();
     scroll(cx, offset, Direction::Forward);
 }
 
 fn half_page_up(cx: &mut Context) {
     let view = view!(cx.editor);
-    let offset = view.inner_area().height as usize / 2;
+    let offset = view.inner_height() / 2;
     scroll(cx, offset, Direction::Backward);
 }
 
 fn half_page_down(cx: &mut Context) {
     let view = view!(cx.editor);
-    let offset = view.inner_area().height as usize / 2;
+    let offset = view.inner_height() / 2;
     scroll(cx, offset, Direction::Forward);
 }
 
@@ -1400,10 +1405,11 @@ fn select_regex(cx: &mut Context) {
         "select:".into(),
         Some(reg),
         |_input: &str| Vec::new(),
-        move |view, doc, regex, event| {
+        move |editor, regex, event| {
             if event != PromptEvent::Update {
                 return;
             }
+            let (view, doc) = current!(editor);
             let text = doc.text().slice(..);
             if let Some(selection) =
                 selection::select_on_matches(text, doc.selection(view.id), &regex)
@@ -1423,10 +1429,11 @@ fn split_selection(cx: &mut Context) {
         "split:".into(),
         Some(reg),
         |_input: &str| Vec::new(),
-        move |view, doc, regex, event| {
+        move |editor, regex, event| {
             if event != PromptEvent::Update {
                 return;
             }
+            let (view, doc) = current!(editor);
             let text = doc.text().slice(..);
             let selection = selection::split_on_matches(text, doc.selection(view.id), &regex);
             doc.set_selection(view.id, selection);
@@ -1448,14 +1455,14 @@ fn split_selection_on_newline(cx: &mut Context) {
 }
 
 fn search_impl(
-    doc: &mut Document,
-    view: &mut View,
+    editor: &mut Editor,
     contents: &str,
     regex: &Regex,
     movement: Movement,
     direction: Direction,
-    scrolloff: usize,
 ) {
+    let scrolloff = editor.config.scrolloff;
+    let (view, doc) = current!(editor);
     let text = doc.text().slice(..);
     let selection = doc.selection(view.id);
 
@@ -1513,8 +1520,8 @@ fn search_impl(
         };
 
         doc.set_selection(view.id, selection);
-        if view.is_cursor_in_view(doc, 0) {
-            view.ensure_cursor_in_view(doc, scrolloff);
+        if view.is_cursor_in_view(doc, &editor.theme, 0) {
+            view.ensure_cursor_in_view(doc, &editor.theme, scrolloff);
         } else {
             align_view(doc, view, Align::Center)
         }
@@ -1541,7 +1548,6 @@ fn rsearch(cx: &mut Context) {
 // TODO: use one function for search vs extend
 fn searcher(cx: &mut Context, direction: Direction) {
     let reg = cx.register.unwrap_or('/');
-    let scrolloff = cx.editor.config.scrolloff;
 
     let (_, doc) = current!(cx.editor);
 
@@ -1563,19 +1569,34 @@ fn searcher(cx: &mut Context, direction: Direction) {
                 .map(|comp| (0.., std::borrow::Cow::Owned(comp.clone())))
                 .collect()
         },
-        move |view, doc, regex, event| {
+        move |editor, regex, event| {
             if event != PromptEvent::Update {
                 return;
             }
-            search_impl(
-                doc,
-                view,
-                &contents,
-                &regex,
-                Movement::Move,
-                direction,
-                scrolloff,
-            );
+            search_impl(editor, &contents, &regex, Movement::Move, direction);
+            let (view, doc) = current!(editor);
+            // for the search gutter, only the lines in view are searched
+            let text = doc.text().slice(..);
+            let first_line = view.offset.row.min(text.len_lines() - 1);
+            let end = text.line_to_byte((view.last_line(doc) + 1).min(text.len_lines()));
+            let mut start = text.line_to_byte(first_line);
+            let mut lines = Vec::new();
+            while let Some(mat) = regex.find_at(&contents, start) {
+                if mat.start() >= end {
+                    break;
+                }
+                let line = text.byte_to_line(mat.start());
+                if lines.last() != Some(&line) {
+                    lines.push(line);
+                }
+                // step over empty matches so the search advances
+                start = match contents[mat.end()..].chars().next() {
+                    Some(ch) if mat.start() == mat.end() => mat.end() + ch.len_utf8(),
+                    Some(_) => mat.end(),
+                    None => break,
+                };
+            }
+            doc.set_search_matches(Some(lines));
         },
     );
 
@@ -1583,27 +1604,21 @@ fn searcher(cx: &mut Context, direction: Direction) {
 }
 
 fn search_next_or_prev_impl(cx: &mut Context, movement: Movement, direction: Direction) {
-    let scrolloff = cx.editor.config.scrolloff;
-    let (view, doc) = current!(cx.editor);
     let registers = &cx.editor.registers;
     if let Some(query) = registers.read('/') {
-        let query = query.last().unwrap();
-        let contents = doc.text().slice(..).to_string();
+        let query = query.last().unwrap().clone();
+        let contents = doc!(cx.editor).text().slice(..).to_string();
         let case_insensitive = if cx.editor.config.smart_case {
             !query.chars().any(char::is_uppercase)
         } else {
             false
         };
-        if let Ok(regex) = RegexBuilder::new(query)
+        if let Ok(regex) = RegexBuilder::new(&query)
             .case_insensitive(case_insensitive)
             .build()
         {
-            search_impl(doc, view, &contents, &regex, movement, direction, scrolloff);
+            search_impl(cx.editor, &contents, &regex, movement, direction);
         } else {
-            // get around warning `mutable_borrow_reservation_conflict`
-            // which will be a hard error in the future
-            // see: https://github.com/rust-lang/rust/issues/59159
-            let query = query.clone();
             cx.editor.set_error(format!("Invalid regex: {}", query));
         }
     }
@@ -1652,7 +1667,7 @@ fn global_search(cx: &mut Context) {
                 .map(|comp| (0.., std::borrow::Cow::Owned(comp.clone())))
                 .collect()
         },
-        move |_view, _doc, regex, event| {
+        move |_editor, regex, event| {
             if event != PromptEvent::Validate {
                 return;
             }
@@ -1750,11 +1765,11 @@ fn global_search(cx: &mut Context) {
                             relative_path.into()
                         }
                     },
-                    move |editor: &mut Editor, (line_num, path), action| {
-                        match editor.open(path.into(), action) {
+                    move |cx, (line_num, path), action| {
+                        match cx.editor.open(path.into(), action) {
                             Ok(_) => {}
                             Err(e) => {
-                                editor.set_error(format!(
+                                cx.editor.set_error(format!(
                                     "Failed to open file '{}': {}",
                                     path.display(),
                                     e
@@ -1764,7 +1779,7 @@ fn global_search(cx: &mut Context) {
                         }
 
                         let line_num = *line_num;
-                        let (view, doc) = current!(editor);
+                        let (view, doc) = current!(cx.editor);
                         let text = doc.text();
                         let start = text.line_to_char(line_num);
                         let end = text.line_to_char((line_num + 1).min(text.len_lines()));
@@ -2051,7 +2066,8 @@ pub mod cmd {
             shared
         });
         let future = doc.format_and_save(fmt);
-        cx.jobs.add(Job::new(future).wait_before_exiting());
+        cx.jobs
+            .add(Job::with_callback(make_save_callback(future)).wait_before_exiting());
 
         if path.is_some() {
             let id = doc.id();
@@ -2265,7 +2281,8 @@ pub mod cmd {
 
             // TODO: handle error.
             let handle = doc.save();
-            cx.jobs.add(Job::new(handle).wait_before_exiting());
+            cx.jobs
+                .add(Job::with_callback(make_save_callback(handle)).wait_before_exiting());
         }
 
         if quit {
@@ -2385,7 +2402,23 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        yank_main_selection_to_clipboard_impl(cx.editor, ClipboardType::Clipboard)
+        yank_main_selection_to_clipboard_impl(cx.editor, None, ClipboardType::Clipboard)
+    }
+
+    fn clear_clipboard(
+        cx: &mut compositor::Context,
+        _args: &[Cow<str>],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        clear_clipboard_impl(cx.editor, ClipboardType::Clipboard)
+    }
+
+    fn clear_primary_clipboard(
+        cx: &mut compositor::Context,
+        _args: &[Cow<str>],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        clear_clipboard_impl(cx.editor, ClipboardType::Selection)
     }
 
     fn yank_joined_to_clipboard(
@@ -2396,7 +2429,7 @@ pub mod cmd {
         let (_, doc) = current!(cx.editor);
         let default_sep = Cow::Borrowed(doc.line_ending.as_str());
         let separator = args.first().unwrap_or(&default_sep);
-        yank_joined_to_clipboard_impl(cx.editor, separator, ClipboardType::Clipboard)
+        yank_joined_to_clipboard_impl(cx.editor, None, separator, ClipboardType::Clipboard)
     }
 
     fn yank_main_selection_to_primary_clipboard(
@@ -2404,7 +2437,7 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        yank_main_selection_to_clipboard_impl(cx.editor, ClipboardType::Selection)
+        yank_main_selection_to_clipboard_impl(cx.editor, None, ClipboardType::Selection)
     }
 
     fn yank_joined_to_primary_clipboard(
@@ -2415,7 +2448,7 @@ pub mod cmd {
         let (_, doc) = current!(cx.editor);
         let default_sep = Cow::Borrowed(doc.line_ending.as_str());
         let separator = args.first().unwrap_or(&default_sep);
-        yank_joined_to_clipboard_impl(cx.editor, separator, ClipboardType::Selection)
+        yank_joined_to_clipboard_impl(cx.editor, None, separator, ClipboardType::Selection)
     }
 
     fn paste_clipboard_after(
@@ -2423,7 +2456,13 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        paste_clipboard_impl(cx.editor, Paste::After, ClipboardType::Clipboard, 1)
+        paste_clipboard_impl(
+            cx.editor,
+            cx.jobs,
+            Paste::After,
+            ClipboardType::Clipboard,
+            1,
+        )
     }
 
     fn paste_clipboard_before(
@@ -2431,7 +2470,13 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        paste_clipboard_impl(cx.editor, Paste::After, ClipboardType::Clipboard, 1)
+        paste_clipboard_impl(
+            cx.editor,
+            cx.jobs,
+            Paste::After,
+            ClipboardType::Clipboard,
+            1,
+        )
     }
 
     fn paste_primary_clipboard_after(
@@ -2439,7 +2484,13 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        paste_clipboard_impl(cx.editor, Paste::After, ClipboardType::Selection, 1)
+        paste_clipboard_impl(
+            cx.editor,
+            cx.jobs,
+            Paste::After,
+            ClipboardType::Selection,
+            1,
+        )
     }
 
     fn paste_primary_clipboard_before(
@@ -2447,29 +2498,13 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        paste_clipboard_impl(cx.editor, Paste::After, ClipboardType::Selection, 1)
-    }
-
-    fn replace_selections_with_clipboard_impl(
-        cx: &mut compositor::Context,
-        clipboard_type: ClipboardType,
-    ) -> anyhow::Result<()> {
-        let (view, doc) = current!(cx.editor);
-
-        match cx.editor.clipboard_provider.get_contents(clipboard_type) {
-            Ok(contents) => {
-                let selection = doc.selection(view.id);
-                let transaction =
-                    Transaction::change_by_selection(doc.text(), selection, |range| {
-                        (range.from(), range.to(), Some(contents.as_str().into()))
-                    });
-
-                doc.apply(&transaction, view.id);
-                doc.append_changes_to_history(view.id);
-                Ok(())
-            }
-            Err(e) => Err(e.context("Couldn't get system clipboard contents")),
-        }
+        paste_clipboard_impl(
+            cx.editor,
+            cx.jobs,
+            Paste::After,
+            ClipboardType::Selection,
+            1,
+        )
     }
 
     fn replace_selections_with_clipboard(
@@ -2477,7 +2512,7 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        replace_selections_with_clipboard_impl(cx, ClipboardType::Clipboard)
+        replace_selections_with_clipboard_impl(cx.editor, cx.jobs, ClipboardType::Clipboard, 1)
     }
 
     fn replace_selections_with_primary_clipboard(
@@ -2485,7 +2520,7 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        replace_selections_with_clipboard_impl(cx, ClipboardType::Selection)
+        replace_selections_with_clipboard_impl(cx.editor, cx.jobs, ClipboardType::Selection, 1)
     }
 
     fn show_clipboard_provider(
@@ -2493,8 +2528,16 @@ pub mod cmd {
         _args: &[Cow<str>],
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
-        cx.editor
-            .set_status(cx.editor.clipboard_provider.name().to_string());
+        let name = cx.editor.clipboard_provider_name();
+        let primary = if cx.editor.clipboard_supports_primary() {
+            "supported"
+        } else {
+            "unsupported"
+        };
+        cx.editor.set_status(format!(
+            "clipboard: {}, primary selection: {}",
+            name, primary
+        ));
         Ok(())
     }
 
@@ -2556,7 +2599,10 @@ pub mod cmd {
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
         let (view, doc) = current!(cx.editor);
-        doc.reload(view.id)
+        doc.reload(view.id)?;
+        let doc_id = doc.id();
+        cx.editor.refresh_blame(doc_id);
+        Ok(())
     }
 
     fn tree_sitter_scopes(
@@ -2623,6 +2669,72 @@ pub mod cmd {
         Ok(())
     }
 
+    fn mark(
+        cx: &mut compositor::Context,
+        args: &[Cow<str>],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let mut chars = args.first().map(|arg| arg.chars()).into_iter().flatten();
+        let name = match (chars.next(), chars.next()) {
+            (Some(name), None) if name.width() == Some(1) => name,
+            _ => bail!("Mark name has to be a single character"),
+        };
+
+        let (view, doc) = current!(cx.editor);
+        let line = doc
+            .selection(view.id)
+            .primary()
+            .cursor_line(doc.text().slice(..));
+        let mut marks = doc.marks().to_vec();
+        marks.retain(|mark| mark.name != name && mark.line != line);
+        marks.push(Mark { line, name });
+        doc.set_marks(marks);
+        Ok(())
+    }
+
+    fn unmark(
+        cx: &mut compositor::Context,
+        _args: &[Cow<str>],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let (view, doc) = current!(cx.editor);
+        let line = doc
+            .selection(view.id)
+            .primary()
+            .cursor_line(doc.text().slice(..));
+        let mut marks = doc.marks().to_vec();
+        marks.retain(|mark| mark.line != line);
+        doc.set_marks(marks);
+        Ok(())
+    }
+
+    fn breakpoint(
+        cx: &mut compositor::Context,
+        args: &[Cow<str>],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let condition = if args.is_empty() {
+            None
+        } else {
+            Some(args.join(" "))
+        };
+
+        let (view, doc) = current!(cx.editor);
+        let line = doc
+            .selection(view.id)
+            .primary()
+            .cursor_line(doc.text().slice(..));
+        let mut breakpoints = doc.breakpoints().to_vec();
+        let len = breakpoints.len();
+        breakpoints.retain(|breakpoint| breakpoint.line != line);
+        // without a condition, an existing breakpoint is removed instead of replaced
+        if breakpoints.len() == len || condition.is_some() {
+            breakpoints.push(Breakpoint { line, condition });
+        }
+        doc.set_breakpoints(breakpoints);
+        Ok(())
+    }
+
     pub(super) fn goto_line_number(
         cx: &mut compositor::Context,
         args: &[Cow<str>],
@@ -2636,7 +2748,7 @@ pub mod cmd {
 
         let (view, doc) = current!(cx.editor);
 
-        view.ensure_cursor_in_view(doc, line);
+        view.ensure_cursor_in_view(doc, &cx.editor.theme, line);
 
         Ok(())
     }
@@ -2817,6 +2929,20 @@ pub mod cmd {
             fun: yank_joined_to_primary_clipboard,
             completer: None,
         },
+        TypableCommand {
+            name: "clipboard-clear",
+            aliases: &[],
+            doc: "Clear system clipboard.",
+            fun: clear_clipboard,
+            completer: None,
+        },
+        TypableCommand {
+            name: "primary-clipboard-clear",
+            aliases: &[],
+            doc: "Clear system primary clipboard.",
+            fun: clear_primary_clipboard,
+            completer: None,
+        },
         TypableCommand {
             name: "clipboard-paste-after",
             aliases: &[],
@@ -2862,7 +2988,7 @@ pub mod cmd {
         TypableCommand {
             name: "show-clipboard-provider",
             aliases: &[],
-            doc: "Show clipboard provider name in status bar.",
+            doc: "Show clipboard provider name and primary selection support in status bar.",
             fun: show_clipboard_provider,
             completer: None,
         },
@@ -2928,6 +3054,27 @@ pub mod cmd {
             doc: "Go to line number.",
             fun: goto_line_number,
             completer: None,
+        },
+        TypableCommand {
+            name: "mark",
+            aliases: &[],
+            doc: "Set a mark with the given single character name on the current line.",
+            fun: mark,
+            completer: None,
+        },
+        TypableCommand {
+            name: "unmark",
+            aliases: &[],
+            doc: "Remove the mark from the current line.",
+            fun: unmark,
+            completer: None,
+        },
+        TypableCommand {
+            name: "breakpoint",
+            aliases: &[],
+            doc: "Toggle a breakpoint on the current line, or set one with the given condition.",
+            fun: breakpoint,
+            completer: None,
         }
     ];
 
@@ -3083,8 +3230,8 @@ fn buffer_picker(cx: &mut Context) {
             .map(|(_, doc)| new_meta(doc))
             .collect(),
         BufferMeta::format,
-        |editor: &mut Editor, meta, _action| {
-            editor.switch(meta.id, Action::Replace);
+        |cx, meta, _action| {
+            cx.editor.switch(meta.id, Action::Replace);
         },
         |editor, meta| {
             let doc = &editor.documents.get(&meta.id)?;
@@ -3151,9 +3298,9 @@ fn symbol_picker(cx: &mut Context) {
                 let mut picker = FilePicker::new(
                     symbols,
                     |symbol| (&symbol.name).into(),
-                    move |editor: &mut Editor, symbol, _action| {
-                        push_jump(editor);
-                        let (view, doc) = current!(editor);
+                    move |cx, symbol, _action| {
+                        push_jump(cx.editor);
+                        let (view, doc) = current!(cx.editor);
 
                         if let Some(range) =
                             lsp_range_to_range(doc.text(), symbol.location.range, offset_encoding)
@@ -3212,10 +3359,10 @@ fn workspace_symbol_picker(cx: &mut Context) {
                             format!("{} ({})", &symbol.name, relative_path).into()
                         }
                     },
-                    move |editor: &mut Editor, symbol, action| {
+                    move |cx, symbol, action| {
                         let path = symbol.location.uri.to_file_path().unwrap();
-                        editor.open(path, action).expect("editor.open failed");
-                        let (view, doc) = current!(editor);
+                        cx.editor.open(path, action).expect("editor.open failed");
+                        let (view, doc) = current!(cx.editor);
 
                         if let Some(range) =
                             lsp_range_to_range(doc.text(), symbol.location.range, offset_encoding)
@@ -3256,7 +3403,8 @@ pub fn code_action(cx: &mut Context) {
         language_server.offset_encoding(),
     );
 
-    let future = language_server.code_actions(doc.identifier(), range);
+    let future =
+        language_server.code_actions(doc.identifier(), range, lsp::CodeActionContext::default());
     let offset_encoding = language_server.offset_encoding();
 
     cx.callback(
@@ -3274,15 +3422,15 @@ pub fn code_action(cx: &mut Context) {
                         }
                         lsp::CodeActionOrCommand::Command(command) => command.title.as_str().into(),
                     },
-                    move |editor, code_action, _action| match code_action {
+                    move |cx, code_action, _action| match code_action {
                         lsp::CodeActionOrCommand::Command(command) => {
                             log::debug!("code action command: {:?}", command);
-                            editor.set_error(String::from("Handling code action command is not implemented yet, see https://github.com/helix-editor/helix/issues/183"));
+                            cx.editor.set_error(String::from("Handling code action command is not implemented yet, see https://github.com/helix-editor/helix/issues/183"));
                         }
                         lsp::CodeActionOrCommand::CodeAction(code_action) => {
                             log::debug!("code action: {:?}", code_action);
                             if let Some(ref workspace_edit) = code_action.edit {
-                                apply_workspace_edit(editor, offset_encoding, workspace_edit)
+                                apply_workspace_edit(cx.editor, offset_encoding, workspace_edit)
                             }
                         }
                     },
@@ -3519,6 +3667,19 @@ async fn make_format_callback(
     Ok(call)
 }
 
+async fn make_save_callback(
+    save: impl Future<Output = anyhow::Result<SavedEvent>>,
+) -> anyhow::Result<job::Callback> {
+    let saved = save.await?;
+    let call: job::Callback = Box::new(move |editor: &mut Editor, _compositor: &mut Compositor| {
+        if let Some(doc) = editor.document_mut(saved.doc_id) {
+            doc.set_saved(saved);
+        }
+        editor.refresh_blame(saved.doc_id);
+    });
+    Ok(call)
+}
+
 enum Open {
     Below,
     Above,
@@ -3807,9 +3968,7 @@ fn goto_impl(
                     let line = location.range.start.line;
                     format!("{}:{}", file, line).into()
                 },
-                move |editor: &mut Editor, location, action| {
-                    jump_to(editor, location, offset_encoding, action)
-                },
+                move |cx, location, action| jump_to(cx.editor, location, offset_encoding, action),
                 |_editor, location| {
                     let path = location.uri.to_file_path().unwrap();
                     let line = Some((
@@ -4502,8 +4661,142 @@ fn yank(cx: &mut Context) {
     exit_select_mode(cx);
 }
 
+/// The marker and the clipboard types that contents written to `clipboard_type` end up in, to
+/// remember whether they were yanked linewise, see [`LinewiseMarker`].
+type LinewiseMarks = Option<(LinewiseMarker, Vec<ClipboardType>)>;
+
+fn linewise_marks(editor: &Editor, clipboard_type: ClipboardType) -> LinewiseMarks {
+    let marker = LinewiseMarker::from_config(&editor.config.clipboard)?;
+    let mut clipboard_types = vec![clipboard_type];
+    if clipboard_type == ClipboardType::Clipboard
+        && editor.config.clipboard.yank_to_primary
+        && editor.clipboard_provider.supports_primary()
+    {
+        clipboard_types.push(ClipboardType::Selection);
+    }
+    Some((marker, clipboard_types))
+}
+
+/// Remembers whether `contents` were yanked linewise, once they were written to the clipboard.
+/// Failing to do so only makes pasting fall back to guessing, so errors are just logged.
+fn mark_linewise(marks: LinewiseMarks, contents: &str) {
+    let (marker, clipboard_types) = match marks {
+        Some(marks) => marks,
+        None => return,
+    };
+    for clipboard_type in clipboard_types {
+        if let Err(err) = marker.set(contents, clipboard_type) {
+            log::debug!("Couldn't mark the clipboard contents as linewise: {}", err);
+        }
+    }
+}
+
+/// Writes `contents` to the clipboard, and to the primary selection too if the clipboard is
+/// written and `yank-to-primary` is set.
+fn set_clipboard_contents(
+    editor: &mut Editor,
+    contents: String,
+    clipboard_type: ClipboardType,
+) -> anyhow::Result<()> {
+    if clipboard_type == ClipboardType::Clipboard && editor.config.clipboard.yank_to_primary {
+        editor.clipboard_provider.set_contents_all(contents)
+    } else {
+        editor
+            .clipboard_provider
+            .set_contents(contents, clipboard_type)
+    }
+}
+
+/// Background clipboard writes run one at a time, see [`write_clipboard`].
+static CLIPBOARD_WRITE: Mutex<()> = Mutex::new(());
+/// Number of background clipboard writes issued, to skip the ones a later yank superseded.
+static CLIPBOARD_WRITE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `contents` to the clipboard like [`set_clipboard_contents`] and shows `status` once
+/// they're written. If `jobs` are given and the provider can be written on another thread, the
+/// write happens in the background, so a slow copy command doesn't block the editor, and errors
+/// are shown in the status line once it's done. Background writes happen in the order they were
+/// issued, and are skipped if a later write is pending, so the clipboard ends up with the last
+/// yank. Without `jobs` this waits for the write, for callers that need to know whether it
+/// worked.
+///
+/// Failing to write the primary selection along with the clipboard is only logged.
+fn write_clipboard(
+    editor: &mut Editor,
+    jobs: Option<&mut Jobs>,
+    contents: String,
+    clipboard_type: ClipboardType,
+    status: String,
+) -> anyhow::Result<()> {
+    let marks = linewise_marks(editor, clipboard_type);
+    let (jobs, writer) = match jobs.and_then(|jobs| {
+        let writer = editor.clipboard_provider.contents_writer(clipboard_type)?;
+        Some((jobs, writer))
+    }) {
+        Some(writer) => writer,
+        None => {
+            set_clipboard_contents(editor, contents.clone(), clipboard_type)?;
+            mark_linewise(marks, &contents);
+            editor.set_status(status);
+            return Ok(());
+        }
+    };
+
+    let provider = &mut editor.clipboard_provider;
+    let primary_writer = if clipboard_type == ClipboardType::Clipboard
+        && editor.config.clipboard.yank_to_primary
+        && provider.supports_primary()
+    {
+        match provider.contents_writer(ClipboardType::Selection) {
+            Some(writer) => Some(writer),
+            None => {
+                if let Err(err) = provider.set_contents(contents.clone(), ClipboardType::Selection)
+                {
+                    log::warn!("Couldn't set the primary selection: {}", err);
+                }
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let generation = CLIPBOARD_WRITE_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    jobs.callback(async move {
+        let result = tokio::task::spawn_blocking(move || {
+            let _write = CLIPBOARD_WRITE
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            if CLIPBOARD_WRITE_GENERATION.load(Ordering::SeqCst) != generation {
+                return anyhow::Ok(false);
+            }
+            writer(contents.clone())?;
+            if let Some(primary_writer) = primary_writer {
+                if let Err(err) = primary_writer(contents.clone()) {
+                    log::warn!("Couldn't set the primary selection: {}", err);
+                }
+            }
+            mark_linewise(marks, &contents);
+            Ok(true)
+        })
+        .await?;
+        let call: job::Callback = Box::new(move |editor: &mut Editor, _| match result {
+            Ok(true) => editor.set_status(status),
+            // superseded by a later write, which shows its own status
+            Ok(false) => {}
+            Err(e) => editor.set_error(format!(
+                "{}",
+                e.context("Couldn't set system clipboard content")
+            )),
+        });
+        Ok(call)
+    });
+    Ok(())
+}
+
 fn yank_joined_to_clipboard_impl(
     editor: &mut Editor,
+    jobs: Option<&mut Jobs>,
     separator: &str,
     clipboard_type: ClipboardType,
 ) -> anyhow::Result<()> {
@@ -4523,55 +4816,81 @@ fn yank_joined_to_clipboard_impl(
 
     let joined = values.join(separator);
 
-    editor
-        .clipboard_provider
-        .set_contents(joined, clipboard_type)
+    write_clipboard(editor, jobs, joined, clipboard_type, msg)
         .context("Couldn't set system clipboard content")?;
 
-    editor.set_status(msg);
-
     Ok(())
 }
 
 fn yank_joined_to_clipboard(cx: &mut Context) {
     let line_ending = doc!(cx.editor).line_ending;
-    let _ =
-        yank_joined_to_clipboard_impl(cx.editor, line_ending.as_str(), ClipboardType::Clipboard);
+    let _ = yank_joined_to_clipboard_impl(
+        cx.editor,
+        Some(cx.jobs),
+        line_ending.as_str(),
+        ClipboardType::Clipboard,
+    );
     exit_select_mode(cx);
 }
 
 fn yank_main_selection_to_clipboard_impl(
     editor: &mut Editor,
+    jobs: Option<&mut Jobs>,
     clipboard_type: ClipboardType,
 ) -> anyhow::Result<()> {
     let (view, doc) = current!(editor);
     let text = doc.text().slice(..);
 
-    let value = doc.selection(view.id).primary().fragment(text);
+    let value = doc.selection(view.id).primary().fragment(text).into_owned();
 
-    if let Err(e) = editor
-        .clipboard_provider
-        .set_contents(value.into_owned(), clipboard_type)
-    {
+    let msg = "yanked main selection to system clipboard".to_owned();
+    if let Err(e) = write_clipboard(editor, jobs, value, clipboard_type, msg) {
         bail!("Couldn't set system clipboard content: {}", e);
     }
+    Ok(())
+}
+
+/// Adds context to errors from reading the clipboard, except for errors whose message already
+/// says what went wrong, like non-text contents or a provider that timed out.
+fn clipboard_read_error(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref() {
+        Some(
+            ClipboardError::NotFound(_)
+            | ClipboardError::Timeout(_)
+            | ClipboardError::NonText { .. }
+            | ClipboardError::ProcessFailed { .. },
+        ) => e,
+        _ => e.context("Couldn't get system clipboard contents"),
+    }
+}
+
+fn clear_clipboard_impl(editor: &mut Editor, clipboard_type: ClipboardType) -> anyhow::Result<()> {
+    if let Err(e) = editor.clipboard_provider.clear(clipboard_type) {
+        bail!("Couldn't clear system clipboard: {}", e);
+    }
 
-    editor.set_status("yanked main selection to system clipboard".to_owned());
+    editor.set_status("cleared system clipboard".to_owned());
     Ok(())
 }
 
 fn yank_main_selection_to_clipboard(cx: &mut Context) {
-    let _ = yank_main_selection_to_clipboard_impl(cx.editor, ClipboardType::Clipboard);
+    let _ =
+        yank_main_selection_to_clipboard_impl(cx.editor, Some(cx.jobs), ClipboardType::Clipboard);
 }
 
 fn yank_joined_to_primary_clipboard(cx: &mut Context) {
     let line_ending = doc!(cx.editor).line_ending;
-    let _ =
-        yank_joined_to_clipboard_impl(cx.editor, line_ending.as_str(), ClipboardType::Selection);
+    let _ = yank_joined_to_clipboard_impl(
+        cx.editor,
+        Some(cx.jobs),
+        line_ending.as_str(),
+        ClipboardType::Selection,
+    );
 }
 
 fn yank_main_selection_to_primary_clipboard(cx: &mut Context) {
-    let _ = yank_main_selection_to_clipboard_impl(cx.editor, ClipboardType::Selection);
+    let _ =
+        yank_main_selection_to_clipboard_impl(cx.editor, Some(cx.jobs), ClipboardType::Selection);
     exit_select_mode(cx);
 }
 
@@ -4632,32 +4951,97 @@ fn paste_impl(
     Some(transaction)
 }
 
+/// Reads the clipboard and passes its contents to `f` along with the current view and document.
+/// Providers that can be read on another thread are read in the background with
+/// [`read_in_background`], others right away.
+fn read_clipboard<F>(
+    editor: &mut Editor,
+    jobs: &mut Jobs,
+    clipboard_type: ClipboardType,
+    f: F,
+) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut View, &mut Document, String) + Send + 'static,
+{
+    let reader = match editor.clipboard_provider.contents_reader(clipboard_type) {
+        Some(reader) => reader,
+        None => {
+            let contents = editor
+                .clipboard_provider
+                .get_contents(clipboard_type)
+                .map_err(clipboard_read_error)?;
+            let (view, doc) = current!(editor);
+            f(view, doc, contents);
+            return Ok(());
+        }
+    };
+    read_in_background(editor, jobs, reader, f);
+    Ok(())
+}
+
+/// Runs `reader` on another thread, so a slow clipboard doesn't block the editor, and passes
+/// its result to `f` once it arrives, with the view and document that were current when
+/// reading started. If either was closed in the meantime the contents are dropped. Errors are
+/// shown in the status line.
+fn read_in_background<F>(editor: &mut Editor, jobs: &mut Jobs, reader: ContentsReader, f: F)
+where
+    F: FnOnce(&mut View, &mut Document, String) + Send + 'static,
+{
+    const PASTING: &str = "pasting...";
+
+    let (view, doc) = current_ref!(editor);
+    let (view_id, doc_id) = (view.id, doc.id());
+    editor.set_status(PASTING.to_owned());
+    jobs.callback(async move {
+        let contents = tokio::task::spawn_blocking(reader).await?;
+        let call: job::Callback = Box::new(move |editor: &mut Editor, _| {
+            if matches!(&editor.status_msg, Some((msg, _)) if msg == PASTING) {
+                editor.clear_status();
+            }
+            let contents = match contents {
+                Ok(contents) => contents,
+                Err(e) => return editor.set_error(format!("{}", clipboard_read_error(e))),
+            };
+            let view = editor
+                .tree
+                .views_mut()
+                .map(|(view, _)| view)
+                .find(|view| view.id == view_id && view.doc == doc_id);
+            match (view, editor.documents.get_mut(&doc_id)) {
+                (Some(view), Some(doc)) => f(view, doc, contents),
+                _ => log::info!("discarded the clipboard contents because the view was closed"),
+            }
+        });
+        Ok(call)
+    });
+}
+
 fn paste_clipboard_impl(
     editor: &mut Editor,
+    jobs: &mut Jobs,
     action: Paste,
     clipboard_type: ClipboardType,
     count: usize,
 ) -> anyhow::Result<()> {
-    let (view, doc) = current!(editor);
-
-    match editor
-        .clipboard_provider
-        .get_contents(clipboard_type)
-        .map(|contents| paste_impl(&[contents], doc, view, action, count))
-    {
-        Ok(Some(transaction)) => {
+    let marker = LinewiseMarker::from_config(&editor.config.clipboard);
+    read_clipboard(editor, jobs, clipboard_type, move |view, doc, contents| {
+        // contents yanked linewise by Helix paste linewise, even if the clipboard tools
+        // stripped their trailing line ending
+        let contents = match marker {
+            Some(marker) => marker.restore(contents, clipboard_type, doc.line_ending.as_str()),
+            None => contents,
+        };
+        if let Some(transaction) = paste_impl(&[contents], doc, view, action, count) {
             doc.apply(&transaction, view.id);
             doc.append_changes_to_history(view.id);
-            Ok(())
         }
-        Ok(None) => Ok(()),
-        Err(e) => Err(e.context("Couldn't get system clipboard contents")),
-    }
+    })
 }
 
 fn paste_clipboard_after(cx: &mut Context) {
     let _ = paste_clipboard_impl(
         cx.editor,
+        cx.jobs,
         Paste::After,
         ClipboardType::Clipboard,
         cx.count(),
@@ -4667,6 +5051,7 @@ fn paste_clipboard_after(cx: &mut Context) {
 fn paste_clipboard_before(cx: &mut Context) {
     let _ = paste_clipboard_impl(
         cx.editor,
+        cx.jobs,
         Paste::Before,
         ClipboardType::Clipboard,
         cx.count(),
@@ -4676,6 +5061,7 @@ fn paste_clipboard_before(cx: &mut Context) {
 fn paste_primary_clipboard_after(cx: &mut Context) {
     let _ = paste_clipboard_impl(
         cx.editor,
+        cx.jobs,
         Paste::After,
         ClipboardType::Selection,
         cx.count(),
@@ -4685,12 +5071,72 @@ fn paste_primary_clipboard_after(cx: &mut Context) {
 fn paste_primary_clipboard_before(cx: &mut Context) {
     let _ = paste_clipboard_impl(
         cx.editor,
+        cx.jobs,
         Paste::Before,
         ClipboardType::Selection,
         cx.count(),
     );
 }
 
+fn paste_clipboard_history(cx: &mut Context) {
+    let count = cx.count();
+    let reader = match cx.editor.clipboard_provider.list_history() {
+        Some(reader) => reader,
+        None => {
+            cx.editor.set_error(
+                "The clipboard provider has no history, set `history-list` in [editor.clipboard]"
+                    .to_string(),
+            );
+            return;
+        }
+    };
+
+    cx.jobs.callback(async move {
+        let entries = tokio::task::spawn_blocking(reader).await?;
+        let call: job::Callback =
+            Box::new(move |editor: &mut Editor, compositor: &mut Compositor| {
+                let entries = match entries {
+                    Ok(entries) if entries.is_empty() => {
+                        editor.set_status("The clipboard history is empty".to_string());
+                        return;
+                    }
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        editor.set_error(format!(
+                            "{}",
+                            e.context("Couldn't list the clipboard history")
+                        ));
+                        return;
+                    }
+                };
+                let picker = Picker::new(
+                    true,
+                    entries,
+                    |entry| entry.as_str().into(),
+                    move |cx, entry, _action| {
+                        // decoding may run a command, which shouldn't block the editor
+                        let reader = cx.editor.clipboard_provider.history_entry_reader(entry);
+                        read_in_background(
+                            cx.editor,
+                            cx.jobs,
+                            reader,
+                            move |view, doc, contents| {
+                                if let Some(transaction) =
+                                    paste_impl(&[contents], doc, view, Paste::After, count)
+                                {
+                                    doc.apply(&transaction, view.id);
+                                    doc.append_changes_to_history(view.id);
+                                }
+                            },
+                        );
+                    },
+                );
+                compositor.push(Box::new(picker));
+            });
+        Ok(call)
+    });
+}
+
 fn replace_with_yanked(cx: &mut Context) {
     let count = cx.count();
     let reg_name = cx.register.unwrap_or('"');
@@ -4726,36 +5172,35 @@ fn replace_with_yanked(cx: &mut Context) {
 
 fn replace_selections_with_clipboard_impl(
     editor: &mut Editor,
+    jobs: &mut Jobs,
     clipboard_type: ClipboardType,
     count: usize,
 ) -> anyhow::Result<()> {
-    let (view, doc) = current!(editor);
-
-    match editor.clipboard_provider.get_contents(clipboard_type) {
-        Ok(contents) => {
-            let selection = doc.selection(view.id);
-            let transaction = Transaction::change_by_selection(doc.text(), selection, |range| {
-                (
-                    range.from(),
-                    range.to(),
-                    Some(contents.repeat(count).as_str().into()),
-                )
-            });
+    read_clipboard(editor, jobs, clipboard_type, move |view, doc, contents| {
+        let selection = doc.selection(view.id);
+        let transaction = Transaction::change_by_selection(doc.text(), selection, |range| {
+            (
+                range.from(),
+                range.to(),
+                Some(contents.repeat(count).as_str().into()),
+            )
+        });
 
-            doc.apply(&transaction, view.id);
-            doc.append_changes_to_history(view.id);
-            Ok(())
-        }
-        Err(e) => Err(e.context("Couldn't get system clipboard contents")),
-    }
+        doc.apply(&transaction, view.id);
+        doc.append_changes_to_history(view.id);
+    })
 }
 
 fn replace_selections_with_clipboard(cx: &mut Context) {
-    let _ = replace_selections_with_clipboard_impl(cx.editor, ClipboardType::Clipboard, cx.count());
+    let count = cx.count();
+    let _ =
+        replace_selections_with_clipboard_impl(cx.editor, cx.jobs, ClipboardType::Clipboard, count);
 }
 
 fn replace_selections_with_primary_clipboard(cx: &mut Context) {
-    let _ = replace_selections_with_clipboard_impl(cx.editor, ClipboardType::Selection, cx.count());
+    let count = cx.count();
+    let _ =
+        replace_selections_with_clipboard_impl(cx.editor, cx.jobs, ClipboardType::Selection, count);
 }
 
 fn paste_after(cx: &mut Context) {
@@ -4962,10 +5407,11 @@ fn keep_or_remove_selections_impl(cx: &mut Context, remove: bool) {
         if !remove { "keep:" } else { "remove:" }.into(),
         Some(reg),
         |_input: &str| Vec::new(),
-        move |view, doc, regex, event| {
+        move |editor, regex, event| {
             if event != PromptEvent::Update {
                 return;
             }
+            let (view, doc) = current!(editor);
             let text = doc.text().slice(..);
 
             if let Some(selection) =
@@ -5447,7 +5893,7 @@ fn align_view_middle(cx: &mut Context) {
 
     view.offset.col = pos
         .col
-        .saturating_sub((view.inner_area().width as usize) / 2);
+        .saturating_sub((view.inner_area(doc, &cx.editor.theme).width as usize) / 2);
 }
 
 fn scroll_up(cx: &mut Context) {
@@ -5767,7 +6213,7 @@ fn shell(cx: &mut Context, prompt: Cow<'static, str>, behavior: ShellBehavior) {
 
             // after replace cursor may be out of bounds, do this to
             // make sure cursor is in view and update scroll as well
-            view.ensure_cursor_in_view(doc, cx.editor.config.scrolloff);
+            view.ensure_cursor_in_view(doc, &cx.editor.theme, cx.editor.config.scrolloff);
         },
     );
 
@@ -18,6 +18,7 @@ use helix_core::{
 use helix_view::{
     document::{Mode, SCRATCH_BUFFER_NAME},
     graphics::{CursorKind, Modifier, Rect, Style},
+    gutter::GutterText,
     info::Info,
     input::KeyEvent,
     keyboard::{KeyCode, KeyModifiers},
@@ -71,7 +72,7 @@ impl EditorView {
         loader: &syntax::Loader,
         config: &helix_view::editor::Config,
     ) {
-        let inner = view.inner_area();
+        let inner = view.inner_area(doc, theme);
         let area = view.area;
 
         let highlights = Self::doc_syntax_highlights(doc, view.offset, inner.height, theme, loader);
@@ -428,26 +429,32 @@ impl EditorView {
         let gutter_style = theme.get("ui.gutter");
 
         // avoid lots of small allocations by reusing a text buffer for each line
-        let mut text = String::with_capacity(8);
+        let mut text = GutterText::with_capacity(8);
 
-        for (constructor, width) in view.gutters() {
-            let gutter = constructor(doc, view, theme, config, is_focused, *width);
-            text.reserve(*width); // ensure there's enough space for the gutter
+        for gutter in view.gutters() {
+            let width = gutter.width(view, doc, theme);
+            if width == 0 {
+                continue;
+            }
+            let gutter = gutter.render(doc, view, theme, config, is_focused, width);
+            text.reserve(width); // ensure there's enough space for the gutter
             for (i, line) in (view.offset.row..(last_line + 1)).enumerate() {
                 let selected = cursors.contains(&line);
 
-                if let Some(style) = gutter(line, selected, &mut text) {
-                    surface.set_stringn(
-                        viewport.x + offset,
-                        viewport.y + i as u16,
-                        &text,
-                        *width,
-                        gutter_style.patch(style),
-                    );
+                // Every row is the first row of its line as long as lines are not wrapped.
+                if let Some(style) = gutter(line, selected, false, &mut text) {
+                    let mut x = viewport.x + offset;
+                    let mut remaining = width;
+                    for (span, style) in text.spans(gutter_style.patch(style)) {
+                        let (end, _) =
+                            surface.set_stringn(x, viewport.y + i as u16, span, remaining, style);
+                        remaining = remaining.saturating_sub((end - x) as usize);
+                        x = end;
+                    }
                 }
                 text.clear();
             }
-            offset += *width as u16;
+            offset += width as u16;
         }
     }
 
@@ -600,6 +607,24 @@ impl EditorView {
                 .push(Span::styled(format!(" {} ", count), base_style));
         }
 
+        // Lines changed since the diff base
+        let diff_stat = doc.diff_stat();
+        for (prefix, count, scope) in [
+            ('+', diff_stat.added, "diff.plus"),
+            ('~', diff_stat.modified, "diff.delta"),
+            ('-', diff_stat.deleted, "diff.minus"),
+        ] {
+            if count > 0 {
+                right_side_text.0.push(Span::styled(
+                    format!(" {}{}", prefix, count),
+                    base_style.patch(theme.get(scope)),
+                ));
+            }
+        }
+        if !diff_stat.is_empty() {
+            right_side_text.0.push(Span::styled(" ", base_style));
+        }
+
         // Selections
         let sels_count = doc.selection(view.id).len();
         right_side_text.0.push(Span::styled(
@@ -787,9 +812,30 @@ impl EditorView {
             } => {
                 let editor = &mut cxt.editor;
 
+                let gutter_click = editor.tree.views().find_map(|(view, _focus)| {
+                    view.gutter_at_screen_coords(
+                        &editor.documents[&view.doc],
+                        &editor.theme,
+                        row,
+                        column,
+                    )
+                    .map(|(gutter, line)| (gutter.clone(), line, view.id))
+                });
+
+                if let Some((gutter, line, view_id)) = gutter_click {
+                    editor.tree.focus = view_id;
+                    gutter.on_click(editor, line);
+                    return EventResult::Consumed(None);
+                }
+
                 let result = editor.tree.views().find_map(|(view, _focus)| {
-                    view.pos_at_screen_coords(&editor.documents[&view.doc], row, column)
-                        .map(|pos| (pos, view.id))
+                    view.pos_at_screen_coords(
+                        &editor.documents[&view.doc],
+                        &editor.theme,
+                        row,
+                        column,
+                    )
+                    .map(|pos| (pos, view.id))
                 });
 
                 if let Some((pos, view_id)) = result {
@@ -818,7 +864,7 @@ impl EditorView {
             } => {
                 let (view, doc) = current!(cxt.editor);
 
-                let pos = match view.pos_at_screen_coords(doc, row, column) {
+                let pos = match view.pos_at_screen_coords(doc, &cxt.editor.theme, row, column) {
                     Some(pos) => pos,
                     None => return EventResult::Ignored,
                 };
@@ -845,8 +891,13 @@ impl EditorView {
                 };
 
                 let result = cxt.editor.tree.views().find_map(|(view, _focus)| {
-                    view.pos_at_screen_coords(&cxt.editor.documents[&view.doc], row, column)
-                        .map(|_| view.id)
+                    view.pos_at_screen_coords(
+                        &cxt.editor.documents[&view.doc],
+                        &cxt.editor.theme,
+                        row,
+                        column,
+                    )
+                    .map(|_| view.id)
                 });
 
                 match result {
@@ -902,8 +953,13 @@ impl EditorView {
                 }
 
                 let result = editor.tree.views().find_map(|(view, _focus)| {
-                    view.pos_at_screen_coords(&editor.documents[&view.doc], row, column)
-                        .map(|pos| (pos, view.id))
+                    view.pos_at_screen_coords(
+                        &editor.documents[&view.doc],
+                        &editor.theme,
+                        row,
+                        column,
+                    )
+                    .map(|pos| (pos, view.id))
                 });
 
                 if let Some((pos, view_id)) = result {
@@ -1007,7 +1063,7 @@ impl Component for EditorView {
                 }
 
                 let (view, doc) = current!(cxt.editor);
-                view.ensure_cursor_in_view(doc, cxt.editor.config.scrolloff);
+                view.ensure_cursor_in_view(doc, &cxt.editor.theme, cxt.editor.config.scrolloff);
 
                 // mode transitions
                 match (mode, doc.mode()) {
@@ -21,7 +21,7 @@ pub use text::Text;
 
 use helix_core::regex::Regex;
 use helix_core::regex::RegexBuilder;
-use helix_view::{Document, Editor, View};
+use helix_view::Editor;
 
 use std::path::PathBuf;
 
@@ -30,7 +30,7 @@ pub fn regex_prompt(
     prompt: std::borrow::Cow<'static, str>,
     history_register: Option<char>,
     completion_fn: impl FnMut(&str) -> Vec<prompt::Completion> + 'static,
-    fun: impl Fn(&mut View, &mut Document, Regex, PromptEvent) + 'static,
+    fun: impl Fn(&mut Editor, Regex, PromptEvent) + 'static,
 ) -> Prompt {
     let (view, doc) = current!(cx.editor);
     let view_id = view.id;
@@ -46,22 +46,22 @@ pub fn regex_prompt(
                 PromptEvent::Abort => {
                     let (view, doc) = current!(cx.editor);
                     doc.set_selection(view.id, snapshot.clone());
+                    doc.set_search_matches(None);
                     view.offset = offset_snapshot;
                 }
                 PromptEvent::Validate => {
                     // TODO: push_jump to store selection just before jump
 
+                    doc_mut!(cx.editor).set_search_matches(None);
                     match Regex::new(input) {
-                        Ok(regex) => {
-                            let (view, doc) = current!(cx.editor);
-                            fun(view, doc, regex, event);
-                        }
+                        Ok(regex) => fun(cx.editor, regex, event),
                         Err(_err) => (), // TODO: mark command line as error
                     }
                 }
                 PromptEvent::Update => {
                     // skip empty input, TODO: trigger default
                     if input.is_empty() {
+                        doc_mut!(cx.editor).set_search_matches(None);
                         return;
                     }
 
@@ -81,9 +81,14 @@ pub fn regex_prompt(
                             // revert state to what it was before the last update
                             doc.set_selection(view.id, snapshot.clone());
 
-                            fun(view, doc, regex, event);
+                            fun(cx.editor, regex, event);
 
-                            view.ensure_cursor_in_view(doc, cx.editor.config.scrolloff);
+                            let (view, doc) = current!(cx.editor);
+                            view.ensure_cursor_in_view(
+                                doc,
+                                &cx.editor.theme,
+                                cx.editor.config.scrolloff,
+                            );
                         }
                         Err(_err) => (), // TODO: mark command line as error
                     }
@@ -161,8 +166,8 @@ pub fn file_picker(root: PathBuf, config: &helix_view::editor::Config) -> FilePi
                 .unwrap()
                 .into()
         },
-        move |editor: &mut Editor, path: &PathBuf, action| {
-            editor
+        move |cx, path: &PathBuf, action| {
+            cx.editor
                 .open(path.into(), action)
                 .expect("editor.open failed");
         },
@@ -1,126 +1,734 @@
 // Implementation reference: https://github.com/neovim/neovim/blob/f2906a4669a2eef6d7bf86a29648793d63c98949/runtime/autoload/provider/clipboard.vim#L68-L152
 
 use anyhow::Result;
+use encoding_rs::Encoding;
+use serde::Deserialize;
 use std::borrow::Cow;
+use std::time::Duration;
 
+use crate::editor::deserialize_duration_millis;
+
+pub use provider::{
+    CommandConfig, CommandProvider, CommandProviderBuilder, FileProvider, LinewiseMarker,
+    NopProvider,
+};
+#[cfg(any(test, feature = "test-util"))]
+pub use provider::{MockCall, MockProvider};
+
+/// Clipboard settings, read from the `[editor.clipboard]` section of the config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct ClipboardConfig {
+    /// User-defined clipboard commands. Autodetection is skipped when this is set.
+    pub custom: Option<CustomClipboardConfig>,
+    /// Name of the provider to use instead of autodetecting one, e.g. `xsel`.
+    pub provider: Option<String>,
+    /// Time in milliseconds a clipboard command may take before it is killed. Defaults to 500ms.
+    #[serde(deserialize_with = "deserialize_duration_millis")]
+    pub timeout: Duration,
+    /// Time in milliseconds to wait for the terminal to answer an OSC 52 clipboard read before
+    /// falling back to the internal clipboard. Defaults to 500ms.
+    #[serde(deserialize_with = "deserialize_duration_millis")]
+    pub osc52_timeout: Duration,
+    /// OSC 52 selection target the osc52 provider uses for the clipboard. Defaults to `c`.
+    #[serde(deserialize_with = "deserialize_osc52_target")]
+    pub osc52_clipboard_target: String,
+    /// OSC 52 selection target the osc52 provider uses for the primary selection. Defaults to
+    /// `p`.
+    #[serde(deserialize_with = "deserialize_osc52_target")]
+    pub osc52_primary_target: String,
+    /// Wrap the OSC 52 sequences the osc52 provider sends in tmux or screen passthrough
+    /// sequences, so they reach the outer terminal. Defaults to false.
+    pub osc52_passthrough: bool,
+    /// Read the primary selection through OSC 52 when the terminal answers a clipboard read with
+    /// an empty clipboard. Defaults to false.
+    pub osc52_primary_fallback: bool,
+    /// Copy to the terminal clipboard through OSC 52 with the osc52 provider. When disabled,
+    /// yanks are only kept inside the editor and pastes read them from there instead of the
+    /// terminal clipboard. Defaults to true.
+    pub osc52_copy: bool,
+    /// When the osc52 provider reads the terminal clipboard. Defaults to `never`.
+    pub osc52_paste: Osc52Paste,
+    /// Largest contents in bytes the osc52 provider copies to the terminal clipboard. Larger
+    /// copies fail with an error instead of sending a sequence that terminals or multiplexers
+    /// with a size limit silently drop, and are only kept inside the editor. Defaults to no
+    /// limit.
+    pub osc52_max_size: Option<usize>,
+    /// Name of the tmux buffer used by the tmux provider. Defaults to `helix`.
+    pub tmux_buffer: String,
+    /// Have the tmux provider ask tmux to load the outer terminal's clipboard with
+    /// `refresh-client -l` before pasting, waiting up to `tmux-refresh-timeout` for it.
+    /// Defaults to false.
+    pub tmux_refresh_client: bool,
+    /// Time in milliseconds the tmux provider waits for the outer terminal's clipboard after
+    /// `refresh-client -l` before falling back to its buffer. Defaults to 200ms.
+    #[serde(deserialize_with = "deserialize_duration_millis")]
+    pub tmux_refresh_timeout: Duration,
+    /// Use the clipboard commands for the primary selection when a command provider has no
+    /// primary selection commands, instead of ignoring it. Defaults to false.
+    pub primary_fallback_to_clipboard: bool,
+    /// Largest clipboard contents in bytes read from clipboard commands. Commands producing more
+    /// output are killed. Defaults to 64MiB.
+    pub max_size: usize,
+    /// How often a clipboard command failing transiently is run again before giving up: when it
+    /// couldn't be started, or when a copy command exits with an error. Commands that aren't
+    /// installed, paste commands exiting with an error and commands that time out are not
+    /// retried. Defaults to 1.
+    pub retries: usize,
+    /// Copy yanks to the system clipboard into the primary selection as well. Defaults to false.
+    pub yank_to_primary: bool,
+    /// Skip running the copy command of a command provider when the contents were just copied,
+    /// until the clipboard is read again. Yanks are lost if another program changed the
+    /// clipboard in between. Defaults to false.
+    pub dedup_writes: bool,
+    /// How command providers treat a trailing newline of pasted contents. Defaults to
+    /// `preserve`.
+    pub trailing_newline: TrailingNewline,
+    /// Command listing the history of a clipboard manager, one entry per line and newest first,
+    /// e.g. `cliphist list`. Enables pasting from the history with command providers.
+    pub history_list: Option<CommandConfig>,
+    /// Command reading an entry listed by `history-list` from its input and printing the
+    /// contents to paste, e.g. `cliphist decode`. Without it entries are pasted as listed.
+    pub history_decode: Option<CommandConfig>,
+    /// Remember in the cache directory whether the contents last copied were yanked linewise,
+    /// so that pasting them in any Helix instance is linewise too, even if the clipboard tools
+    /// added or stripped the trailing newline. Contents copied by other programs are pasted
+    /// linewise if they end with a newline. Defaults to false.
+    pub linewise_marker: bool,
+}
+
+/// When the osc52 provider reads the terminal clipboard, see [`ClipboardConfig::osc52_paste`].
+/// Clipboards that aren't read from the terminal are read from the internal clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Osc52Paste {
+    /// Reads until the terminal fails to answer once, assuming it blocks reads.
+    ///
+    /// Reads race the editor's own input handling for the terminal's answer, so keys pressed
+    /// while reading may be lost.
+    Auto,
+    /// Always reads, waiting up to `osc52-timeout` each time.
+    Always,
+    /// Never reads, for terminals that only allow writing the clipboard.
+    Never,
+}
+
+/// How command providers treat a trailing newline of pasted contents, see
+/// [`ClipboardConfig::trailing_newline`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrailingNewline {
+    /// Paste what the paste command prints.
+    Keep,
+    /// Paste contents that were copied from Helix with exactly the trailing newlines they were
+    /// copied with, for clipboard tools that add or strip them. Line-wise yanks keep their
+    /// newline and character-wise ones don't get one.
+    Preserve,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            custom: None,
+            provider: None,
+            timeout: Duration::from_millis(500),
+            osc52_timeout: Duration::from_millis(500),
+            osc52_clipboard_target: "c".to_string(),
+            osc52_primary_target: "p".to_string(),
+            osc52_passthrough: false,
+            osc52_primary_fallback: false,
+            osc52_copy: true,
+            osc52_paste: Osc52Paste::Never,
+            osc52_max_size: None,
+            tmux_buffer: "helix".to_string(),
+            tmux_refresh_client: false,
+            tmux_refresh_timeout: Duration::from_millis(200),
+            primary_fallback_to_clipboard: false,
+            max_size: 64 * 1024 * 1024,
+            retries: 1,
+            yank_to_primary: false,
+            dedup_writes: false,
+            trailing_newline: TrailingNewline::Preserve,
+            history_list: None,
+            history_decode: None,
+            linewise_marker: false,
+        }
+    }
+}
+
+/// Selection targets OSC 52 knows: clipboard, primary, secondary, select and the cut buffers.
+const OSC52_TARGETS: &str = "cpqs01234567";
+
+fn deserialize_osc52_target<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let target = String::deserialize(deserializer)?;
+    if target.is_empty() || !target.chars().all(|c| OSC52_TARGETS.contains(c)) {
+        return Err(serde::de::Error::custom(format!(
+            "invalid OSC 52 target {:?}, expected some of {:?}",
+            target, OSC52_TARGETS
+        )));
+    }
+    Ok(target)
+}
+
+/// Commands making up a user-defined clipboard provider.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CustomClipboardConfig {
+    pub paste: CommandConfig,
+    pub copy: CommandConfig,
+    pub primary_paste: Option<CommandConfig>,
+    pub primary_copy: Option<CommandConfig>,
+    /// Encoding of the text the commands read and write, e.g. `utf-16le`. Defaults to UTF-8.
+    #[serde(
+        default = "default_encoding",
+        deserialize_with = "deserialize_encoding"
+    )]
+    pub encoding: &'static Encoding,
+}
+
+fn default_encoding() -> &'static Encoding {
+    encoding_rs::UTF_8
+}
+
+fn deserialize_encoding<'de, D>(deserializer: D) -> Result<&'static Encoding, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let label = String::deserialize(deserializer)?;
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown encoding {:?}", label)))
+}
+
+/// Errors returned by the clipboard providers, wrapped in an [`anyhow::Error`]. Use
+/// `err.downcast_ref::<ClipboardError>()` to react to a specific kind of failure.
+#[derive(Debug)]
+pub enum ClipboardError {
+    /// A provider, or a tool or service it needs, isn't available.
+    NotFound(String),
+    /// A provider didn't answer in time.
+    Timeout(String),
+    /// The clipboard holds something other than text, like an image.
+    NonText {
+        /// MIME type of the contents, if the provider can tell.
+        mime_type: Option<String>,
+    },
+    /// A clipboard command exited with an error.
+    ProcessFailed {
+        program: String,
+        /// The first lines the command printed to stderr, empty if it printed nothing or
+        /// stderr isn't captured.
+        stderr: String,
+    },
+    /// The contents are larger than the provider can write, like terminals limiting the size
+    /// of OSC 52 sequences.
+    TooLarge {
+        /// Size of the contents in bytes.
+        size: usize,
+        /// Largest contents in bytes the provider writes.
+        max_size: usize,
+    },
+    /// Talking to a clipboard command or the terminal failed.
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(message) | Self::Timeout(message) => f.write_str(message),
+            Self::NonText { mime_type } => {
+                write!(f, "clipboard contains non-text data")?;
+                if let Some(mime_type) = mime_type {
+                    write!(f, " ({})", mime_type)?;
+                }
+                Ok(())
+            }
+            Self::ProcessFailed { program, stderr } => {
+                write!(f, "clipboard provider {} failed", program)?;
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            }
+            Self::TooLarge { size, max_size } => write!(
+                f,
+                "clipboard contents of {} bytes exceed the size limit of {} bytes",
+                size, max_size
+            ),
+            Self::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClipboardError {}
+
+impl From<std::io::Error> for ClipboardError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClipboardType {
     Clipboard,
     Selection,
+    /// The X11 SECONDARY selection. Providers that don't support it read it as empty and
+    /// ignore writes to it.
+    Secondary,
+}
+
+/// Reads the clipboard on another thread, see [`ClipboardProvider::contents_reader`].
+pub type ContentsReader = Box<dyn FnOnce() -> Result<String> + Send>;
+
+/// Writes the clipboard on another thread, see [`ClipboardProvider::contents_writer`].
+pub type ContentsWriter = Box<dyn FnOnce(String) -> Result<()> + Send>;
+
+/// Lists the history of a clipboard manager on another thread, see
+/// [`ClipboardProvider::list_history`].
+pub type HistoryReader = Box<dyn FnOnce() -> Result<Vec<String>> + Send>;
+
+/// How a clipboard provider accesses the clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardKind {
+    /// Runs external programs.
+    Command,
+    /// Sends OSC 52 escape sequences to the terminal.
+    Terminal,
+    /// Talks to the system clipboard directly, through a library or D-Bus.
+    Native,
+    /// Keeps the clipboard in files.
+    File,
+    /// Keeps the clipboard inside the editor.
+    None,
+}
+
+/// Describes a clipboard provider, see [`ClipboardProvider::info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardInfo {
+    /// Name shown to the user, e.g. `xclip` or `wl-paste+wl-copy`.
+    pub name: String,
+    pub kind: ClipboardKind,
+    /// Whether [`ClipboardType::Selection`] is backed by a primary selection.
+    pub supports_primary: bool,
+    /// Programs the provider runs, empty if it doesn't run any.
+    pub binaries: Vec<String>,
 }
 
 pub trait ClipboardProvider: std::fmt::Debug {
-    fn name(&self) -> Cow<str>;
+    /// Describes the provider.
+    fn info(&self) -> ClipboardInfo;
+    fn name(&self) -> Cow<str> {
+        Cow::Owned(self.info().name)
+    }
+    /// Reads the clipboard. This is synchronous and blocks until the provider answers (or its
+    /// timeout expires), so it can be called from any editor code path.
     fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String>;
+    /// Writes the clipboard. Like [`ClipboardProvider::get_contents`] this blocks until the
+    /// provider is done.
     fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()>;
+
+    /// Returns a function reading the clipboard that can run on another thread, so that slow
+    /// providers don't block the editor. Providers that can only be read in place return `None`
+    /// and are read with [`ClipboardProvider::get_contents`].
+    fn contents_reader(&self, _clipboard_type: ClipboardType) -> Option<ContentsReader> {
+        None
+    }
+
+    /// Returns a function writing the clipboard that can run on another thread, the counterpart
+    /// of [`ClipboardProvider::contents_reader`]. Providers that can only be written in place
+    /// return `None` and are written with [`ClipboardProvider::set_contents`].
+    fn contents_writer(&mut self, _clipboard_type: ClipboardType) -> Option<ContentsWriter> {
+        None
+    }
+
+    /// Whether [`ClipboardType::Selection`] is backed by a primary selection, rather than
+    /// being ignored.
+    fn supports_primary(&self) -> bool {
+        false
+    }
+
+    /// Whether [`ClipboardType::Secondary`] is backed by a secondary selection, rather than
+    /// being ignored.
+    fn supports_secondary(&self) -> bool {
+        false
+    }
+
+    /// Returns the `index`-th previously set value, where `0` is the most recent one. Providers
+    /// that don't keep a history return `None`.
+    fn get_history(&self, _clipboard_type: ClipboardType, _index: usize) -> Option<String> {
+        None
+    }
+
+    /// Returns a function listing the entries of an external clipboard manager's history,
+    /// newest first, that can run on another thread. Providers without access to one return
+    /// `None`, which leaves no history to paste from.
+    fn list_history(&self) -> Option<HistoryReader> {
+        None
+    }
+
+    /// Turns an entry listed by [`ClipboardProvider::list_history`] into the contents to paste.
+    /// Defaults to the entry itself.
+    fn decode_history_entry(&self, entry: &str) -> Result<String> {
+        Ok(entry.to_string())
+    }
+
+    /// Returns a function running [`ClipboardProvider::decode_history_entry`] for `entry` that
+    /// can run on another thread. Defaults to returning the entry itself.
+    fn history_entry_reader(&self, entry: &str) -> ContentsReader {
+        let entry = entry.to_string();
+        Box::new(move || Ok(entry))
+    }
+
+    /// Whether `clipboard_type` may hold something else than what the editor last set, e.g.
+    /// because another program copied to it. Providers that can't tell cheaply answer `true`.
+    fn has_changed(&self, _clipboard_type: ClipboardType) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Sets both the clipboard and, if supported, the primary selection to `contents`. Only
+    /// failing to set the clipboard is an error, failing to set the primary selection is logged.
+    fn set_contents_all(&mut self, contents: String) -> Result<()> {
+        if !self.supports_primary() {
+            return self.set_contents(contents, ClipboardType::Clipboard);
+        }
+        self.set_contents(contents.clone(), ClipboardType::Clipboard)?;
+        if let Err(err) = self.set_contents(contents, ClipboardType::Selection) {
+            log::warn!("Couldn't set the primary selection: {}", err);
+        }
+        Ok(())
+    }
+
+    /// Empties the clipboard. Defaults to setting it to an empty string.
+    fn clear(&mut self, clipboard_type: ClipboardType) -> Result<()> {
+        self.set_contents(String::new(), clipboard_type)
+    }
 }
 
 macro_rules! command_provider {
-    (paste => $get_prg:literal $( , $get_arg:literal )* ; copy => $set_prg:literal $( , $set_arg:literal )* ; ) => {{
-        Box::new(provider::CommandProvider {
-            get_cmd: provider::CommandConfig {
-                prg: $get_prg,
-                args: &[ $( $get_arg ),* ],
-            },
-            set_cmd: provider::CommandConfig {
-                prg: $set_prg,
-                args: &[ $( $set_arg ),* ],
-            },
-            get_primary_cmd: None,
-            set_primary_cmd: None,
-        })
+    ($config:expr; paste => $get_prg:literal $( , $get_arg:literal )* ; copy => $set_prg:literal $( , $set_arg:literal )* ; ) => {{
+        Box::new(
+            provider::CommandProvider::builder()
+                .config(&$config)
+                .paste($get_prg, vec![ $( $get_arg.to_owned() ),* ])
+                .copy($set_prg, vec![ $( $set_arg.to_owned() ),* ])
+                .build_command_provider()
+                .expect("paste and copy commands are set"),
+        )
     }};
 
-    (paste => $get_prg:literal $( , $get_arg:literal )* ;
+    ($config:expr;
+     paste => $get_prg:literal $( , $get_arg:literal )* ;
      copy => $set_prg:literal $( , $set_arg:literal )* ;
      primary_paste => $pr_get_prg:literal $( , $pr_get_arg:literal )* ;
      primary_copy => $pr_set_prg:literal $( , $pr_set_arg:literal )* ;
     ) => {{
-        Box::new(provider::CommandProvider {
-            get_cmd: provider::CommandConfig {
-                prg: $get_prg,
-                args: &[ $( $get_arg ),* ],
-            },
-            set_cmd: provider::CommandConfig {
-                prg: $set_prg,
-                args: &[ $( $set_arg ),* ],
-            },
-            get_primary_cmd: Some(provider::CommandConfig {
-                prg: $pr_get_prg,
-                args: &[ $( $pr_get_arg ),* ],
-            }),
-            set_primary_cmd: Some(provider::CommandConfig {
-                prg: $pr_set_prg,
-                args: &[ $( $pr_set_arg ),* ],
-            }),
-        })
+        Box::new(
+            provider::CommandProvider::builder()
+                .config(&$config)
+                .paste($get_prg, vec![ $( $get_arg.to_owned() ),* ])
+                .copy($set_prg, vec![ $( $set_arg.to_owned() ),* ])
+                .primary_paste($pr_get_prg, vec![ $( $pr_get_arg.to_owned() ),* ])
+                .primary_copy($pr_set_prg, vec![ $( $pr_set_arg.to_owned() ),* ])
+                .build_command_provider()
+                .expect("paste and copy commands are set"),
+        )
+    }};
+
+    ($config:expr;
+     paste => $get_prg:literal $( , $get_arg:literal )* ;
+     copy => $set_prg:literal $( , $set_arg:literal )* ;
+     primary_paste => $pr_get_prg:literal $( , $pr_get_arg:literal )* ;
+     primary_copy => $pr_set_prg:literal $( , $pr_set_arg:literal )* ;
+     secondary_paste => $sec_get_prg:literal $( , $sec_get_arg:literal )* ;
+     secondary_copy => $sec_set_prg:literal $( , $sec_set_arg:literal )* ;
+    ) => {{
+        Box::new(
+            provider::CommandProvider::builder()
+                .config(&$config)
+                .paste($get_prg, vec![ $( $get_arg.to_owned() ),* ])
+                .copy($set_prg, vec![ $( $set_arg.to_owned() ),* ])
+                .primary_paste($pr_get_prg, vec![ $( $pr_get_arg.to_owned() ),* ])
+                .primary_copy($pr_set_prg, vec![ $( $pr_set_arg.to_owned() ),* ])
+                .secondary_paste($sec_get_prg, vec![ $( $sec_get_arg.to_owned() ),* ])
+                .secondary_copy($sec_set_prg, vec![ $( $sec_set_arg.to_owned() ),* ])
+                .build_command_provider()
+                .expect("paste and copy commands are set"),
+        )
     }};
 }
 
-pub fn get_clipboard_provider() -> Box<dyn ClipboardProvider> {
-    // TODO: support for user-defined provider, probably when we have plugin support by setting a
-    // variable?
+/// Detects the clipboard provider to use on this system.
+///
+/// Detection looks up executables in `PATH` and may spawn processes to probe them, so the result
+/// should be cached by the caller instead of calling this for every clipboard operation. The
+/// editor does this on startup and only re-detects on explicit request.
+///
+/// Setting the `HELIX_CLIPBOARD_FILE` environment variable overrides the configuration and
+/// keeps the clipboard in that file, see [`provider::FileProvider`]. Setting
+/// `HELIX_CLIPBOARD_PROVIDER` overrides the configured provider name, e.g. `none` keeps the
+/// clipboard internal without touching the terminal or spawning processes.
+///
+/// Fails only if a provider was requested by name and can't be used.
+pub fn get_clipboard_provider(config: &ClipboardConfig) -> Result<Box<dyn ClipboardProvider>> {
+    if let Some(path) = std::env::var_os("HELIX_CLIPBOARD_FILE") {
+        return Ok(Box::new(provider::FileProvider::new(path.into())));
+    }
+
+    if let Ok(name) = std::env::var("HELIX_CLIPBOARD_PROVIDER") {
+        return get_clipboard_provider_by_name(&name, config);
+    }
+
+    if let Some(custom) = &config.custom {
+        let mut builder = provider::CommandProvider::builder()
+            .config(config)
+            .paste_command(custom.paste.clone())
+            .copy_command(custom.copy.clone())
+            .encoding(custom.encoding);
+        if let Some(cmd) = &custom.primary_paste {
+            builder = builder.primary_paste_command(cmd.clone());
+        }
+        if let Some(cmd) = &custom.primary_copy {
+            builder = builder.primary_copy_command(cmd.clone());
+        }
+        return builder.build();
+    }
+
+    if let Some(name) = &config.provider {
+        return get_clipboard_provider_by_name(name, config);
+    }
+
+    #[cfg(all(feature = "wayland-clipboard", unix, not(target_os = "macos")))]
+    if env_var_is_set("WAYLAND_DISPLAY") {
+        // Compositors without `wlr-data-control`, like GNOME's, fall through to wl-clipboard.
+        match provider::WaylandProvider::connect() {
+            Ok(provider) => return Ok(Box::new(provider)),
+            Err(err) => log::debug!("The Wayland clipboard is not available: {}", err),
+        }
+    }
+
+    #[cfg(all(feature = "klipper-clipboard", unix, not(target_os = "macos")))]
+    if env_var_is_set("DISPLAY") && !env_var_is_set("WAYLAND_DISPLAY") {
+        match provider::KlipperProvider::connect(x11_primary_provider(config)) {
+            Ok(provider) => return Ok(Box::new(provider)),
+            Err(err) => log::debug!("Klipper is not available: {}", err),
+        }
+    }
+
+    #[cfg(feature = "native-clipboard")]
+    if native_clipboard_available() {
+        match provider::NativeProvider::new() {
+            Ok(provider) => return Ok(Box::new(provider)),
+            Err(err) => log::warn!("Failed to initialize the native clipboard: {}", err),
+        }
+    }
+
+    let name = detect_provider_name();
+    if name == "none" {
+        warn_no_system_clipboard();
+    }
+    build_provider(name, config)
+}
+
+/// Tells once per process that yanks won't reach the system clipboard, as detection runs again
+/// whenever the config is reloaded.
+fn warn_no_system_clipboard() {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| {
+        log::warn!(
+            "No system clipboard provider found, only the in-editor clipboard is available. \
+             Install wl-clipboard (Wayland), xclip or xsel (X11, with DISPLAY set), lemonade, \
+             doitclient, termux-api or run inside tmux to use the system clipboard, set \
+             editor.clipboard.provider to \"osc52\" to use the terminal clipboard, or configure \
+             editor.clipboard.custom."
+        );
+    });
+}
+
+/// Builds the provider called `name`, failing if its tools are not installed.
+pub fn get_clipboard_provider_by_name(
+    name: &str,
+    config: &ClipboardConfig,
+) -> Result<Box<dyn ClipboardProvider>> {
+    let required: &[&str] = match name {
+        "pbcopy" => &["pbcopy", "pbpaste"],
+        "wl-clipboard" => &["wl-copy", "wl-paste"],
+        "xclip" => &["xclip"],
+        "xsel" => &["xsel"],
+        "lemonade" => &["lemonade"],
+        "doitclient" => &["doitclient"],
+        "win32yank" => &["win32yank.exe"],
+        "wsl" => &["clip.exe", "powershell.exe"],
+        "termux" => &["termux-clipboard-set", "termux-clipboard-get"],
+        "tmux" => &["tmux"],
+        _ => &[],
+    };
+    if let Some(missing) = required.iter().find(|executable| !exists(executable)) {
+        return Err(ClipboardError::NotFound(format!(
+            "clipboard provider {} requires `{}`, which was not found",
+            name, missing
+        ))
+        .into());
+    }
+    build_provider(name, config)
+}
 
+/// Picks the name of the first usable provider, falling back to an internal clipboard.
+fn detect_provider_name() -> &'static str {
     if exists("pbcopy") && exists("pbpaste") {
-        command_provider! {
+        "pbcopy"
+    } else if is_wsl() && exists("win32yank.exe") {
+        // WSLg sets DISPLAY and WAYLAND_DISPLAY, but users expect the Windows clipboard.
+        "win32yank"
+    } else if is_wsl() && exists("clip.exe") && exists("powershell.exe") {
+        "wsl"
+    } else if env_var_is_set("WAYLAND_DISPLAY") && exists("wl-copy") && exists("wl-paste") {
+        "wl-clipboard"
+    } else if env_var_is_set("DISPLAY") && exists("xclip") {
+        "xclip"
+    } else if env_var_is_set("DISPLAY") && exists("xsel") && is_exit_success("xsel", &["-o", "-b"])
+    {
+        // FIXME: check performance of is_exit_success
+        "xsel"
+    } else if exists("lemonade") {
+        "lemonade"
+    } else if exists("doitclient") {
+        "doitclient"
+    } else if exists("win32yank.exe") {
+        "win32yank"
+    } else if exists("termux-clipboard-set") && exists("termux-clipboard-get") {
+        "termux"
+    } else if env_var_is_set("TMUX") && exists("tmux") {
+        "tmux"
+    } else if cfg!(target_os = "windows") {
+        "windows"
+    } else {
+        // OSC 52 isn't detected, as terminals differ too much in what they allow
+        "none"
+    }
+}
+
+/// Builds the provider called `name` without checking whether it is usable.
+fn build_provider(name: &str, config: &ClipboardConfig) -> Result<Box<dyn ClipboardProvider>> {
+    let provider: Box<dyn ClipboardProvider> = match name {
+        "pbcopy" => command_provider! {
+            config;
             paste => "pbpaste";
             copy => "pbcopy";
-        }
-    } else if env_var_is_set("WAYLAND_DISPLAY") && exists("wl-copy") && exists("wl-paste") {
-        command_provider! {
+        },
+        "wl-clipboard" => command_provider! {
+            config;
             paste => "wl-paste", "--no-newline";
             copy => "wl-copy", "--type", "text/plain";
             primary_paste => "wl-paste", "-p", "--no-newline";
             primary_copy => "wl-copy", "-p", "--type", "text/plain";
-        }
-    } else if env_var_is_set("DISPLAY") && exists("xclip") {
-        command_provider! {
+        },
+        "xclip" => command_provider! {
+            config;
             paste => "xclip", "-o", "-selection", "clipboard";
             copy => "xclip", "-i", "-selection", "clipboard";
             primary_paste => "xclip", "-o";
             primary_copy => "xclip", "-i";
-        }
-    } else if env_var_is_set("DISPLAY") && exists("xsel") && is_exit_success("xsel", &["-o", "-b"])
-    {
-        // FIXME: check performance of is_exit_success
-        command_provider! {
+            secondary_paste => "xclip", "-o", "-selection", "secondary";
+            secondary_copy => "xclip", "-i", "-selection", "secondary";
+        },
+        "xsel" => command_provider! {
+            config;
             paste => "xsel", "-o", "-b";
             copy => "xsel", "-i", "-b";
             primary_paste => "xsel", "-o";
             primary_copy => "xsel", "-i";
-        }
-    } else if exists("lemonade") {
-        command_provider! {
+            secondary_paste => "xsel", "-o", "-s";
+            secondary_copy => "xsel", "-i", "-s";
+        },
+        "lemonade" => command_provider! {
+            config;
             paste => "lemonade", "paste";
             copy => "lemonade", "copy";
-        }
-    } else if exists("doitclient") {
-        command_provider! {
+        },
+        "doitclient" => command_provider! {
+            config;
             paste => "doitclient", "wclip", "-r";
             copy => "doitclient", "wclip";
-        }
-    } else if exists("win32yank.exe") {
-        // FIXME: does it work within WSL?
-        command_provider! {
+        },
+        "win32yank" => command_provider! {
+            config;
             paste => "win32yank.exe", "-o", "--lf";
             copy => "win32yank.exe", "-i", "--crlf";
         }
-    } else if exists("termux-clipboard-set") && exists("termux-clipboard-get") {
-        command_provider! {
+        .normalizing_crlf(),
+        "wsl" => command_provider! {
+            config;
+            paste => "powershell.exe", "-NoProfile", "-Command", "Get-Clipboard -Raw";
+            copy => "clip.exe";
+        }
+        .normalizing_crlf(),
+        "termux" => command_provider! {
+            config;
             paste => "termux-clipboard-get";
             copy => "termux-clipboard-set";
+        },
+        "tmux" => Box::new(provider::TmuxProvider {
+            buffer: config.tmux_buffer.clone(),
+            timeout: config.timeout,
+            max_size: config.max_size,
+            retries: config.retries,
+            refresh_timeout: if config.tmux_refresh_client {
+                Some(config.tmux_refresh_timeout)
+            } else {
+                None
+            },
+        }),
+        #[cfg(all(feature = "wayland-clipboard", unix, not(target_os = "macos")))]
+        "wayland" => Box::new(provider::WaylandProvider::connect()?),
+        #[cfg(all(feature = "klipper-clipboard", unix, not(target_os = "macos")))]
+        "klipper" => Box::new(provider::KlipperProvider::connect(x11_primary_provider(
+            config,
+        ))?),
+        #[cfg(feature = "native-clipboard")]
+        "native" => Box::new(provider::NativeProvider::new()?),
+        #[cfg(target_os = "windows")]
+        "windows" => Box::new(provider::WindowsProvider::default()),
+        #[cfg(unix)]
+        "osc52" => Box::new(provider::TermProvider::new(config)),
+        "none" => Box::new(provider::NopProvider::new()),
+        _ => {
+            return Err(
+                ClipboardError::NotFound(format!("unknown clipboard provider {}", name)).into(),
+            )
         }
-    } else if env_var_is_set("TMUX") && exists("tmux") {
-        command_provider! {
-            paste => "tmux", "save-buffer", "-";
-            copy => "tmux", "load-buffer", "-";
-        }
+    };
+    Ok(provider)
+}
+
+/// Whether a display server the native clipboard can talk to is available.
+#[cfg(feature = "native-clipboard")]
+fn native_clipboard_available() -> bool {
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        true
     } else {
-        #[cfg(target_os = "windows")]
-        return Box::new(provider::WindowsProvider::default());
+        env_var_is_set("DISPLAY")
+    }
+}
+
+/// Provider for the X11 primary selection, which Klipper doesn't give access to.
+#[cfg(all(feature = "klipper-clipboard", unix, not(target_os = "macos")))]
+fn x11_primary_provider(config: &ClipboardConfig) -> Option<Box<dyn ClipboardProvider>> {
+    ["xclip", "xsel"]
+        .into_iter()
+        .find(|name| exists(name))
+        .and_then(|name| build_provider(name, config).ok())
+}
 
-        #[cfg(not(target_os = "windows"))]
-        return Box::new(provider::NopProvider::new());
+/// Whether we run inside the Windows Subsystem for Linux.
+fn is_wsl() -> bool {
+    if env_var_is_set("WSL_DISTRO_NAME") {
+        return true;
     }
+    std::fs::read_to_string("/proc/version")
+        .map(|version| version.to_lowercase().contains("microsoft"))
+        .unwrap_or(false)
 }
 
 fn exists(executable_name: &str) -> bool {
@@ -141,170 +749,3676 @@ fn is_exit_success(program: &str, args: &[&str]) -> bool {
 }
 
 mod provider {
-    use super::{ClipboardProvider, ClipboardType};
-    use anyhow::{bail, Context as _, Result};
-    use std::borrow::Cow;
+    use super::{
+        ClipboardError, ClipboardInfo, ClipboardKind, ClipboardProvider, ClipboardType, Osc52Paste,
+        TrailingNewline,
+    };
+    use anyhow::{anyhow, bail, Context as _, Result};
+    use encoding_rs::Encoding;
+    use serde::Deserialize;
+    #[cfg(unix)]
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
-    #[cfg(not(target_os = "windows"))]
-    #[derive(Debug)]
+    /// Number of values [`NopProvider`] remembers per clipboard type.
+    pub const HISTORY_LEN: usize = 16;
+
+    /// Internal clipboard that keeps the last [`HISTORY_LEN`] values of each clipboard type,
+    /// most recent first.
+    #[derive(Default, Debug, Clone)]
     pub struct NopProvider {
-        buf: String,
-        primary_buf: String,
+        buf: VecDeque<String>,
+        primary_buf: VecDeque<String>,
     }
 
-    #[cfg(not(target_os = "windows"))]
     impl NopProvider {
         pub fn new() -> Self {
-            Self {
-                buf: String::new(),
-                primary_buf: String::new(),
+            Self::default()
+        }
+
+        /// History of `clipboard_type`, `None` for the unsupported secondary selection.
+        fn ring(&self, clipboard_type: ClipboardType) -> Option<&VecDeque<String>> {
+            match clipboard_type {
+                ClipboardType::Clipboard => Some(&self.buf),
+                ClipboardType::Selection => Some(&self.primary_buf),
+                ClipboardType::Secondary => None,
+            }
+        }
+
+        fn ring_mut(&mut self, clipboard_type: ClipboardType) -> Option<&mut VecDeque<String>> {
+            match clipboard_type {
+                ClipboardType::Clipboard => Some(&mut self.buf),
+                ClipboardType::Selection => Some(&mut self.primary_buf),
+                ClipboardType::Secondary => None,
             }
         }
     }
 
-    #[cfg(not(target_os = "windows"))]
     impl ClipboardProvider for NopProvider {
-        fn name(&self) -> Cow<str> {
-            Cow::Borrowed("none")
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: "none".to_string(),
+                kind: ClipboardKind::None,
+                supports_primary: self.supports_primary(),
+                binaries: Vec::new(),
+            }
         }
 
-        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
-            let value = match clipboard_type {
-                ClipboardType::Clipboard => self.buf.clone(),
-                ClipboardType::Selection => self.primary_buf.clone(),
-            };
+        fn supports_primary(&self) -> bool {
+            true
+        }
 
-            Ok(value)
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            Ok(self.get_history(clipboard_type, 0).unwrap_or_default())
         }
 
         fn set_contents(&mut self, content: String, clipboard_type: ClipboardType) -> Result<()> {
-            match clipboard_type {
-                ClipboardType::Clipboard => self.buf = content,
-                ClipboardType::Selection => self.primary_buf = content,
+            if let Some(ring) = self.ring_mut(clipboard_type) {
+                if ring.len() == HISTORY_LEN {
+                    ring.pop_back();
+                }
+                ring.push_front(content);
+            }
+            Ok(())
+        }
+
+        fn get_history(&self, clipboard_type: ClipboardType, index: usize) -> Option<String> {
+            self.ring(clipboard_type)?.get(index).cloned()
+        }
+
+        /// Nothing but the editor writes the internal clipboard.
+        fn has_changed(&self, _clipboard_type: ClipboardType) -> Result<bool> {
+            Ok(false)
+        }
+
+        /// Forgets the whole history of `clipboard_type`.
+        fn clear(&mut self, clipboard_type: ClipboardType) -> Result<()> {
+            if let Some(ring) = self.ring_mut(clipboard_type) {
+                ring.clear();
             }
             Ok(())
         }
     }
 
-    #[cfg(target_os = "windows")]
-    #[derive(Default, Debug)]
-    pub struct WindowsProvider;
+    /// Accesses the clipboard of the terminal through the OSC 52 escape sequence, which also
+    /// works over SSH. Reads fall back to an internal clipboard if the terminal doesn't answer.
+    #[cfg(unix)]
+    #[derive(Debug, Clone)]
+    pub struct TermProvider {
+        fallback: NopProvider,
+        /// Whether stdout is a terminal and `/dev/tty` can be opened. Without a terminal only
+        /// the internal clipboard is used, so no escape sequences end up in redirected output.
+        terminal: bool,
+        /// How long to wait for the terminal to answer a read.
+        timeout: Duration,
+        /// String terminator used for the sequences we send. Starts out as ST and follows
+        /// whatever the terminal used in its last answer.
+        terminator: Cell<&'static str>,
+        /// OSC 52 selection targets used for the clipboard and the primary selection.
+        clipboard_target: String,
+        primary_target: String,
+        /// Multiplexer that written sequences are passed through, if enabled.
+        passthrough: Option<Multiplexer>,
+        /// Whether an empty clipboard is read from the primary selection instead.
+        primary_fallback: bool,
+        /// Whether writes are sent to the terminal, rather than only kept internally.
+        copy: bool,
+        /// When reads ask the terminal.
+        paste: Osc52Paste,
+        /// Largest contents in bytes written to the terminal, if limited.
+        max_size: Option<usize>,
+        /// Set once a read timed out with [`Osc52Paste::Auto`]. Shared with the copies that
+        /// read in the background.
+        paste_blocked: Arc<AtomicBool>,
+    }
 
-    #[cfg(target_os = "windows")]
-    impl ClipboardProvider for WindowsProvider {
-        fn name(&self) -> Cow<str> {
-            Cow::Borrowed("clipboard-win")
+    /// Terminal multiplexer that OSC 52 sequences have to be explicitly passed through to reach
+    /// the outer terminal.
+    #[cfg(unix)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Multiplexer {
+        Tmux,
+        Screen,
+    }
+
+    #[cfg(unix)]
+    impl Multiplexer {
+        /// Detects the multiplexer we run in from the `TMUX` and `STY` environment variables.
+        fn detect() -> Option<Self> {
+            if super::env_var_is_set("TMUX") {
+                Some(Self::Tmux)
+            } else if super::env_var_is_set("STY") {
+                Some(Self::Screen)
+            } else {
+                None
+            }
         }
 
-        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
-            match clipboard_type {
-                ClipboardType::Clipboard => {
-                    let contents = clipboard_win::get_clipboard(clipboard_win::formats::Unicode)?;
-                    Ok(contents)
+        /// Wraps `sequence` in the multiplexer's passthrough sequence. tmux needs the escapes
+        /// within to be doubled, screen ends the passthrough at the first ST, so the sequence is
+        /// terminated with BEL instead. screen also truncates passthrough sequences longer than
+        /// 768 bytes, so the sequence is split over several of them, which screen forwards as
+        /// one.
+        pub fn wrap(self, sequence: &str) -> String {
+            match self {
+                Self::Tmux => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+                Self::Screen => {
+                    let sequence = sequence.strip_suffix(OSC52_ST).map_or_else(
+                        || sequence.to_string(),
+                        |sequence| format!("{}{}", sequence, OSC52_BEL),
+                    );
+                    // the sequence is ASCII, so chunks never split a character
+                    sequence
+                        .as_bytes()
+                        .chunks(SCREEN_PASSTHROUGH_CHUNK)
+                        .map(|chunk| format!("\x1bP{}\x1b\\", String::from_utf8_lossy(chunk)))
+                        .collect()
                 }
-                ClipboardType::Selection => Ok(String::new()),
             }
         }
+    }
 
-        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
-            match clipboard_type {
-                ClipboardType::Clipboard => {
-                    clipboard_win::set_clipboard(clipboard_win::formats::Unicode, contents)?;
+    #[cfg(unix)]
+    impl TermProvider {
+        /// Checks once whether a terminal is available, see [`TermProvider::with_terminal`].
+        pub fn new(config: &super::ClipboardConfig) -> Self {
+            let terminal = match check_terminal() {
+                Ok(()) => true,
+                Err(err) => {
+                    log::warn!(
+                        "Not using the terminal clipboard, only the internal one: {}",
+                        err
+                    );
+                    false
                 }
-                ClipboardType::Selection => {}
             };
-            Ok(())
+            Self::with_terminal(config, terminal)
         }
-    }
 
-    #[derive(Debug)]
-    pub struct CommandConfig {
-        pub prg: &'static str,
-        pub args: &'static [&'static str],
-    }
+        /// Creates a provider that behaves like [`NopProvider`] if there is no `terminal`.
+        pub(super) fn with_terminal(config: &super::ClipboardConfig, terminal: bool) -> Self {
+            Self {
+                fallback: NopProvider::new(),
+                terminal,
+                timeout: config.osc52_timeout,
+                terminator: Cell::new(OSC52_ST),
+                clipboard_target: config.osc52_clipboard_target.clone(),
+                primary_target: config.osc52_primary_target.clone(),
+                passthrough: if config.osc52_passthrough {
+                    Multiplexer::detect()
+                } else {
+                    None
+                },
+                primary_fallback: config.osc52_primary_fallback,
+                copy: config.osc52_copy,
+                paste: config.osc52_paste,
+                max_size: config.osc52_max_size,
+                paste_blocked: Arc::new(AtomicBool::new(false)),
+            }
+        }
 
-    impl CommandConfig {
-        fn execute(&self, input: Option<&str>, pipe_output: bool) -> Result<Option<String>> {
+        /// Wraps a sequence we send for the multiplexer, if passthrough is enabled.
+        fn passthrough(&self, sequence: String) -> String {
+            match self.passthrough {
+                Some(multiplexer) => multiplexer.wrap(&sequence),
+                None => sequence,
+            }
+        }
+
+        fn get_clip_char(&self, clipboard_type: ClipboardType) -> &str {
+            match clipboard_type {
+                ClipboardType::Clipboard => &self.clipboard_target,
+                ClipboardType::Selection => &self.primary_target,
+                ClipboardType::Secondary => "q",
+            }
+        }
+
+        /// Asks the terminal for the clipboard contents and waits for the answer.
+        fn query(&self, clipboard_type: ClipboardType) -> Result<String> {
             use std::io::Write;
-            use std::process::{Command, Stdio};
 
-            let stdin = input.map(|_| Stdio::piped()).unwrap_or_else(Stdio::null);
-            let stdout = pipe_output.then(Stdio::piped).unwrap_or_else(Stdio::null);
+            let mut tty = open_tty()?;
+            write!(
+                tty,
+                "\x1b]52;{};?{}",
+                self.get_clip_char(clipboard_type),
+                self.terminator.get()
+            )?;
+            tty.flush()?;
 
-            let mut child = Command::new(self.prg)
-                .args(self.args)
-                .stdin(stdin)
-                .stdout(stdout)
-                .stderr(Stdio::null())
-                .spawn()?;
+            let response = read_response(&mut tty, Instant::now() + self.timeout)?;
+            let (contents, terminator) =
+                parse_osc52_response(&response, self.get_clip_char(clipboard_type))?;
+            self.terminator.set(terminator);
+            Ok(contents)
+        }
 
-            if let Some(input) = input {
-                let mut stdin = child.stdin.take().context("stdin is missing")?;
-                stdin
-                    .write_all(input.as_bytes())
-                    .context("couldn't write in stdin")?;
+        /// Whether reads ask the terminal rather than going straight to the internal clipboard.
+        /// Without copying, the terminal clipboard never holds what was yanked last.
+        pub(super) fn reads_terminal(&self) -> bool {
+            if !self.copy {
+                return false;
             }
+            match self.paste {
+                Osc52Paste::Always => true,
+                Osc52Paste::Never => false,
+                Osc52Paste::Auto => !self.paste_blocked.load(Ordering::Relaxed),
+            }
+        }
 
-            // TODO: add timer?
-            let output = child.wait_with_output()?;
-
-            if !output.status.success() {
-                bail!("clipboard provider {} failed", self.prg);
+        /// Reads `clipboard_type` with `query`, trying the primary selection if the clipboard is
+        /// empty and the primary fallback is enabled. Falls back to the internal clipboard if
+        /// the queries fail or reads don't ask the terminal.
+        pub(super) fn read_contents(
+            &self,
+            clipboard_type: ClipboardType,
+            query: impl Fn(ClipboardType) -> Result<String>,
+        ) -> Result<String> {
+            if !self.reads_terminal() {
+                return self.fallback.get_contents(clipboard_type);
             }
+            let query = |clipboard_type| {
+                query(clipboard_type).inspect_err(|err| {
+                    if matches!(err.downcast_ref(), Some(ClipboardError::Timeout(_)))
+                        && self.paste == Osc52Paste::Auto
+                        && !self.paste_blocked.swap(true, Ordering::Relaxed)
+                    {
+                        log::info!(
+                            "The terminal didn't answer an OSC 52 read, reading the internal \
+                             clipboard from now on"
+                        );
+                    }
+                })
+            };
+            query(clipboard_type)
+                .and_then(|contents| {
+                    if contents.is_empty()
+                        && self.primary_fallback
+                        && clipboard_type == ClipboardType::Clipboard
+                    {
+                        log::debug!("Clipboard is empty, reading the primary selection instead");
+                        query(ClipboardType::Selection)
+                    } else {
+                        Ok(contents)
+                    }
+                })
+                .or_else(|err| self.fallback_contents(clipboard_type, err))
+        }
 
-            if pipe_output {
-                Ok(Some(String::from_utf8(output.stdout)?))
-            } else {
-                Ok(None)
+        /// Answers a read from the internal clipboard after querying the terminal failed.
+        pub(super) fn fallback_contents(
+            &self,
+            clipboard_type: ClipboardType,
+            err: anyhow::Error,
+        ) -> Result<String> {
+            log::info!(
+                "Reading the clipboard through OSC 52 failed, using the internal clipboard: {}",
+                err
+            );
+            self.fallback.get_contents(clipboard_type)
+        }
+
+        /// Sends `contents` to the terminal through `out`. The internal clipboard is updated
+        /// first, so that it stays in sync even if writing the escape sequence fails or the
+        /// contents exceed the size limit.
+        pub(super) fn write_contents(
+            &mut self,
+            out: &mut impl std::io::Write,
+            contents: String,
+            clipboard_type: ClipboardType,
+        ) -> Result<()> {
+            if let Some(max_size) = self.max_size.filter(|&max_size| contents.len() > max_size) {
+                let size = contents.len();
+                self.fallback.set_contents(contents, clipboard_type)?;
+                return Err(ClipboardError::TooLarge { size, max_size }.into());
             }
+            let sequence = self.passthrough(format!(
+                "\x1b]52;{};{}{}",
+                self.get_clip_char(clipboard_type),
+                base64::encode(&contents),
+                self.terminator.get()
+            ));
+            self.fallback.set_contents(contents, clipboard_type)?;
+            out.write_all(sequence.as_bytes())?;
+            out.flush()?;
+            Ok(())
+        }
+
+        /// Sends the OSC 52 sequence with an empty payload, which clears the clipboard, through
+        /// `out` and clears the internal clipboard.
+        pub(super) fn write_clear(
+            &mut self,
+            out: &mut impl std::io::Write,
+            clipboard_type: ClipboardType,
+        ) -> Result<()> {
+            self.fallback.clear(clipboard_type)?;
+            let sequence = self.passthrough(format!(
+                "\x1b]52;{};{}",
+                self.get_clip_char(clipboard_type),
+                self.terminator.get()
+            ));
+            out.write_all(sequence.as_bytes())?;
+            out.flush()?;
+            Ok(())
         }
     }
 
-    #[derive(Debug)]
-    pub struct CommandProvider {
-        pub get_cmd: CommandConfig,
-        pub set_cmd: CommandConfig,
-        pub get_primary_cmd: Option<CommandConfig>,
-        pub set_primary_cmd: Option<CommandConfig>,
+    /// Fails if stdout isn't a terminal or `/dev/tty` can't be opened, which happens with
+    /// redirected output and in some CI environments and containers.
+    #[cfg(unix)]
+    fn check_terminal() -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        // Safety: `isatty` only inspects the file descriptor, which stays open.
+        if unsafe { libc::isatty(std::io::stdout().as_raw_fd()) } != 1 {
+            bail!("stdout isn't a terminal");
+        }
+        open_tty()?;
+        Ok(())
     }
 
-    impl ClipboardProvider for CommandProvider {
-        fn name(&self) -> Cow<str> {
-            if self.get_cmd.prg != self.set_cmd.prg {
-                Cow::Owned(format!("{}+{}", self.get_cmd.prg, self.set_cmd.prg))
-            } else {
-                Cow::Borrowed(self.get_cmd.prg)
-            }
+    /// Opens the controlling terminal. Sequences are written to it rather than to stdout, so
+    /// they don't get mixed into output the renderer buffered but hasn't flushed yet.
+    #[cfg(unix)]
+    fn open_tty() -> Result<std::fs::File> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(ClipboardError::Io)
+            .context("couldn't open /dev/tty")
+    }
+
+    #[cfg(unix)]
+    const OSC52_PREFIX: &[u8] = b"\x1b]52;";
+    #[cfg(unix)]
+    const OSC52_ST: &str = "\x1b\\";
+    #[cfg(unix)]
+    const OSC52_BEL: &str = "\x07";
+    /// Bytes of a sequence passed through screen per passthrough sequence, below the 768 bytes
+    /// screen truncates them to.
+    #[cfg(unix)]
+    const SCREEN_PASSTHROUGH_CHUNK: usize = 512;
+
+    /// Decodes an OSC 52 answer of the form `ESC ] 52 ; <clip char> ; <base64 payload> ST`,
+    /// where the string terminator is either `ESC \` or BEL. The clip char has to be the
+    /// `target` that was queried. Returns the clipboard contents and the terminator the terminal
+    /// used.
+    #[cfg(unix)]
+    pub(super) fn parse_osc52_response(
+        response: &[u8],
+        target: &str,
+    ) -> Result<(String, &'static str)> {
+        let response = std::str::from_utf8(response)?;
+        let response = response
+            .strip_prefix("\x1b]52;")
+            .context("malformed OSC 52 response")?;
+        let payload_start = response.find(';').context("malformed OSC 52 response")?;
+        let response_target = &response[..payload_start];
+        if response_target != target {
+            bail!(
+                "OSC 52 response is for target {:?} instead of {:?}",
+                response_target,
+                target
+            );
         }
+        let payload = &response[payload_start + 1..];
+        let (payload, terminator) = if let Some(payload) = payload.strip_suffix(OSC52_ST) {
+            (payload, OSC52_ST)
+        } else if let Some(payload) = payload.strip_suffix(OSC52_BEL) {
+            (payload, OSC52_BEL)
+        } else {
+            bail!("malformed OSC 52 response");
+        };
+        let contents = String::from_utf8(base64::decode(payload)?)?;
+        Ok((contents, terminator))
+    }
 
-        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
-            match clipboard_type {
-                ClipboardType::Clipboard => Ok(self
-                    .get_cmd
-                    .execute(None, true)?
-                    .context("output is missing")?),
-                ClipboardType::Selection => {
-                    if let Some(cmd) = &self.get_primary_cmd {
-                        return cmd.execute(None, true)?.context("output is missing");
-                    }
+    /// Largest OSC 52 response we are willing to buffer, to avoid unbounded growth when the
+    /// terminal never terminates the sequence.
+    #[cfg(unix)]
+    const MAX_OSC52_RESPONSE_LEN: usize = 64 * 1024 * 1024;
 
-                    Ok(String::new())
-                }
-            }
+    /// Returns the range of the first complete OSC 52 sequence in `buf`, including its
+    /// terminator.
+    #[cfg(unix)]
+    fn find_osc52_response(buf: &[u8]) -> Option<std::ops::Range<usize>> {
+        let start = buf
+            .windows(OSC52_PREFIX.len())
+            .position(|window| window == OSC52_PREFIX)?;
+        // Base64 never contains ESC or BEL, so the first one ends the payload.
+        let body = start + OSC52_PREFIX.len();
+        let terminator = body + buf[body..].iter().position(|&b| b == 0x1b || b == 0x07)?;
+        match buf[terminator] {
+            0x07 => Some(start..terminator + 1),
+            _ => (buf.get(terminator + 1) == Some(&b'\\')).then(|| start..terminator + 2),
         }
+    }
 
-        fn set_contents(&mut self, value: String, clipboard_type: ClipboardType) -> Result<()> {
-            let cmd = match clipboard_type {
-                ClipboardType::Clipboard => &self.set_cmd,
-                ClipboardType::Selection => {
-                    if let Some(cmd) = &self.set_primary_cmd {
-                        cmd
-                    } else {
-                        return Ok(());
+    /// Reads from the terminal until a complete OSC 52 response arrived, which may take several
+    /// reads for large clipboard contents, or until `deadline` passes. Returns just the response.
+    ///
+    /// Fails with [`ClipboardError::Timeout`] if the terminal doesn't answer in time, which
+    /// terminals blocking reads do.
+    #[cfg(unix)]
+    fn read_response(tty: &mut std::fs::File, deadline: Instant) -> Result<Vec<u8>> {
+        use std::io::Read;
+        use std::os::unix::io::AsRawFd;
+
+        let mut response = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            if let Some(range) = find_osc52_response(&response) {
+                return Ok(response[range].to_vec());
+            }
+            if response.len() > MAX_OSC52_RESPONSE_LEN {
+                bail!("OSC 52 response exceeds {} bytes", MAX_OSC52_RESPONSE_LEN);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut pollfd = libc::pollfd {
+                fd: tty.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // Safety: `pollfd` is a valid pointer to exactly one `pollfd` struct.
+            let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis() as libc::c_int) };
+            if ready < 0 {
+                return Err(ClipboardError::Io(std::io::Error::last_os_error()).into());
+            }
+            if ready == 0 {
+                return Err(ClipboardError::Timeout(
+                    "timed out waiting for the OSC 52 response".to_string(),
+                )
+                .into());
+            }
+
+            let read = tty.read(&mut buf).map_err(ClipboardError::Io)?;
+            if read == 0 {
+                bail!("terminal closed while reading the OSC 52 response");
+            }
+            response.extend_from_slice(&buf[..read]);
+        }
+    }
+
+    #[cfg(unix)]
+    impl ClipboardProvider for TermProvider {
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: "osc52".to_string(),
+                kind: if self.terminal {
+                    ClipboardKind::Terminal
+                } else {
+                    ClipboardKind::None
+                },
+                supports_primary: self.supports_primary(),
+                binaries: Vec::new(),
+            }
+        }
+
+        /// Reads from a copy of the provider, so the string terminator the terminal answers
+        /// with isn't remembered.
+        fn contents_reader(&self, clipboard_type: ClipboardType) -> Option<super::ContentsReader> {
+            let provider = self.clone();
+            Some(Box::new(move || provider.get_contents(clipboard_type)))
+        }
+
+        fn supports_primary(&self) -> bool {
+            true
+        }
+
+        /// OSC 52 can target the secondary selection, the internal fallback doesn't keep it.
+        fn supports_secondary(&self) -> bool {
+            self.terminal
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            if !self.terminal {
+                return self.fallback.get_contents(clipboard_type);
+            }
+            self.read_contents(clipboard_type, |clipboard_type| self.query(clipboard_type))
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            if !self.terminal || !self.copy {
+                return self.fallback.set_contents(contents, clipboard_type);
+            }
+            self.write_contents(&mut open_tty()?, contents, clipboard_type)
+        }
+
+        fn get_history(&self, clipboard_type: ClipboardType, index: usize) -> Option<String> {
+            self.fallback.get_history(clipboard_type, index)
+        }
+
+        fn clear(&mut self, clipboard_type: ClipboardType) -> Result<()> {
+            if !self.terminal || !self.copy {
+                return self.fallback.clear(clipboard_type);
+            }
+            self.write_clear(&mut open_tty()?, clipboard_type)
+        }
+    }
+
+    /// Windows has no primary selection, so [`ClipboardType::Selection`] is emulated with a
+    /// buffer that only lives as long as the editor.
+    #[cfg(target_os = "windows")]
+    #[derive(Default, Debug)]
+    pub struct WindowsProvider {
+        primary_buf: String,
+    }
+
+    #[cfg(target_os = "windows")]
+    impl ClipboardProvider for WindowsProvider {
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: "clipboard-win".to_string(),
+                kind: ClipboardKind::Native,
+                supports_primary: self.supports_primary(),
+                binaries: Vec::new(),
+            }
+        }
+
+        fn supports_primary(&self) -> bool {
+            true
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            match clipboard_type {
+                ClipboardType::Clipboard => {
+                    let contents = clipboard_win::get_clipboard(clipboard_win::formats::Unicode)?;
+                    Ok(contents)
+                }
+                ClipboardType::Selection => Ok(self.primary_buf.clone()),
+                ClipboardType::Secondary => Ok(String::new()),
+            }
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            match clipboard_type {
+                ClipboardType::Clipboard => {
+                    clipboard_win::set_clipboard(clipboard_win::formats::Unicode, contents)?;
+                }
+                ClipboardType::Selection => self.primary_buf = contents,
+                ClipboardType::Secondary => {}
+            };
+            Ok(())
+        }
+    }
+
+    /// Talks to the display server in-process instead of spawning clipboard tools.
+    #[cfg(feature = "native-clipboard")]
+    pub struct NativeProvider {
+        clipboard: std::cell::RefCell<arboard::Clipboard>,
+    }
+
+    #[cfg(feature = "native-clipboard")]
+    impl NativeProvider {
+        pub fn new() -> Result<Self> {
+            Ok(Self {
+                clipboard: std::cell::RefCell::new(arboard::Clipboard::new()?),
+            })
+        }
+    }
+
+    #[cfg(feature = "native-clipboard")]
+    impl std::fmt::Debug for NativeProvider {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("NativeProvider").finish()
+        }
+    }
+
+    #[cfg(feature = "native-clipboard")]
+    impl ClipboardProvider for NativeProvider {
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: "native".to_string(),
+                kind: ClipboardKind::Native,
+                supports_primary: self.supports_primary(),
+                binaries: Vec::new(),
+            }
+        }
+
+        fn supports_primary(&self) -> bool {
+            cfg!(all(unix, not(target_os = "macos")))
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            let mut clipboard = self.clipboard.borrow_mut();
+            match clipboard_type {
+                ClipboardType::Clipboard => Ok(clipboard.get_text()?),
+                ClipboardType::Selection => native_primary::get(&mut clipboard),
+                ClipboardType::Secondary => Ok(String::new()),
+            }
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            let clipboard = self.clipboard.get_mut();
+            match clipboard_type {
+                ClipboardType::Clipboard => Ok(clipboard.set_text(contents)?),
+                ClipboardType::Selection => native_primary::set(clipboard, contents),
+                ClipboardType::Secondary => Ok(()),
+            }
+        }
+    }
+
+    /// The primary selection only exists on X11 and Wayland.
+    #[cfg(all(feature = "native-clipboard", unix, not(target_os = "macos")))]
+    mod native_primary {
+        use anyhow::Result;
+        use arboard::{Clipboard, GetExtLinux, LinuxClipboardKind, SetExtLinux};
+
+        pub fn get(clipboard: &mut Clipboard) -> Result<String> {
+            let contents = clipboard
+                .get()
+                .clipboard(LinuxClipboardKind::Primary)
+                .text()?;
+            Ok(contents)
+        }
+
+        pub fn set(clipboard: &mut Clipboard, contents: String) -> Result<()> {
+            clipboard
+                .set()
+                .clipboard(LinuxClipboardKind::Primary)
+                .text(contents)?;
+            Ok(())
+        }
+    }
+
+    #[cfg(all(feature = "native-clipboard", not(all(unix, not(target_os = "macos")))))]
+    mod native_primary {
+        use anyhow::Result;
+        use arboard::Clipboard;
+
+        pub fn get(_clipboard: &mut Clipboard) -> Result<String> {
+            Ok(String::new())
+        }
+
+        pub fn set(_clipboard: &mut Clipboard, _contents: String) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Talks to the Wayland compositor through the `wlr-data-control` protocol. Copied contents
+    /// are served from a thread of this process instead of a lingering `wl-copy` process.
+    #[cfg(all(feature = "wayland-clipboard", unix, not(target_os = "macos")))]
+    #[derive(Debug)]
+    pub struct WaylandProvider {
+        supports_primary: bool,
+    }
+
+    #[cfg(all(feature = "wayland-clipboard", unix, not(target_os = "macos")))]
+    impl WaylandProvider {
+        /// Checks that the compositor offers `wlr-data-control`, failing if it doesn't.
+        pub fn connect() -> Result<Self> {
+            use wl_clipboard_rs::utils::{self, PrimarySelectionCheckError};
+
+            match utils::is_primary_selection_supported() {
+                Ok(supports_primary) => Ok(Self { supports_primary }),
+                Err(PrimarySelectionCheckError::MissingProtocol { name, .. }) => Err(
+                    ClipboardError::NotFound(format!("the compositor doesn't support {}", name))
+                        .into(),
+                ),
+                Err(err) => Err(err.into()),
+            }
+        }
+    }
+
+    #[cfg(all(feature = "wayland-clipboard", unix, not(target_os = "macos")))]
+    impl ClipboardProvider for WaylandProvider {
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: "wayland".to_string(),
+                kind: ClipboardKind::Native,
+                supports_primary: self.supports_primary(),
+                binaries: Vec::new(),
+            }
+        }
+
+        fn supports_primary(&self) -> bool {
+            self.supports_primary
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            use std::io::Read;
+            use wl_clipboard_rs::paste;
+
+            let clipboard = match clipboard_type {
+                ClipboardType::Clipboard => paste::ClipboardType::Regular,
+                ClipboardType::Selection => paste::ClipboardType::Primary,
+                ClipboardType::Secondary => return Ok(String::new()),
+            };
+            match paste::get_contents(clipboard, paste::Seat::Unspecified, paste::MimeType::Text) {
+                Ok((mut pipe, _)) => {
+                    let mut contents = String::new();
+                    pipe.read_to_string(&mut contents)?;
+                    Ok(contents)
+                }
+                Err(paste::Error::ClipboardEmpty) | Err(paste::Error::NoMimeType) => {
+                    Ok(String::new())
+                }
+                Err(err) => Err(err.into()),
+            }
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            use wl_clipboard_rs::copy;
+
+            let clipboard = match clipboard_type {
+                ClipboardType::Clipboard => copy::ClipboardType::Regular,
+                ClipboardType::Selection => copy::ClipboardType::Primary,
+                ClipboardType::Secondary => return Ok(()),
+            };
+            // The prepared copy can't leave the thread that created it, so it is set up on the
+            // serving thread, which reports back whether that worked. Serving ends once another
+            // client takes over the selection.
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let mut options = copy::Options::new();
+                options.clipboard(clipboard).foreground(true);
+                let source = copy::Source::Bytes(contents.into_bytes().into_boxed_slice());
+                match options.prepare_copy(source, copy::MimeType::Text) {
+                    Ok(prepared) => {
+                        let _ = tx.send(Ok(()));
+                        if let Err(err) = prepared.serve() {
+                            log::error!("Failed to serve the Wayland clipboard: {}", err);
+                        }
+                    }
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                    }
+                }
+            });
+            rx.recv()??;
+            Ok(())
+        }
+    }
+
+    /// Talks to KDE's clipboard manager Klipper over D-Bus, so that yanks show up in its
+    /// history. Klipper only manages the clipboard, the primary selection goes through
+    /// `primary` if there is one and is ignored otherwise.
+    #[cfg(all(feature = "klipper-clipboard", unix, not(target_os = "macos")))]
+    #[derive(Debug)]
+    pub struct KlipperProvider {
+        connection: zbus::blocking::Connection,
+        primary: Option<Box<dyn ClipboardProvider>>,
+    }
+
+    #[cfg(all(feature = "klipper-clipboard", unix, not(target_os = "macos")))]
+    impl KlipperProvider {
+        const SERVICE: &'static str = "org.kde.klipper";
+
+        /// Connects to the session bus, failing if Klipper is not running.
+        pub fn connect(primary: Option<Box<dyn ClipboardProvider>>) -> Result<Self> {
+            let connection = zbus::blocking::Connection::session()?;
+            let running: bool = connection
+                .call_method(
+                    Some("org.freedesktop.DBus"),
+                    "/org/freedesktop/DBus",
+                    Some("org.freedesktop.DBus"),
+                    "NameHasOwner",
+                    &(Self::SERVICE),
+                )?
+                .body()?;
+            if !running {
+                return Err(
+                    ClipboardError::NotFound(format!("{} is not running", Self::SERVICE)).into(),
+                );
+            }
+            Ok(Self {
+                connection,
+                primary,
+            })
+        }
+
+        fn call<B, R>(&self, method: &str, body: &B) -> Result<R>
+        where
+            B: serde::Serialize + zbus::zvariant::DynamicType,
+            R: serde::de::DeserializeOwned + zbus::zvariant::Type,
+        {
+            let reply = self.connection.call_method(
+                Some(Self::SERVICE),
+                "/klipper",
+                Some("org.kde.klipper.klipper"),
+                method,
+                body,
+            )?;
+            Ok(reply.body()?)
+        }
+    }
+
+    #[cfg(all(feature = "klipper-clipboard", unix, not(target_os = "macos")))]
+    impl ClipboardProvider for KlipperProvider {
+        /// Includes the programs the provider of the primary selection runs.
+        fn info(&self) -> ClipboardInfo {
+            let primary = self.primary.as_ref().map(|primary| primary.info());
+            ClipboardInfo {
+                name: match &primary {
+                    Some(primary) => format!("klipper+{}", primary.name),
+                    None => "klipper".to_string(),
+                },
+                kind: ClipboardKind::Native,
+                supports_primary: self.supports_primary(),
+                binaries: primary.map(|primary| primary.binaries).unwrap_or_default(),
+            }
+        }
+
+        fn supports_primary(&self) -> bool {
+            self.primary.is_some()
+        }
+
+        /// The provider of the primary selection handles the secondary selection as well.
+        fn supports_secondary(&self) -> bool {
+            matches!(&self.primary, Some(primary) if primary.supports_secondary())
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            match (clipboard_type, &self.primary) {
+                (ClipboardType::Clipboard, _) => self.call("getClipboardContents", &()),
+                (_, Some(primary)) => primary.get_contents(clipboard_type),
+                (_, None) => Ok(String::new()),
+            }
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            match (clipboard_type, &mut self.primary) {
+                (ClipboardType::Clipboard, _) => self.call("setClipboardContents", &(contents)),
+                (_, Some(primary)) => primary.set_contents(contents, clipboard_type),
+                (_, None) => Ok(()),
+            }
+        }
+    }
+
+    /// A program and its arguments. The arguments are passed verbatim, without going through a
+    /// shell, so shell syntax like pipes needs an explicit `sh -c`.
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+    #[serde(try_from = "RawCommandConfig")]
+    pub struct CommandConfig {
+        pub prg: String,
+        pub args: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct RawCommandConfig {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    }
+
+    impl TryFrom<RawCommandConfig> for CommandConfig {
+        type Error = String;
+
+        fn try_from(raw: RawCommandConfig) -> Result<Self, Self::Error> {
+            let config = Self {
+                prg: raw.command,
+                args: raw.args,
+            };
+            config.validate()?;
+            Ok(config)
+        }
+    }
+
+    /// Programs that interpret their arguments as shell code.
+    const SHELLS: &[&str] = &[
+        "sh",
+        "bash",
+        "dash",
+        "zsh",
+        "ksh",
+        "fish",
+        "nu",
+        "cmd",
+        "powershell",
+        "pwsh",
+    ];
+
+    /// Shell operators that are meaningless when passed to a program verbatim.
+    const SHELL_OPERATORS: &[&str] = &["|", "||", "&&", ";", "<", ">", ">>", "2>", "2>&1", "&>"];
+
+    impl CommandConfig {
+        pub fn new(prg: impl Into<String>, args: Vec<String>) -> Self {
+            Self {
+                prg: prg.into(),
+                args,
+            }
+        }
+
+        /// Rejects arguments that look like they were meant for a shell, like `"-o | tr"`, unless
+        /// the program is a shell.
+        fn validate(&self) -> Result<(), String> {
+            let program = std::path::Path::new(&self.prg)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default();
+            if SHELLS.contains(&program) {
+                return Ok(());
+            }
+            let shell_arg = self.args.iter().find(|arg| {
+                arg.split_whitespace()
+                    .any(|word| SHELL_OPERATORS.contains(&word))
+            });
+            match shell_arg {
+                Some(arg) => Err(format!(
+                    "clipboard command argument {:?} looks like shell syntax, but arguments are \
+                     passed to {} verbatim. Use `command = \"sh\"` with `args = [\"-c\", \"...\"]` \
+                     to run it through a shell",
+                    arg, self.prg
+                )),
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// Whether `err` may go away when the command is run again: an I/O error other than the
+    /// command not being found or not being executable, or, when `writing`, the command
+    /// exiting with an error, like `wl-copy` does right after the compositor restarted. Paste
+    /// commands also exit with an error when the clipboard is empty or holds no text, which
+    /// retrying doesn't change. Timeouts aren't retried either.
+    fn is_retryable(err: &anyhow::Error, writing: bool) -> bool {
+        match err.downcast_ref() {
+            Some(ClipboardError::Io(err)) => !matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            ),
+            Some(ClipboardError::ProcessFailed { .. }) => writing,
+            _ => false,
+        }
+    }
+
+    /// Time to wait before the first retry of a failed command, doubled for each further retry.
+    const RETRY_BACKOFF: Duration = Duration::from_millis(20);
+
+    /// How [`CommandConfig::execute`] runs a command.
+    #[derive(Debug, Clone, Copy)]
+    pub(super) struct ExecuteOptions {
+        /// Returns the output, killing the command once it grows beyond this many bytes.
+        pub max_output: Option<usize>,
+        /// Reports what the command printed to stderr if it fails.
+        pub capture_stderr: bool,
+        pub timeout: Duration,
+        /// How many more times to run the command after a transient failure, see
+        /// [`is_retryable`].
+        pub retries: usize,
+    }
+
+    /// Reads what an exited command wrote to `stderr`, without waiting for more. Commands that
+    /// fork into the background, like `wl-copy`, keep stderr open after they exit, so reading
+    /// until the end would block for as long as the background process lives.
+    #[cfg(unix)]
+    fn read_exited_stderr(mut stderr: std::process::ChildStderr) -> String {
+        use std::io::Read;
+        use std::os::unix::io::AsRawFd;
+
+        let fd = stderr.as_raw_fd();
+        // Safety: `fcntl` only changes the flags of the file descriptor, which stays open.
+        unsafe {
+            libc::fcntl(
+                fd,
+                libc::F_SETFL,
+                libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+            )
+        };
+        let mut buf = Vec::new();
+        // stops at the end, or with `WouldBlock` once the pipe is empty
+        let _ = stderr.read_to_end(&mut buf);
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
+    /// Reads what an exited command wrote to `stderr` on another thread, waiting briefly for it
+    /// in case the command left a background process holding stderr open.
+    #[cfg(not(unix))]
+    fn read_exited_stderr(mut stderr: std::process::ChildStderr) -> String {
+        use std::io::Read;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = stderr.read_to_string(&mut buf);
+            let _ = tx.send(buf);
+        });
+        rx.recv_timeout(Duration::from_millis(100))
+            .unwrap_or_default()
+    }
+
+    impl CommandConfig {
+        /// Runs the command, feeding it `input`. Its output is returned if `max_output` is set,
+        /// and the command is killed once the output grows beyond `max_output` bytes.
+        ///
+        /// A command that fails transiently is run up to `retries` more times, to paper over
+        /// races like the compositor restarting.
+        pub(super) fn execute(
+            &self,
+            input: Option<String>,
+            options: ExecuteOptions,
+        ) -> Result<Option<String>> {
+            let output = self.execute_bytes(input.map(String::into_bytes), options)?;
+            output
+                .map(|stdout| {
+                    String::from_utf8(stdout)
+                        .map_err(|_| ClipboardError::NonText { mime_type: None }.into())
+                })
+                .transpose()
+        }
+
+        /// Like [`CommandConfig::execute`], but passes the input and output through as bytes.
+        pub(super) fn execute_bytes(
+            &self,
+            input: Option<Vec<u8>>,
+            options: ExecuteOptions,
+        ) -> Result<Option<Vec<u8>>> {
+            let writing = input.is_some();
+            let mut backoff = RETRY_BACKOFF;
+            for attempt in 1..=options.retries {
+                match self.execute_once(input.clone(), options) {
+                    Err(err) if is_retryable(&err, writing) => {
+                        log::debug!(
+                            "{}, retrying ({}/{}) in {:?}",
+                            err,
+                            attempt,
+                            options.retries,
+                            backoff
+                        );
+                        std::thread::sleep(backoff);
+                        backoff *= 2;
+                    }
+                    result => return result,
+                }
+            }
+            self.execute_once(input, options)
+        }
+
+        fn execute_once(
+            &self,
+            input: Option<Vec<u8>>,
+            options: ExecuteOptions,
+        ) -> Result<Option<Vec<u8>>> {
+            let ExecuteOptions {
+                max_output,
+                capture_stderr,
+                timeout,
+                ..
+            } = options;
+            use std::io::{Read, Write};
+            use std::process::{Command, Stdio};
+            use std::sync::atomic::{AtomicBool, Ordering};
+            use std::sync::Arc;
+            use std::thread;
+
+            let pipe_output = max_output.is_some();
+
+            let stdin = input
+                .as_ref()
+                .map(|_| Stdio::piped())
+                .unwrap_or_else(Stdio::null);
+            let stdout = pipe_output.then(Stdio::piped).unwrap_or_else(Stdio::null);
+            let stderr = capture_stderr
+                .then(Stdio::piped)
+                .unwrap_or_else(Stdio::null);
+
+            let mut child = Command::new(&self.prg)
+                .args(&self.args)
+                .stdin(stdin)
+                .stdout(stdout)
+                .stderr(stderr)
+                .spawn()
+                .map_err(|err| {
+                    let message = format!("couldn't run clipboard provider {}: {}", self.prg, err);
+                    anyhow::Error::new(ClipboardError::Io(err)).context(message)
+                })?;
+
+            // Feed stdin and drain stdout on separate threads so that a command that never reads
+            // or never exits can't block us past the timeout. Doing both concurrently also keeps
+            // a command that writes while reading, like `cat`, from filling the stdout pipe and
+            // deadlocking on large inputs.
+            let writer = match input {
+                Some(input) => {
+                    let mut stdin = child.stdin.take().context("stdin is missing")?;
+                    Some(thread::spawn(move || stdin.write_all(&input)))
+                }
+                None => None,
+            };
+            let output_exceeded = Arc::new(AtomicBool::new(false));
+            let reader = if let Some(max_output) = max_output {
+                let stdout = child.stdout.take().context("stdout is missing")?;
+                let output_exceeded = output_exceeded.clone();
+                Some(thread::spawn(move || {
+                    // Reading one byte more than allowed tells apart output that just fits.
+                    let mut buf = Vec::new();
+                    let limit = (max_output as u64).saturating_add(1);
+                    stdout.take(limit).read_to_end(&mut buf)?;
+                    if buf.len() > max_output {
+                        output_exceeded.store(true, Ordering::Relaxed);
+                    }
+                    Ok::<_, std::io::Error>(buf)
+                }))
+            } else {
+                None
+            };
+            let exceeded_error = || {
+                anyhow!(
+                    "clipboard provider {} output exceeds {} bytes",
+                    self.prg,
+                    max_output.unwrap_or_default()
+                )
+            };
+            let stderr = if capture_stderr {
+                Some(child.stderr.take().context("stderr is missing")?)
+            } else {
+                None
+            };
+
+            let deadline = Instant::now() + timeout;
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if output_exceeded.load(Ordering::Relaxed) {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(exceeded_error());
+                }
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(ClipboardError::Timeout(format!(
+                        "clipboard provider {} timed out",
+                        self.prg
+                    ))
+                    .into());
+                }
+                thread::sleep(Duration::from_millis(5));
+            };
+            // The command may have failed because we stopped reading its output.
+            if output_exceeded.load(Ordering::Relaxed) {
+                return Err(exceeded_error());
+            }
+
+            if !status.success() {
+                let stderr = stderr.map(read_exited_stderr).unwrap_or_default();
+                let message: Vec<_> = stderr
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .take(2)
+                    .collect();
+                return Err(ClipboardError::ProcessFailed {
+                    program: self.prg.clone(),
+                    stderr: message.join(" "),
+                }
+                .into());
+            }
+
+            if let Some(writer) = writer {
+                writer
+                    .join()
+                    .map_err(|_| anyhow!("stdin writer panicked"))?
+                    .context("couldn't write in stdin")?;
+            }
+
+            match reader {
+                Some(reader) => {
+                    let stdout = reader
+                        .join()
+                        .map_err(|_| anyhow!("stdout reader panicked"))?
+                        .context("couldn't read from stdout")?;
+                    if output_exceeded.load(Ordering::Relaxed) {
+                        return Err(exceeded_error());
+                    }
+                    Ok(Some(stdout))
+                }
+                None => Ok(None),
+            }
+        }
+    }
+
+    /// How long [`WriteCache`] remembers written contents.
+    const WRITE_CACHE_TTL: Duration = Duration::from_secs(5);
+
+    /// Remembers the contents a [`CommandProvider`] recently wrote to each clipboard type, so
+    /// that writing them again can be skipped. Reading the clipboard forgets them, since the
+    /// clipboard may have been changed by another program in the meantime.
+    #[derive(Debug, Clone, Default)]
+    pub struct WriteCache {
+        enabled: bool,
+        written: RefCell<[Option<(String, Instant)>; 3]>,
+    }
+
+    impl WriteCache {
+        pub fn new(enabled: bool) -> Self {
+            Self {
+                enabled,
+                written: Default::default(),
+            }
+        }
+
+        fn index(clipboard_type: ClipboardType) -> usize {
+            match clipboard_type {
+                ClipboardType::Clipboard => 0,
+                ClipboardType::Selection => 1,
+                ClipboardType::Secondary => 2,
+            }
+        }
+
+        /// Whether `contents` were recently written to `clipboard_type`.
+        fn contains(&self, contents: &str, clipboard_type: ClipboardType) -> bool {
+            matches!(
+                &self.written.borrow()[Self::index(clipboard_type)],
+                Some((written, at)) if written == contents && at.elapsed() < WRITE_CACHE_TTL
+            )
+        }
+
+        /// The contents recently written to `clipboard_type` and when, if any.
+        fn written(&self, clipboard_type: ClipboardType) -> Option<(String, Instant)> {
+            self.written.borrow()[Self::index(clipboard_type)]
+                .clone()
+                .filter(|(_, at)| at.elapsed() < WRITE_CACHE_TTL)
+        }
+
+        /// Remembers an entry returned by [`WriteCache::written`] again.
+        fn restore(&self, written: (String, Instant), clipboard_type: ClipboardType) {
+            self.written.borrow_mut()[Self::index(clipboard_type)] = Some(written);
+        }
+
+        fn set(&self, contents: Option<String>, clipboard_type: ClipboardType) {
+            if self.enabled {
+                self.written.borrow_mut()[Self::index(clipboard_type)] =
+                    contents.map(|contents| (contents, Instant::now()));
+            }
+        }
+
+        fn clear(&self) {
+            *self.written.borrow_mut() = Default::default();
+        }
+    }
+
+    /// Remembers how many trailing newlines the contents a [`CommandProvider`] last wrote to
+    /// each clipboard type ended with, by a hash of the contents without them. Copies of the
+    /// provider that write in the background share it.
+    #[derive(Debug, Clone, Default)]
+    pub struct NewlineMemory {
+        written: Arc<Mutex<[Option<WrittenNewlines>; 3]>>,
+    }
+
+    /// Hash of written contents without their trailing newlines, and the number of them.
+    type WrittenNewlines = (u64, usize);
+
+    impl NewlineMemory {
+        fn split(contents: &str) -> WrittenNewlines {
+            use std::hash::{Hash, Hasher};
+
+            let trimmed = contents.trim_end_matches('\n');
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            trimmed.hash(&mut hasher);
+            (hasher.finish(), contents.len() - trimmed.len())
+        }
+
+        fn set(&self, contents: &str, clipboard_type: ClipboardType) {
+            self.written.lock().unwrap()[WriteCache::index(clipboard_type)] =
+                Some(Self::split(contents));
+        }
+
+        /// Gives `contents` the trailing newlines they were written with, if they were written
+        /// last.
+        fn restore(&self, mut contents: String, clipboard_type: ClipboardType) -> String {
+            let written = self.written.lock().unwrap()[WriteCache::index(clipboard_type)];
+            let (hash, newlines) = Self::split(&contents);
+            match written {
+                Some((written_hash, written_newlines))
+                    if written_hash == hash && written_newlines != newlines =>
+                {
+                    contents.truncate(contents.len() - newlines);
+                    contents.extend(std::iter::repeat_n('\n', written_newlines));
+                    contents
+                }
+                _ => contents,
+            }
+        }
+    }
+
+    /// Remembers whether the contents last copied to each clipboard type were yanked linewise,
+    /// in a directory shared by all running editors. The clipboard itself only holds the plain
+    /// text, so other programs aren't affected. Contents are recognized by a hash that ignores
+    /// their trailing line endings, since clipboard tools may add or strip them.
+    ///
+    /// Each clipboard type has its own file, which is replaced as a whole, so editors marking
+    /// at the same time never see a partially written mark.
+    #[derive(Debug, Clone)]
+    pub struct LinewiseMarker {
+        dir: PathBuf,
+    }
+
+    impl LinewiseMarker {
+        pub fn new(dir: PathBuf) -> Self {
+            Self { dir }
+        }
+
+        /// The marker in the cache directory, if enabled with `linewise-marker`.
+        pub fn from_config(config: &super::ClipboardConfig) -> Option<Self> {
+            if config.linewise_marker {
+                Some(Self::new(
+                    helix_core::cache_dir().join("clipboard-linewise"),
+                ))
+            } else {
+                None
+            }
+        }
+
+        /// 64-bit FNV-1a, which unlike the std hashers is the same in every build and process.
+        fn hash(contents: &str) -> u64 {
+            contents
+                .trim_end_matches(['\r', '\n'])
+                .bytes()
+                .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                    (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+                })
+        }
+
+        fn path(&self, clipboard_type: ClipboardType) -> PathBuf {
+            self.dir.join(match clipboard_type {
+                ClipboardType::Clipboard => "clipboard",
+                ClipboardType::Selection => "primary",
+                ClipboardType::Secondary => "secondary",
+            })
+        }
+
+        /// Marks `contents` as copied to `clipboard_type`, linewise if they end with a line
+        /// ending.
+        pub fn set(&self, contents: &str, clipboard_type: ClipboardType) -> Result<()> {
+            let linewise = contents.ends_with('\n') || contents.ends_with('\r');
+            let mark = format!("{} {}\n", Self::hash(contents), u8::from(linewise));
+            let path = self.path(clipboard_type);
+            let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+            std::fs::create_dir_all(&self.dir)
+                .and_then(|()| std::fs::write(&tmp, mark))
+                .and_then(|()| std::fs::rename(&tmp, &path))
+                .with_context(|| format!("couldn't write {}", path.display()))
+        }
+
+        /// Whether `contents` read from `clipboard_type` were marked as yanked linewise, `None`
+        /// if they weren't copied by Helix.
+        pub fn get(&self, contents: &str, clipboard_type: ClipboardType) -> Option<bool> {
+            let mark = std::fs::read_to_string(self.path(clipboard_type)).ok()?;
+            let (hash, linewise) = mark.trim_end().split_once(' ')?;
+            let hash: u64 = hash.parse().ok()?;
+            (hash == Self::hash(contents)).then_some(linewise == "1")
+        }
+
+        /// Gives `contents` read from `clipboard_type` a trailing `line_ending` if they were
+        /// yanked linewise, or strips it if they weren't, so that they paste the way they were
+        /// yanked. Contents that weren't copied by Helix are returned as they are.
+        pub fn restore(
+            &self,
+            contents: String,
+            clipboard_type: ClipboardType,
+            line_ending: &str,
+        ) -> String {
+            let linewise = match self.get(&contents, clipboard_type) {
+                Some(linewise) => linewise,
+                None => return contents,
+            };
+            let trimmed = contents.trim_end_matches(['\r', '\n']);
+            match (linewise, trimmed.len() == contents.len()) {
+                (true, true) => contents + line_ending,
+                (false, false) => trimmed.to_string(),
+                _ => contents,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct CommandProvider {
+        pub get_cmd: CommandConfig,
+        pub set_cmd: CommandConfig,
+        pub get_primary_cmd: Option<CommandConfig>,
+        pub set_primary_cmd: Option<CommandConfig>,
+        pub get_secondary_cmd: Option<CommandConfig>,
+        pub set_secondary_cmd: Option<CommandConfig>,
+        /// Lists the history of a clipboard manager, see [`ClipboardConfig::history_list`].
+        ///
+        /// [`ClipboardConfig::history_list`]: super::ClipboardConfig::history_list
+        pub history_list_cmd: Option<CommandConfig>,
+        /// Decodes a history entry, see [`ClipboardConfig::history_decode`].
+        ///
+        /// [`ClipboardConfig::history_decode`]: super::ClipboardConfig::history_decode
+        pub history_decode_cmd: Option<CommandConfig>,
+        /// How long a command may run before it is killed.
+        pub timeout: Duration,
+        /// Converts CRLF line endings to LF when pasting, for Windows clipboards.
+        pub normalize_crlf: bool,
+        /// Whether to include the command's error output in failures.
+        pub capture_stderr: bool,
+        /// Use `get_cmd` and `set_cmd` for the primary selection if there are no primary
+        /// selection commands.
+        pub primary_fallback: bool,
+        /// Largest output in bytes read from a paste command.
+        pub max_size: usize,
+        /// How often a failing command is run again.
+        pub retries: usize,
+        /// Contents written last, to skip writing them again.
+        pub write_cache: WriteCache,
+        /// Encoding of the text the commands read and write.
+        pub encoding: &'static Encoding,
+        /// How a trailing newline of pasted contents is treated.
+        pub trailing_newline: TrailingNewline,
+        /// Trailing newlines of the contents written last, for [`TrailingNewline::Preserve`].
+        pub newline_memory: NewlineMemory,
+    }
+
+    /// A call made to a [`MockProvider`].
+    #[cfg(any(test, feature = "test-util"))]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum MockCall {
+        Get(ClipboardType),
+        Set(String, ClipboardType),
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    #[derive(Debug, Default)]
+    struct MockState {
+        /// Answers for the next reads, before falling back to `contents`.
+        responses: VecDeque<Result<String, ClipboardError>>,
+        /// Errors for the next writes.
+        set_errors: VecDeque<ClipboardError>,
+        contents: [Option<String>; 3],
+        calls: Vec<MockCall>,
+    }
+
+    /// Clipboard provider for tests that records all reads and writes and answers reads with
+    /// programmed responses, including errors. Without a programmed response a read returns
+    /// what was last written, like a real clipboard. Clones share their state, so a clone can
+    /// be inspected after handing the provider to the editor.
+    #[cfg(any(test, feature = "test-util"))]
+    #[derive(Debug, Clone, Default)]
+    pub struct MockProvider {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    impl MockProvider {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Answers the next read that has no earlier response with `response`.
+        pub fn respond(&self, response: Result<String, ClipboardError>) {
+            self.state.lock().unwrap().responses.push_back(response);
+        }
+
+        /// Fails the next write that has no earlier error with `err`, leaving the contents as
+        /// they are.
+        pub fn fail_set(&self, err: ClipboardError) {
+            self.state.lock().unwrap().set_errors.push_back(err);
+        }
+
+        /// Returns the calls made so far, oldest first.
+        pub fn calls(&self) -> Vec<MockCall> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        /// Forgets the calls made so far.
+        pub fn clear_calls(&self) {
+            self.state.lock().unwrap().calls.clear();
+        }
+
+        /// Returns the contents last written to `clipboard_type`, if any.
+        pub fn contents(&self, clipboard_type: ClipboardType) -> Option<String> {
+            self.state.lock().unwrap().contents[WriteCache::index(clipboard_type)].clone()
+        }
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    impl ClipboardProvider for MockProvider {
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: "mock".to_string(),
+                kind: ClipboardKind::None,
+                supports_primary: self.supports_primary(),
+                binaries: Vec::new(),
+            }
+        }
+
+        fn supports_primary(&self) -> bool {
+            true
+        }
+
+        fn supports_secondary(&self) -> bool {
+            true
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::Get(clipboard_type));
+            match state.responses.pop_front() {
+                Some(response) => Ok(response?),
+                None => Ok(state.contents[WriteCache::index(clipboard_type)]
+                    .clone()
+                    .unwrap_or_default()),
+            }
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            let mut state = self.state.lock().unwrap();
+            state
+                .calls
+                .push(MockCall::Set(contents.clone(), clipboard_type));
+            if let Some(err) = state.set_errors.pop_front() {
+                return Err(err.into());
+            }
+            state.contents[WriteCache::index(clipboard_type)] = Some(contents);
+            Ok(())
+        }
+    }
+
+    /// Keeps the clipboard in a file, for tests and headless machines. The primary selection
+    /// goes to a second file with `.primary` appended to the name. Missing files read as empty.
+    #[derive(Debug)]
+    pub struct FileProvider {
+        path: PathBuf,
+        primary_path: PathBuf,
+    }
+
+    impl FileProvider {
+        pub fn new(path: PathBuf) -> Self {
+            let mut primary_path = path.clone().into_os_string();
+            primary_path.push(".primary");
+            Self {
+                path,
+                primary_path: primary_path.into(),
+            }
+        }
+
+        /// File of `clipboard_type`, `None` for the unsupported secondary selection.
+        fn path(&self, clipboard_type: ClipboardType) -> Option<&Path> {
+            match clipboard_type {
+                ClipboardType::Clipboard => Some(&self.path),
+                ClipboardType::Selection => Some(&self.primary_path),
+                ClipboardType::Secondary => None,
+            }
+        }
+    }
+
+    impl ClipboardProvider for FileProvider {
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: format!("file ({})", self.path.display()),
+                kind: ClipboardKind::File,
+                supports_primary: self.supports_primary(),
+                binaries: Vec::new(),
+            }
+        }
+
+        fn supports_primary(&self) -> bool {
+            true
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            let path = match self.path(clipboard_type) {
+                Some(path) => path,
+                None => return Ok(String::new()),
+            };
+            match std::fs::read_to_string(path) {
+                Ok(contents) => Ok(contents),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+                Err(err) => {
+                    Err(anyhow::Error::new(err)
+                        .context(format!("couldn't read {}", path.display())))
+                }
+            }
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            let path = match self.path(clipboard_type) {
+                Some(path) => path,
+                None => return Ok(()),
+            };
+            std::fs::write(path, contents)
+                .with_context(|| format!("couldn't write {}", path.display()))
+        }
+    }
+
+    /// Keeps the clipboard in a named tmux buffer, so it doesn't clash with buffers created by
+    /// hand. Falls back to the most recent unnamed buffer if the named one can't be used.
+    #[derive(Debug, Clone)]
+    pub struct TmuxProvider {
+        pub buffer: String,
+        /// How long a command may run before it is killed.
+        pub timeout: Duration,
+        /// Largest buffer in bytes that is read.
+        pub max_size: usize,
+        /// How often a failing command is run again.
+        pub retries: usize,
+        /// How long to wait for the outer terminal's clipboard after `refresh-client -l`, if
+        /// it should be requested before reading.
+        pub refresh_timeout: Option<Duration>,
+    }
+
+    /// How often the tmux provider checks for the buffer requested with `refresh-client -l`.
+    const TMUX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    impl TmuxProvider {
+        fn tmux(args: &[&str]) -> CommandConfig {
+            CommandConfig {
+                prg: "tmux".to_string(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+            }
+        }
+
+        /// Returns the name of the most recent buffer, if there is any.
+        fn newest_buffer(&self) -> Result<Option<String>> {
+            let names = Self::tmux(&["list-buffers", "-F", "#{buffer_name}"])
+                .execute(
+                    None,
+                    ExecuteOptions {
+                        max_output: Some(self.max_size),
+                        capture_stderr: true,
+                        timeout: self.timeout,
+                        retries: 0,
+                    },
+                )?
+                .context("output is missing")?;
+            Ok(names.lines().next().map(str::to_owned))
+        }
+
+        /// Asks tmux to load the outer terminal's clipboard into a new buffer and reads it once
+        /// it arrived. Returns `None` if the terminal doesn't answer within `timeout`.
+        fn refresh(&self, timeout: Duration) -> Result<Option<String>> {
+            let before = self.newest_buffer()?;
+            Self::tmux(&["refresh-client", "-l"]).execute(
+                None,
+                ExecuteOptions {
+                    max_output: None,
+                    capture_stderr: true,
+                    timeout: self.timeout,
+                    retries: 0,
+                },
+            )?;
+            let buffer = match poll_new_buffer(before.as_deref(), timeout, || self.newest_buffer())?
+            {
+                Some(buffer) => buffer,
+                None => return Ok(None),
+            };
+            Self::tmux(&["show-buffer", "-b", &buffer]).execute(
+                None,
+                ExecuteOptions {
+                    max_output: Some(self.max_size),
+                    capture_stderr: true,
+                    timeout: self.timeout,
+                    retries: self.retries,
+                },
+            )
+        }
+    }
+
+    /// Calls `newest` until it returns a buffer other than `before` or `timeout` passes. Returns
+    /// the new buffer, if one showed up.
+    pub(super) fn poll_new_buffer(
+        before: Option<&str>,
+        timeout: Duration,
+        mut newest: impl FnMut() -> Result<Option<String>>,
+    ) -> Result<Option<String>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(buffer) = newest()? {
+                if Some(buffer.as_str()) != before {
+                    return Ok(Some(buffer));
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(TMUX_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    impl ClipboardProvider for TmuxProvider {
+        fn info(&self) -> ClipboardInfo {
+            ClipboardInfo {
+                name: "tmux".to_string(),
+                kind: ClipboardKind::Command,
+                supports_primary: self.supports_primary(),
+                binaries: vec!["tmux".to_string()],
+            }
+        }
+
+        fn contents_reader(&self, clipboard_type: ClipboardType) -> Option<super::ContentsReader> {
+            let provider = self.clone();
+            Some(Box::new(move || provider.get_contents(clipboard_type)))
+        }
+
+        fn contents_writer(
+            &mut self,
+            clipboard_type: ClipboardType,
+        ) -> Option<super::ContentsWriter> {
+            let mut provider = self.clone();
+            Some(Box::new(move |contents| {
+                provider.set_contents(contents, clipboard_type)
+            }))
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            if clipboard_type != ClipboardType::Clipboard {
+                return Ok(String::new());
+            }
+
+            if let Some(timeout) = self.refresh_timeout {
+                match self.refresh(timeout) {
+                    Ok(Some(contents)) => return Ok(contents),
+                    Ok(None) => log::debug!("Terminal didn't send its clipboard to tmux"),
+                    Err(err) => log::debug!("Requesting the terminal clipboard failed: {}", err),
+                }
+            }
+
+            let contents = Self::tmux(&["show-buffer", "-b", &self.buffer])
+                .execute(
+                    None,
+                    ExecuteOptions {
+                        max_output: Some(self.max_size),
+                        capture_stderr: true,
+                        timeout: self.timeout,
+                        retries: self.retries,
+                    },
+                )
+                .or_else(|err| {
+                    log::debug!("Reading tmux buffer {} failed: {}", self.buffer, err);
+                    Self::tmux(&["save-buffer", "-"]).execute(
+                        None,
+                        ExecuteOptions {
+                            max_output: Some(self.max_size),
+                            capture_stderr: true,
+                            timeout: self.timeout,
+                            retries: self.retries,
+                        },
+                    )
+                })?;
+            contents.context("output is missing")
+        }
+
+        fn set_contents(&mut self, contents: String, clipboard_type: ClipboardType) -> Result<()> {
+            if clipboard_type != ClipboardType::Clipboard {
+                return Ok(());
+            }
+
+            Self::tmux(&["load-buffer", "-b", &self.buffer, "-"])
+                .execute(
+                    Some(contents.clone()),
+                    ExecuteOptions {
+                        max_output: None,
+                        capture_stderr: true,
+                        timeout: self.timeout,
+                        retries: self.retries,
+                    },
+                )
+                .or_else(|err| {
+                    log::debug!("Writing tmux buffer {} failed: {}", self.buffer, err);
+                    Self::tmux(&["load-buffer", "-"]).execute(
+                        Some(contents),
+                        ExecuteOptions {
+                            max_output: None,
+                            capture_stderr: true,
+                            timeout: self.timeout,
+                            retries: self.retries,
+                        },
+                    )
+                })
+                .map(|_| ())
+        }
+    }
+
+    impl CommandProvider {
+        /// Starts building a provider from owned program names and arguments, for providers that
+        /// are only known at runtime. See [`CommandProviderBuilder`].
+        pub fn builder() -> CommandProviderBuilder {
+            CommandProviderBuilder::default()
+        }
+
+        pub fn normalizing_crlf(mut self: Box<Self>) -> Box<Self> {
+            self.normalize_crlf = true;
+            self
+        }
+    }
+
+    /// Builds a [`CommandProvider`]. The paste and copy commands are required, everything else
+    /// defaults to the defaults of [`ClipboardConfig`](super::ClipboardConfig).
+    ///
+    /// ```
+    /// use helix_view::clipboard::CommandProvider;
+    ///
+    /// let provider = CommandProvider::builder()
+    ///     .paste("xsel", vec!["-o".to_owned(), "-b".to_owned()])
+    ///     .copy("xsel", vec!["-i".to_owned(), "-b".to_owned()])
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(provider.name(), "xsel");
+    /// ```
+    #[derive(Debug, Clone)]
+    pub struct CommandProviderBuilder {
+        paste: Option<CommandConfig>,
+        copy: Option<CommandConfig>,
+        primary_paste: Option<CommandConfig>,
+        primary_copy: Option<CommandConfig>,
+        secondary_paste: Option<CommandConfig>,
+        secondary_copy: Option<CommandConfig>,
+        history_list: Option<CommandConfig>,
+        history_decode: Option<CommandConfig>,
+        timeout: Duration,
+        normalize_crlf: bool,
+        capture_stderr: bool,
+        primary_fallback: bool,
+        max_size: usize,
+        retries: usize,
+        dedup_writes: bool,
+        encoding: &'static Encoding,
+        trailing_newline: TrailingNewline,
+    }
+
+    impl Default for CommandProviderBuilder {
+        fn default() -> Self {
+            Self::new().config(&super::ClipboardConfig::default())
+        }
+    }
+
+    impl CommandProviderBuilder {
+        fn new() -> Self {
+            Self {
+                paste: None,
+                copy: None,
+                primary_paste: None,
+                primary_copy: None,
+                secondary_paste: None,
+                secondary_copy: None,
+                history_list: None,
+                history_decode: None,
+                timeout: Duration::ZERO,
+                normalize_crlf: false,
+                capture_stderr: true,
+                primary_fallback: false,
+                max_size: 0,
+                retries: 0,
+                dedup_writes: false,
+                encoding: encoding_rs::UTF_8,
+                trailing_newline: TrailingNewline::Keep,
+            }
+        }
+
+        /// Takes the timeout, size limit, retries, write deduplication, trailing newline policy,
+        /// history commands and primary selection fallback from `config`.
+        pub fn config(mut self, config: &super::ClipboardConfig) -> Self {
+            self.timeout = config.timeout;
+            self.history_list = config.history_list.clone();
+            self.history_decode = config.history_decode.clone();
+            self.trailing_newline = config.trailing_newline;
+            self.primary_fallback = config.primary_fallback_to_clipboard;
+            self.max_size = config.max_size;
+            self.retries = config.retries;
+            self.dedup_writes = config.dedup_writes;
+            self
+        }
+
+        /// Command printing the clipboard.
+        pub fn paste(self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.paste_command(CommandConfig::new(prg, args))
+        }
+
+        /// Command reading the new clipboard contents from its input.
+        pub fn copy(self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.copy_command(CommandConfig::new(prg, args))
+        }
+
+        /// Command printing the primary selection. Without one the provider has no primary
+        /// selection.
+        pub fn primary_paste(self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.primary_paste_command(CommandConfig::new(prg, args))
+        }
+
+        /// Command reading the new primary selection from its input.
+        pub fn primary_copy(self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.primary_copy_command(CommandConfig::new(prg, args))
+        }
+
+        /// Command printing the X11 secondary selection. Without one the provider has no
+        /// secondary selection.
+        pub fn secondary_paste(mut self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.secondary_paste = Some(CommandConfig::new(prg, args));
+            self
+        }
+
+        /// Command reading the new secondary selection from its input.
+        pub fn secondary_copy(mut self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.secondary_copy = Some(CommandConfig::new(prg, args));
+            self
+        }
+
+        /// Command listing the history of a clipboard manager, one entry per line.
+        pub fn history_list(mut self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.history_list = Some(CommandConfig::new(prg, args));
+            self
+        }
+
+        /// Command reading a history entry from its input and printing its contents.
+        pub fn history_decode(mut self, prg: impl Into<String>, args: Vec<String>) -> Self {
+            self.history_decode = Some(CommandConfig::new(prg, args));
+            self
+        }
+
+        pub fn paste_command(mut self, cmd: CommandConfig) -> Self {
+            self.paste = Some(cmd);
+            self
+        }
+
+        pub fn copy_command(mut self, cmd: CommandConfig) -> Self {
+            self.copy = Some(cmd);
+            self
+        }
+
+        pub fn primary_paste_command(mut self, cmd: CommandConfig) -> Self {
+            self.primary_paste = Some(cmd);
+            self
+        }
+
+        pub fn primary_copy_command(mut self, cmd: CommandConfig) -> Self {
+            self.primary_copy = Some(cmd);
+            self
+        }
+
+        /// How long a command may run before it is killed.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// Converts CRLF line endings to LF when pasting.
+        pub fn normalize_crlf(mut self, normalize_crlf: bool) -> Self {
+            self.normalize_crlf = normalize_crlf;
+            self
+        }
+
+        /// Whether to include the error output of failing commands in errors. Defaults to true.
+        pub fn capture_stderr(mut self, capture_stderr: bool) -> Self {
+            self.capture_stderr = capture_stderr;
+            self
+        }
+
+        /// Encoding of the text the commands read and write. Defaults to UTF-8.
+        pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+            self.encoding = encoding;
+            self
+        }
+
+        /// How a trailing newline of pasted contents is treated.
+        pub fn trailing_newline(mut self, trailing_newline: TrailingNewline) -> Self {
+            self.trailing_newline = trailing_newline;
+            self
+        }
+
+        /// Builds the provider, failing if the paste or copy command is missing.
+        pub fn build(self) -> Result<Box<dyn ClipboardProvider>> {
+            Ok(Box::new(self.build_command_provider()?))
+        }
+
+        /// Like [`CommandProviderBuilder::build`], but keeps the concrete type.
+        pub fn build_command_provider(self) -> Result<CommandProvider> {
+            Ok(CommandProvider {
+                get_cmd: self
+                    .paste
+                    .context("clipboard provider has no paste command")?,
+                set_cmd: self
+                    .copy
+                    .context("clipboard provider has no copy command")?,
+                get_primary_cmd: self.primary_paste,
+                set_primary_cmd: self.primary_copy,
+                get_secondary_cmd: self.secondary_paste,
+                set_secondary_cmd: self.secondary_copy,
+                history_list_cmd: self.history_list,
+                history_decode_cmd: self.history_decode,
+                timeout: self.timeout,
+                normalize_crlf: self.normalize_crlf,
+                capture_stderr: self.capture_stderr,
+                primary_fallback: self.primary_fallback,
+                max_size: self.max_size,
+                retries: self.retries,
+                write_cache: WriteCache::new(self.dedup_writes),
+                encoding: self.encoding,
+                trailing_newline: self.trailing_newline,
+                newline_memory: NewlineMemory::default(),
+            })
+        }
+    }
+
+    /// Encodes `contents` for a command expecting `encoding`, failing if it can't represent
+    /// some of them.
+    pub(super) fn encode(contents: &str, encoding: &'static Encoding) -> Result<Vec<u8>> {
+        // encoding_rs only decodes UTF-16
+        if encoding == encoding_rs::UTF_16LE {
+            return Ok(contents.encode_utf16().flat_map(u16::to_le_bytes).collect());
+        }
+        if encoding == encoding_rs::UTF_16BE {
+            return Ok(contents.encode_utf16().flat_map(u16::to_be_bytes).collect());
+        }
+        let (bytes, _, had_errors) = encoding.encode(contents);
+        if had_errors {
+            bail!("clipboard contents can't be encoded as {}", encoding.name());
+        }
+        Ok(bytes.into_owned())
+    }
+
+    /// Decodes the output of a command producing `encoding`, skipping a byte order mark.
+    pub(super) fn decode(bytes: &[u8], encoding: &'static Encoding) -> Result<String> {
+        let (contents, had_errors) = encoding.decode_with_bom_removal(bytes);
+        if had_errors {
+            bail!("clipboard contents aren't valid {}", encoding.name());
+        }
+        Ok(contents.into_owned())
+    }
+
+    pub(super) fn normalize_crlf(contents: String) -> String {
+        if contents.contains("\r\n") {
+            contents.replace("\r\n", "\n")
+        } else {
+            contents
+        }
+    }
+
+    impl CommandProvider {
+        /// Asks the tool behind `paste_cmd` for the MIME type of the clipboard, for tools that
+        /// can list the types on offer. Returns the first type that isn't text.
+        fn mime_type(&self, paste_cmd: &CommandConfig) -> Option<String> {
+            let mut list_cmd = paste_cmd.clone();
+            match paste_cmd.prg.as_str() {
+                "wl-paste" => {
+                    list_cmd
+                        .args
+                        .retain(|arg| arg == "-p" || arg == "--primary");
+                    list_cmd.args.push("--list-types".to_owned());
+                }
+                "xclip" => list_cmd
+                    .args
+                    .extend(["-t".to_owned(), "TARGETS".to_owned()]),
+                _ => return None,
+            }
+            let types = list_cmd
+                .execute(
+                    None,
+                    ExecuteOptions {
+                        max_output: Some(self.max_size),
+                        capture_stderr: false,
+                        timeout: self.timeout,
+                        retries: 0,
+                    },
+                )
+                .ok()
+                .flatten()?;
+            first_non_text_type(&types).map(str::to_owned)
+        }
+    }
+
+    /// Picks the first MIME type that isn't text from a newline separated list. X11 selection
+    /// targets that aren't MIME types, like `TARGETS`, are skipped.
+    pub(super) fn first_non_text_type(types: &str) -> Option<&str> {
+        types
+            .lines()
+            .map(str::trim)
+            .find(|mime_type| mime_type.contains('/') && !mime_type.starts_with("text/"))
+    }
+
+    impl ClipboardProvider for CommandProvider {
+        /// Lists each program once, in the order paste, copy, primary paste, primary copy,
+        /// history list, history decode.
+        fn info(&self) -> ClipboardInfo {
+            let mut binaries: Vec<String> = Vec::new();
+            let cmds = [&self.get_cmd, &self.set_cmd]
+                .into_iter()
+                .chain(&self.get_primary_cmd)
+                .chain(&self.set_primary_cmd)
+                .chain(&self.history_list_cmd)
+                .chain(&self.history_decode_cmd);
+            for cmd in cmds {
+                if !binaries.contains(&cmd.prg) {
+                    binaries.push(cmd.prg.clone());
+                }
+            }
+            ClipboardInfo {
+                name: if self.get_cmd.prg != self.set_cmd.prg {
+                    format!("{}+{}", self.get_cmd.prg, self.set_cmd.prg)
+                } else {
+                    self.get_cmd.prg.clone()
+                },
+                kind: ClipboardKind::Command,
+                supports_primary: self.supports_primary(),
+                binaries,
+            }
+        }
+
+        fn contents_reader(&self, clipboard_type: ClipboardType) -> Option<super::ContentsReader> {
+            self.write_cache.clear();
+            let provider = self.clone();
+            Some(Box::new(move || provider.get_contents(clipboard_type)))
+        }
+
+        /// Writes with a copy of the provider, which doesn't skip writes since it can't tell
+        /// whether earlier background writes succeeded.
+        fn contents_writer(
+            &mut self,
+            clipboard_type: ClipboardType,
+        ) -> Option<super::ContentsWriter> {
+            self.write_cache.set(None, clipboard_type);
+            let mut provider = self.clone();
+            provider.write_cache = WriteCache::new(false);
+            Some(Box::new(move |contents| {
+                provider.set_contents(contents, clipboard_type)
+            }))
+        }
+
+        fn supports_primary(&self) -> bool {
+            self.get_primary_cmd.is_some()
+        }
+
+        fn supports_secondary(&self) -> bool {
+            self.get_secondary_cmd.is_some()
+        }
+
+        /// Lists the non-empty lines the history list command prints.
+        fn list_history(&self) -> Option<super::HistoryReader> {
+            let cmd = self.history_list_cmd.clone()?;
+            let provider = self.clone();
+            Some(Box::new(move || {
+                let output = cmd
+                    .execute_bytes(
+                        None,
+                        ExecuteOptions {
+                            max_output: Some(provider.max_size),
+                            capture_stderr: provider.capture_stderr,
+                            timeout: provider.timeout,
+                            retries: provider.retries,
+                        },
+                    )?
+                    .context("output is missing")?;
+                let output = decode(&output, provider.encoding)?;
+                Ok(output
+                    .lines()
+                    .filter(|entry| !entry.is_empty())
+                    .map(str::to_string)
+                    .collect())
+            }))
+        }
+
+        fn history_entry_reader(&self, entry: &str) -> super::ContentsReader {
+            let provider = self.clone();
+            let entry = entry.to_string();
+            Box::new(move || provider.decode_history_entry(&entry))
+        }
+
+        fn decode_history_entry(&self, entry: &str) -> Result<String> {
+            let cmd = match &self.history_decode_cmd {
+                Some(cmd) => cmd,
+                None => return Ok(entry.to_string()),
+            };
+            let output = cmd
+                .execute_bytes(
+                    Some(encode(entry, self.encoding)?),
+                    ExecuteOptions {
+                        max_output: Some(self.max_size),
+                        capture_stderr: self.capture_stderr,
+                        timeout: self.timeout,
+                        retries: self.retries,
+                    },
+                )?
+                .context("output is missing")?;
+            let contents = decode(&output, self.encoding)?;
+            if self.normalize_crlf {
+                Ok(normalize_crlf(contents))
+            } else {
+                Ok(contents)
+            }
+        }
+
+        fn get_contents(&self, clipboard_type: ClipboardType) -> Result<String> {
+            self.write_cache.clear();
+            let cmd = match clipboard_type {
+                ClipboardType::Clipboard => &self.get_cmd,
+                ClipboardType::Selection => match &self.get_primary_cmd {
+                    Some(cmd) => cmd,
+                    None if self.primary_fallback => &self.get_cmd,
+                    None => return Ok(String::new()),
+                },
+                ClipboardType::Secondary => match &self.get_secondary_cmd {
+                    Some(cmd) => cmd,
+                    None => return Ok(String::new()),
+                },
+            };
+            let contents = if self.encoding == encoding_rs::UTF_8 {
+                match cmd.execute(
+                    None,
+                    ExecuteOptions {
+                        max_output: Some(self.max_size),
+                        capture_stderr: self.capture_stderr,
+                        timeout: self.timeout,
+                        retries: self.retries,
+                    },
+                ) {
+                    Err(err)
+                        if matches!(err.downcast_ref(), Some(ClipboardError::NonText { .. })) =>
+                    {
+                        return Err(ClipboardError::NonText {
+                            mime_type: self.mime_type(cmd),
+                        }
+                        .into())
                     }
+                    result => result?.context("output is missing")?,
+                }
+            } else {
+                let output = cmd
+                    .execute_bytes(
+                        None,
+                        ExecuteOptions {
+                            max_output: Some(self.max_size),
+                            capture_stderr: self.capture_stderr,
+                            timeout: self.timeout,
+                            retries: self.retries,
+                        },
+                    )?
+                    .context("output is missing")?;
+                decode(&output, self.encoding)?
+            };
+
+            let contents = if self.normalize_crlf {
+                normalize_crlf(contents)
+            } else {
+                contents
+            };
+            match self.trailing_newline {
+                TrailingNewline::Keep => Ok(contents),
+                TrailingNewline::Preserve => {
+                    Ok(self.newline_memory.restore(contents, clipboard_type))
                 }
+            }
+        }
+
+        /// Compares the clipboard with the contents recently written to it, if the write cache
+        /// still remembers them. A clipboard that is unchanged keeps skipping writes of the same
+        /// contents.
+        fn has_changed(&self, clipboard_type: ClipboardType) -> Result<bool> {
+            let written = match self.write_cache.written(clipboard_type) {
+                Some(written) => written,
+                None => return Ok(true),
+            };
+            let changed = self.get_contents(clipboard_type)? != written.0;
+            if !changed {
+                self.write_cache.restore(written, clipboard_type);
+            }
+            Ok(changed)
+        }
+
+        fn set_contents(&mut self, value: String, clipboard_type: ClipboardType) -> Result<()> {
+            let cmd = match clipboard_type {
+                ClipboardType::Clipboard => &self.set_cmd,
+                ClipboardType::Selection => match &self.set_primary_cmd {
+                    Some(cmd) => cmd,
+                    None if self.primary_fallback => &self.set_cmd,
+                    None => return Ok(()),
+                },
+                ClipboardType::Secondary => match &self.set_secondary_cmd {
+                    Some(cmd) => cmd,
+                    None => return Ok(()),
+                },
+            };
+            if self.write_cache.contains(&value, clipboard_type) {
+                log::debug!("Skipping copy of unchanged clipboard contents");
+                return Ok(());
+            }
+            // forget the previous contents in case the command fails
+            self.write_cache.set(None, clipboard_type);
+            let written = if self.write_cache.enabled {
+                Some(value.clone())
+            } else {
+                None
+            };
+            cmd.execute_bytes(
+                Some(encode(&value, self.encoding)?),
+                ExecuteOptions {
+                    max_output: None,
+                    capture_stderr: self.capture_stderr,
+                    timeout: self.timeout,
+                    retries: self.retries,
+                },
+            )?;
+            self.write_cache.set(written, clipboard_type);
+            if self.trailing_newline == TrailingNewline::Preserve {
+                self.newline_memory.set(&value, clipboard_type);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::provider::{ExecuteOptions, HISTORY_LEN};
+    use super::*;
+
+    #[test]
+    fn test_set_contents_all() {
+        let mut provider = NopProvider::new();
+        provider.set_contents_all("helix".to_string()).unwrap();
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "helix"
+        );
+        assert_eq!(
+            provider.get_contents(ClipboardType::Selection).unwrap(),
+            "helix"
+        );
+
+        // the clipboard is written first, and a failure leaves the primary selection alone
+        let mut provider = MockProvider::new();
+        provider.set_contents_all("helix".to_string()).unwrap();
+        assert_eq!(
+            provider.calls(),
+            vec![
+                MockCall::Set("helix".to_string(), ClipboardType::Clipboard),
+                MockCall::Set("helix".to_string(), ClipboardType::Selection),
+            ]
+        );
+        provider.clear_calls();
+        provider.fail_set(ClipboardError::Timeout("clipboard".to_string()));
+        assert!(provider.set_contents_all("yank".to_string()).is_err());
+        assert_eq!(provider.calls().len(), 1);
+        assert_eq!(
+            provider.contents(ClipboardType::Selection),
+            Some("helix".to_string())
+        );
+    }
+
+    #[test]
+    fn test_mock_provider() {
+        let mock = MockProvider::new();
+        let mut provider: Box<dyn ClipboardProvider> = Box::new(mock.clone());
+        provider
+            .set_contents("helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "helix"
+        );
+        assert_eq!(provider.get_contents(ClipboardType::Selection).unwrap(), "");
+
+        mock.respond(Ok("other".to_string()));
+        mock.respond(Err(ClipboardError::Timeout("timed out".to_string())));
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "other"
+        );
+        let err = provider.get_contents(ClipboardType::Clipboard).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ClipboardError::Timeout(_))
+        ));
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "helix"
+        );
+
+        mock.fail_set(ClipboardError::NotFound("gone".to_string()));
+        assert!(provider
+            .set_contents("lost".to_string(), ClipboardType::Selection)
+            .is_err());
+        assert_eq!(mock.contents(ClipboardType::Selection), None);
+        assert_eq!(
+            mock.calls(),
+            vec![
+                MockCall::Set("helix".to_string(), ClipboardType::Clipboard),
+                MockCall::Get(ClipboardType::Clipboard),
+                MockCall::Get(ClipboardType::Selection),
+                MockCall::Get(ClipboardType::Clipboard),
+                MockCall::Get(ClipboardType::Clipboard),
+                MockCall::Get(ClipboardType::Clipboard),
+                MockCall::Set("lost".to_string(), ClipboardType::Selection),
+            ]
+        );
+        mock.clear_calls();
+        assert!(mock.calls().is_empty());
+    }
+
+    #[test]
+    fn test_linewise_marker() {
+        let dir =
+            std::env::temp_dir().join(format!("helix-clipboard-linewise-{}", std::process::id()));
+        let marker = LinewiseMarker::new(dir.clone());
+        // nothing is marked yet
+        assert_eq!(marker.get("line", ClipboardType::Clipboard), None);
+
+        marker.set("line\n", ClipboardType::Clipboard).unwrap();
+        marker.set("word", ClipboardType::Selection).unwrap();
+        // a second editor sees the marks, whatever the tools did to the trailing newline
+        let marker = LinewiseMarker::new(dir.clone());
+        assert_eq!(marker.get("line", ClipboardType::Clipboard), Some(true));
+        assert_eq!(
+            marker.restore("line".to_string(), ClipboardType::Clipboard, "\r\n"),
+            "line\r\n"
+        );
+        assert_eq!(
+            marker.restore("line\n".to_string(), ClipboardType::Clipboard, "\n"),
+            "line\n"
+        );
+        assert_eq!(
+            marker.restore("word\n".to_string(), ClipboardType::Selection, "\n"),
+            "word"
+        );
+        // contents copied by other programs
+        assert_eq!(
+            marker.restore("other".to_string(), ClipboardType::Clipboard, "\n"),
+            "other"
+        );
+        assert_eq!(marker.get("word", ClipboardType::Clipboard), None);
+
+        marker.set("word", ClipboardType::Clipboard).unwrap();
+        assert_eq!(marker.get("word", ClipboardType::Clipboard), Some(false));
+        assert_eq!(marker.get("word", ClipboardType::Selection), Some(false));
+        // the hash doesn't depend on the process
+        assert_eq!(
+            std::fs::read_to_string(dir.join("clipboard")).unwrap(),
+            "8095498464603422525 0\n"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_nop_provider_history() {
+        let mut provider = NopProvider::new();
+        assert_eq!(provider.get_contents(ClipboardType::Clipboard).unwrap(), "");
+        assert_eq!(provider.get_history(ClipboardType::Clipboard, 0), None);
+
+        for i in 0..HISTORY_LEN + 2 {
+            provider
+                .set_contents(i.to_string(), ClipboardType::Clipboard)
+                .unwrap();
+        }
+        provider
+            .set_contents("primary".to_string(), ClipboardType::Selection)
+            .unwrap();
+
+        let last = HISTORY_LEN + 1;
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            last.to_string()
+        );
+        assert_eq!(
+            provider.get_history(ClipboardType::Clipboard, 1),
+            Some((last - 1).to_string())
+        );
+        assert_eq!(
+            provider.get_history(ClipboardType::Clipboard, HISTORY_LEN - 1),
+            Some((last + 1 - HISTORY_LEN).to_string())
+        );
+        assert_eq!(
+            provider.get_history(ClipboardType::Clipboard, HISTORY_LEN),
+            None
+        );
+        assert_eq!(
+            provider.get_history(ClipboardType::Selection, 0),
+            Some("primary".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalize_crlf() {
+        use super::provider::normalize_crlf;
+
+        assert_eq!(normalize_crlf("a\r\nb\r\n".to_string()), "a\nb\n");
+        assert_eq!(normalize_crlf("a\nb\r".to_string()), "a\nb\r");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_large_roundtrip() {
+        let cat = CommandConfig {
+            prg: "cat".to_string(),
+            args: Vec::new(),
+        };
+        let input: String = (0..4 * 1024 * 1024)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let output = cat
+            .execute(
+                Some(input.clone()),
+                ExecuteOptions {
+                    max_output: Some(input.len()),
+                    capture_stderr: false,
+                    timeout: Duration::from_secs(30),
+                    retries: 0,
+                },
+            )
+            .unwrap();
+        assert!(output.as_deref() == Some(input.as_str()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_failure_reports_stderr() {
+        let cmd = CommandConfig {
+            prg: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "echo 'first' >&2; echo >&2; echo 'second' >&2; echo 'third' >&2; exit 1"
+                    .to_string(),
+            ],
+        };
+        let err = cmd
+            .execute(
+                None,
+                ExecuteOptions {
+                    max_output: None,
+                    capture_stderr: true,
+                    timeout: Duration::from_secs(5),
+                    retries: 0,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "clipboard provider sh failed: first second"
+        );
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ClipboardError::ProcessFailed { program, stderr })
+                if program == "sh" && stderr == "first second"
+        ));
+
+        let err = cmd
+            .execute(
+                None,
+                ExecuteOptions {
+                    max_output: None,
+                    capture_stderr: false,
+                    timeout: Duration::from_secs(5),
+                    retries: 0,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "clipboard provider sh failed");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_failure_with_background_process() {
+        // the background process keeps stderr open long after the command exited
+        let cmd = CommandConfig {
+            prg: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "sleep 5 & echo 'failed' >&2; exit 1".to_string(),
+            ],
+        };
+        let start = std::time::Instant::now();
+        let err = cmd
+            .execute(
+                None,
+                ExecuteOptions {
+                    max_output: None,
+                    capture_stderr: true,
+                    timeout: Duration::from_secs(5),
+                    retries: 0,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "clipboard provider sh failed: failed");
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_retries() {
+        let dir =
+            std::env::temp_dir().join(format!("helix-clipboard-retry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let attempts = dir.join("attempts");
+        let count = || std::fs::read_to_string(&attempts).unwrap().lines().count();
+
+        // fails on the first attempt only
+        let flaky = CommandConfig {
+            prg: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "cat > /dev/null; echo >> \"$0\"; [ $(wc -l < \"$0\") -gt 1 ] && printf helix"
+                    .to_string(),
+                attempts.to_str().unwrap().to_string(),
+            ],
+        };
+        let options = ExecuteOptions {
+            max_output: Some(1024),
+            capture_stderr: false,
+            timeout: Duration::from_secs(5),
+            retries: 0,
+        };
+        assert!(flaky.execute(Some(String::new()), options).is_err());
+        std::fs::remove_file(&attempts).unwrap();
+        let options = ExecuteOptions {
+            retries: 1,
+            ..options
+        };
+        let output = flaky.execute(Some(String::new()), options).unwrap();
+        assert_eq!(output.as_deref(), Some("helix"));
+        assert_eq!(count(), 2);
+
+        // paste commands failing, e.g. because the clipboard is empty, are not retried
+        std::fs::remove_file(&attempts).unwrap();
+        assert!(flaky.execute(None, options).is_err());
+        assert_eq!(count(), 1);
+
+        // timeouts are not retried
+        std::fs::remove_file(&attempts).unwrap();
+        let slow = CommandConfig {
+            prg: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "echo >> \"$0\"; sleep 5".to_string(),
+                attempts.to_str().unwrap().to_string(),
+            ],
+        };
+        let err = slow
+            .execute(
+                None,
+                ExecuteOptions {
+                    max_output: None,
+                    capture_stderr: false,
+                    timeout: Duration::from_millis(200),
+                    retries: 2,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "clipboard provider sh timed out");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ClipboardError::Timeout(_))
+        ));
+        assert_eq!(count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_osc52_response() {
+        use super::provider::parse_osc52_response;
+
+        assert_eq!(
+            parse_osc52_response(b"\x1b]52;c;aGVsaXg=\x07", "c").unwrap(),
+            ("helix".to_string(), "\x07")
+        );
+        assert_eq!(
+            parse_osc52_response(b"\x1b]52;p;aGVsaXg=\x1b\\", "p").unwrap(),
+            ("helix".to_string(), "\x1b\\")
+        );
+        assert_eq!(
+            parse_osc52_response(b"\x1b]52;c;\x07", "c").unwrap(),
+            (String::new(), "\x07")
+        );
+        assert_eq!(
+            parse_osc52_response(b"\x1b]52;s0;aGVsaXg=\x07", "s0").unwrap(),
+            ("helix".to_string(), "\x07")
+        );
+        assert!(parse_osc52_response(b"\x1b]52;c;aGVsaXg=", "c").is_err());
+        assert!(parse_osc52_response(b"\x1b]52;p;aGVsaXg=\x07", "c").is_err());
+        // missing `;` between the target and the payload
+        assert!(parse_osc52_response(b"\x1b]52;caGVsaXg=\x07", "c").is_err());
+        assert!(parse_osc52_response(b"\x1b]52;c\x07", "c").is_err());
+        // bad base64, and base64 of invalid UTF-8
+        assert!(parse_osc52_response(b"\x1b]52;c;aGVsa*g=\x07", "c").is_err());
+        assert!(parse_osc52_response(b"\x1b]52;c;/w==\x07", "c").is_err());
+        // garbage
+        assert!(parse_osc52_response(b"", "c").is_err());
+        assert!(parse_osc52_response(b"helix", "c").is_err());
+        assert!(parse_osc52_response(b"\x1b]11;rgb:0000/0000/0000\x07", "c").is_err());
+        assert!(parse_osc52_response(b"\xff\x1b]52;c;aGVsaXg=\x07", "c").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_fallback() {
+        let mut provider = provider::TermProvider::new(&ClipboardConfig {
+            osc52_timeout: Duration::ZERO,
+            ..Default::default()
+        });
+        let mut out = Vec::new();
+        provider
+            .write_contents(&mut out, "old".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        provider
+            .write_contents(&mut out, "helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert!(out.ends_with(b"\x1b]52;c;aGVsaXg=\x1b\\"));
+
+        let contents = provider
+            .fallback_contents(ClipboardType::Clipboard, anyhow::anyhow!("no terminal"))
+            .unwrap();
+        assert_eq!(contents, "helix");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_primary_fallback() {
+        let query = |clipboard_type| match clipboard_type {
+            ClipboardType::Clipboard => Ok(String::new()),
+            ClipboardType::Selection => Ok("primary".to_string()),
+            ClipboardType::Secondary => unreachable!(),
+        };
+        let mut config = ClipboardConfig {
+            osc52_paste: super::Osc52Paste::Always,
+            ..Default::default()
+        };
+        let provider = provider::TermProvider::new(&config);
+        assert_eq!(
+            provider
+                .read_contents(ClipboardType::Clipboard, query)
+                .unwrap(),
+            ""
+        );
+
+        config.osc52_primary_fallback = true;
+        let mut provider = provider::TermProvider::new(&config);
+        assert_eq!(
+            provider
+                .read_contents(ClipboardType::Clipboard, query)
+                .unwrap(),
+            "primary"
+        );
+
+        // a failing primary read falls back to the internal clipboard
+        let mut out = Vec::new();
+        provider
+            .write_contents(&mut out, "internal".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        let contents = provider
+            .read_contents(
+                ClipboardType::Clipboard,
+                |clipboard_type| match clipboard_type {
+                    ClipboardType::Clipboard => Ok(String::new()),
+                    ClipboardType::Selection => Err(anyhow::anyhow!("no answer")),
+                    ClipboardType::Secondary => unreachable!(),
+                },
+            )
+            .unwrap();
+        assert_eq!(contents, "internal");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_paste_modes() {
+        use super::Osc52Paste;
+        use std::cell::Cell;
+
+        let queries = Cell::new(0);
+        let timeout = |_| {
+            queries.set(queries.get() + 1);
+            Err(ClipboardError::Timeout("no answer".to_string()).into())
+        };
+        let mut config = ClipboardConfig {
+            osc52_paste: Osc52Paste::Auto,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+
+        // stops asking the terminal after the first timeout
+        let mut provider = provider::TermProvider::new(&config);
+        provider
+            .write_contents(&mut out, "internal".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert!(provider.reads_terminal());
+        for _ in 0..2 {
+            let contents = provider
+                .read_contents(ClipboardType::Clipboard, timeout)
+                .unwrap();
+            assert_eq!(contents, "internal");
+        }
+        assert_eq!(queries.get(), 1);
+        assert!(!provider.reads_terminal());
+        // other failures don't stop the reads
+        let provider = provider::TermProvider::new(&config);
+        let _ = provider.read_contents(ClipboardType::Clipboard, |_| Err(anyhow::anyhow!("bad")));
+        assert!(provider.reads_terminal());
+
+        config.osc52_paste = Osc52Paste::Always;
+        let provider = provider::TermProvider::new(&config);
+        queries.set(0);
+        for _ in 0..2 {
+            let _ = provider.read_contents(ClipboardType::Clipboard, timeout);
+        }
+        assert_eq!(queries.get(), 2);
+
+        config.osc52_paste = Osc52Paste::Never;
+        let provider = provider::TermProvider::new(&config);
+        queries.set(0);
+        let _ = provider.read_contents(ClipboardType::Clipboard, timeout);
+        assert_eq!(queries.get(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_osc52_passthrough() {
+        use provider::Multiplexer;
+
+        assert_eq!(
+            Multiplexer::Tmux.wrap("\x1b]52;c;aGVsaXg=\x1b\\"),
+            "\x1bPtmux;\x1b\x1b]52;c;aGVsaXg=\x1b\x1b\\\x1b\\"
+        );
+        assert_eq!(
+            Multiplexer::Screen.wrap("\x1b]52;c;aGVsaXg=\x1b\\"),
+            "\x1bP\x1b]52;c;aGVsaXg=\x07\x1b\\"
+        );
+        assert_eq!(
+            Multiplexer::Screen.wrap("\x1b]52;c;aGVsaXg=\x07"),
+            "\x1bP\x1b]52;c;aGVsaXg=\x07\x1b\\"
+        );
+
+        // long sequences are split over several passthrough sequences
+        let payload = "a".repeat(1000);
+        let wrapped = Multiplexer::Screen.wrap(&format!("\x1b]52;c;{}\x07", payload));
+        let chunks: Vec<_> = wrapped
+            .split_terminator("\x1b\\")
+            .map(|chunk| chunk.strip_prefix("\x1bP").unwrap())
+            .collect();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 512));
+        assert_eq!(chunks.concat(), format!("\x1b]52;c;{}\x07", payload));
+    }
+
+    #[test]
+    fn test_command_config_shell_syntax() {
+        let config: CustomClipboardConfig = toml::from_str(
+            r#"
+            paste = { command = "xclip", args = ["-o", "-selection", "clipboard"] }
+            copy = { command = "sh", args = ["-c", "tr -d '\\r' | xclip -i"] }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.copy.prg, "sh");
+        assert_eq!(config.copy.args[1], "tr -d '\\r' | xclip -i");
+
+        let err = toml::from_str::<CustomClipboardConfig>(
+            r#"
+            paste = { command = "xclip", args = ["-o", "|", "tr", "-d", "'\\r'"] }
+            copy = { command = "xclip" }
+            "#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("clipboard command argument \"|\" looks like shell syntax"));
+        assert!(toml::from_str::<CustomClipboardConfig>(
+            r#"
+            paste = { command = "xclip", args = ["-o > /tmp/clip"] }
+            copy = { command = "xclip" }
+            "#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_nop_provider_clear() {
+        let mut provider = NopProvider::new();
+        for clipboard_type in [ClipboardType::Clipboard, ClipboardType::Selection] {
+            provider
+                .set_contents("helix".to_string(), clipboard_type)
+                .unwrap();
+        }
+
+        provider.clear(ClipboardType::Clipboard).unwrap();
+        assert_eq!(provider.get_contents(ClipboardType::Clipboard).unwrap(), "");
+        assert_eq!(provider.get_history(ClipboardType::Clipboard, 0), None);
+        assert_eq!(
+            provider.get_contents(ClipboardType::Selection).unwrap(),
+            "helix"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_without_terminal() {
+        let mut provider =
+            provider::TermProvider::with_terminal(&ClipboardConfig::default(), false);
+        assert_eq!(provider.info().kind, ClipboardKind::None);
+        provider
+            .set_contents("helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "helix"
+        );
+        provider.clear(ClipboardType::Clipboard).unwrap();
+        assert_eq!(provider.get_contents(ClipboardType::Clipboard).unwrap(), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_without_copy() {
+        let config = ClipboardConfig {
+            osc52_timeout: Duration::ZERO,
+            osc52_copy: false,
+            osc52_paste: Osc52Paste::Always,
+            ..Default::default()
+        };
+        let mut provider = provider::TermProvider::with_terminal(&config, true);
+        assert_eq!(provider.info().kind, ClipboardKind::Terminal);
+        // nothing is sent to the terminal, so this works without one
+        provider
+            .set_contents("helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        provider.clear(ClipboardType::Selection).unwrap();
+        // reading doesn't ask the terminal, whose clipboard is stale
+        assert!(!provider.reads_terminal());
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "helix"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_max_size() {
+        let config = ClipboardConfig {
+            osc52_max_size: Some(5),
+            ..Default::default()
+        };
+        let mut provider = provider::TermProvider::with_terminal(&config, true);
+        let mut out = Vec::new();
+        provider
+            .write_contents(&mut out, "helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert_eq!(out, b"\x1b]52;c;aGVsaXg=\x1b\\");
+
+        out.clear();
+        let err = provider
+            .write_contents(&mut out, "helix!".to_string(), ClipboardType::Clipboard)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "clipboard contents of 6 bytes exceed the size limit of 5 bytes"
+        );
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ClipboardError::TooLarge {
+                size: 6,
+                max_size: 5
+            })
+        ));
+        assert!(out.is_empty());
+        // the contents are still kept internally
+        assert_eq!(
+            provider
+                .read_contents(ClipboardType::Clipboard, |_| Err(anyhow::anyhow!(
+                    "no terminal"
+                )))
+                .unwrap(),
+            "helix!"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_clear() {
+        let mut provider = provider::TermProvider::new(&ClipboardConfig {
+            osc52_timeout: Duration::ZERO,
+            ..Default::default()
+        });
+        let mut out = Vec::new();
+        provider
+            .write_contents(&mut out, "helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        out.clear();
+        provider
+            .write_clear(&mut out, ClipboardType::Clipboard)
+            .unwrap();
+        assert_eq!(out, b"\x1b]52;c;\x1b\\");
+
+        let contents = provider
+            .fallback_contents(ClipboardType::Clipboard, anyhow::anyhow!("no terminal"))
+            .unwrap();
+        assert_eq!(contents, "");
+    }
+
+    #[test]
+    fn test_first_non_text_type() {
+        use provider::first_non_text_type;
+
+        assert_eq!(
+            first_non_text_type("text/plain\nimage/png\nimage/jpeg\n"),
+            Some("image/png")
+        );
+        assert_eq!(
+            first_non_text_type("TARGETS\nTIMESTAMP\nimage/png\n"),
+            Some("image/png")
+        );
+        assert_eq!(first_non_text_type("UTF8_STRING\ntext/plain\n"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_non_text() {
+        let provider = provider::CommandProvider {
+            get_cmd: CommandConfig {
+                prg: "printf".to_string(),
+                args: vec!["\\377".to_string()],
+            },
+            set_cmd: CommandConfig {
+                prg: "true".to_string(),
+                args: vec![],
+            },
+            get_primary_cmd: None,
+            set_primary_cmd: None,
+            get_secondary_cmd: None,
+            set_secondary_cmd: None,
+            history_list_cmd: None,
+            history_decode_cmd: None,
+            timeout: Duration::from_secs(5),
+            normalize_crlf: false,
+            capture_stderr: true,
+            primary_fallback: false,
+            max_size: 1024,
+            retries: 0,
+            write_cache: provider::WriteCache::default(),
+            encoding: encoding_rs::UTF_8,
+            trailing_newline: TrailingNewline::Keep,
+            newline_memory: provider::NewlineMemory::default(),
+        };
+        let err = provider.get_contents(ClipboardType::Clipboard).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ClipboardError::NonText { mime_type: None })
+        ));
+        assert_eq!(err.to_string(), "clipboard contains non-text data");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_primary_fallback() {
+        let mut provider = provider::CommandProvider {
+            get_cmd: CommandConfig {
+                prg: "echo".to_string(),
+                args: vec!["-n".to_string(), "helix".to_string()],
+            },
+            set_cmd: CommandConfig {
+                prg: "true".to_string(),
+                args: vec![],
+            },
+            get_primary_cmd: None,
+            set_primary_cmd: None,
+            get_secondary_cmd: None,
+            set_secondary_cmd: None,
+            history_list_cmd: None,
+            history_decode_cmd: None,
+            timeout: Duration::from_secs(5),
+            normalize_crlf: false,
+            capture_stderr: true,
+            primary_fallback: false,
+            max_size: 1024,
+            retries: 0,
+            write_cache: provider::WriteCache::default(),
+            encoding: encoding_rs::UTF_8,
+            trailing_newline: TrailingNewline::Keep,
+            newline_memory: provider::NewlineMemory::default(),
+        };
+        assert_eq!(provider.get_contents(ClipboardType::Selection).unwrap(), "");
+
+        provider.primary_fallback = true;
+        assert_eq!(
+            provider.get_contents(ClipboardType::Selection).unwrap(),
+            "helix"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_contents_reader() {
+        let provider = command_provider! {
+            ClipboardConfig::default();
+            paste => "echo", "-n", "helix";
+            copy => "true";
+        };
+        let reader = provider.contents_reader(ClipboardType::Clipboard).unwrap();
+        let contents = std::thread::spawn(reader).join().unwrap().unwrap();
+        assert_eq!(contents, "helix");
+
+        assert!(NopProvider::new()
+            .contents_reader(ClipboardType::Clipboard)
+            .is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_contents_writer() {
+        let file =
+            std::env::temp_dir().join(format!("helix-clipboard-writer-{}", std::process::id()));
+        let mut provider = command_provider! {
+            ClipboardConfig::default();
+            paste => "true";
+            copy => "true";
+        };
+        provider.set_cmd = CommandConfig {
+            prg: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "cat > \"$0\"".to_string(),
+                file.to_str().unwrap().to_string(),
+            ],
+        };
+
+        let writer = provider.contents_writer(ClipboardType::Clipboard).unwrap();
+        std::thread::spawn(move || writer("helix".to_string()))
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "helix");
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(NopProvider::new()
+            .contents_writer(ClipboardType::Clipboard)
+            .is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_trailing_newline() {
+        let file =
+            std::env::temp_dir().join(format!("helix-clipboard-newline-{}", std::process::id()));
+        let file = file.to_str().unwrap().to_string();
+        let provider = |paste: &str, trailing_newline| {
+            CommandProvider::builder()
+                .paste(
+                    "sh",
+                    vec!["-c".to_string(), paste.to_string(), file.clone()],
+                )
+                .copy(
+                    "sh",
+                    vec!["-c".to_string(), "cat > \"$0\"".to_string(), file.clone()],
+                )
+                .trailing_newline(trailing_newline)
+                .build()
+                .unwrap()
+        };
+        let round_trip = |provider: &mut Box<dyn ClipboardProvider>, contents: &str| {
+            provider
+                .set_contents(contents.to_string(), ClipboardType::Clipboard)
+                .unwrap();
+            provider.get_contents(ClipboardType::Clipboard).unwrap()
+        };
+
+        // adds a newline
+        let mut adds = provider("cat \"$0\"; echo", TrailingNewline::Preserve);
+        // strips all trailing newlines
+        let mut strips = provider("printf %s \"$(cat \"$0\")\"", TrailingNewline::Preserve);
+        for contents in ["line\n", "char", "two\nlines\n"] {
+            assert_eq!(round_trip(&mut adds, contents), contents);
+            assert_eq!(round_trip(&mut strips, contents), contents);
+        }
+
+        // contents that weren't copied from Helix are left alone
+        adds.set_contents("a".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        std::fs::write(&file, "b").unwrap();
+        assert_eq!(adds.get_contents(ClipboardType::Clipboard).unwrap(), "b\n");
+
+        let mut adds = provider("cat \"$0\"; echo", TrailingNewline::Keep);
+        assert_eq!(round_trip(&mut adds, "char"), "char\n");
+        let mut strips = provider("printf %s \"$(cat \"$0\")\"", TrailingNewline::Keep);
+        assert_eq!(round_trip(&mut strips, "line\n"), "line");
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_dedup_writes() {
+        let dir =
+            std::env::temp_dir().join(format!("helix-clipboard-dedup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let copies = dir.join("copies");
+        std::fs::write(&copies, "").unwrap();
+        let count = || std::fs::read_to_string(&copies).unwrap().lines().count();
+
+        let mut provider = command_provider! {
+            ClipboardConfig {
+                dedup_writes: true,
+                ..Default::default()
+            };
+            paste => "echo", "-n", "helix";
+            copy => "true";
+        };
+        // counts the copies
+        provider.set_cmd = CommandConfig {
+            prg: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "cat > /dev/null; echo >> \"$0\"".to_string(),
+                copies.to_str().unwrap().to_string(),
+            ],
+        };
+
+        let mut copy = |contents: &str| {
+            provider
+                .set_contents(contents.to_string(), ClipboardType::Clipboard)
+                .unwrap()
+        };
+        copy("a");
+        copy("a");
+        assert_eq!(count(), 1);
+        copy("b");
+        copy("a");
+        assert_eq!(count(), 3);
+
+        // reading invalidates the cache
+        provider.get_contents(ClipboardType::Clipboard).unwrap();
+        provider
+            .set_contents("a".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert_eq!(count(), 4);
+
+        provider.write_cache = provider::WriteCache::new(false);
+        provider
+            .set_contents("a".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        provider
+            .set_contents("a".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert_eq!(count(), 6);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_has_changed() {
+        let mut provider = command_provider! {
+            ClipboardConfig {
+                dedup_writes: true,
+                ..Default::default()
             };
-            cmd.execute(Some(&value), false).map(|_| ())
+            paste => "echo", "-n", "helix";
+            copy => "cat";
+        };
+        // nothing was written yet
+        assert!(provider.has_changed(ClipboardType::Clipboard).unwrap());
+
+        provider
+            .set_contents("helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert!(!provider.has_changed(ClipboardType::Clipboard).unwrap());
+        // checking keeps the written contents remembered
+        assert!(!provider.has_changed(ClipboardType::Clipboard).unwrap());
+
+        provider
+            .set_contents("other".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert!(provider.has_changed(ClipboardType::Clipboard).unwrap());
+
+        let mut provider = NopProvider::new();
+        provider
+            .set_contents("helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert!(!provider.has_changed(ClipboardType::Clipboard).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_term_provider_targets() {
+        let config: ClipboardConfig =
+            toml::from_str("osc52-clipboard-target = \"s\"\nosc52-primary-target = \"0\"").unwrap();
+        let mut provider = provider::TermProvider::new(&config);
+        let mut out = Vec::new();
+        provider
+            .write_contents(&mut out, "helix".to_string(), ClipboardType::Selection)
+            .unwrap();
+        assert_eq!(out, b"\x1b]52;0;aGVsaXg=\x1b\\");
+        out.clear();
+        provider
+            .write_contents(&mut out, "helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        assert_eq!(out, b"\x1b]52;s;aGVsaXg=\x1b\\");
+
+        assert!(toml::from_str::<ClipboardConfig>("osc52-primary-target = \"x\"").is_err());
+        assert!(toml::from_str::<ClipboardConfig>("osc52-primary-target = \"\"").is_err());
+    }
+
+    #[test]
+    fn test_file_provider() {
+        let dir = std::env::temp_dir().join(format!("helix-clipboard-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut provider = provider::FileProvider::new(dir.join("clipboard"));
+        assert_eq!(provider.get_contents(ClipboardType::Clipboard).unwrap(), "");
+
+        provider
+            .set_contents("helix".to_string(), ClipboardType::Clipboard)
+            .unwrap();
+        provider
+            .set_contents("primary".to_string(), ClipboardType::Selection)
+            .unwrap();
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "helix"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("clipboard.primary")).unwrap(),
+            "primary"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_output_limit() {
+        let yes = CommandConfig {
+            prg: "yes".to_string(),
+            args: Vec::new(),
+        };
+        let err = yes
+            .execute(
+                None,
+                ExecuteOptions {
+                    max_output: Some(1024),
+                    capture_stderr: false,
+                    timeout: Duration::from_secs(5),
+                    retries: 0,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "clipboard provider yes output exceeds 1024 bytes"
+        );
+
+        let printf = CommandConfig {
+            prg: "printf".to_string(),
+            args: vec!["helix".to_string()],
+        };
+        let output = printf
+            .execute(
+                None,
+                ExecuteOptions {
+                    max_output: Some(5),
+                    capture_stderr: false,
+                    timeout: Duration::from_secs(5),
+                    retries: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(output.as_deref(), Some("helix"));
+        assert!(printf
+            .execute(
+                None,
+                ExecuteOptions {
+                    max_output: Some(4),
+                    capture_stderr: false,
+                    timeout: Duration::from_secs(5),
+                    retries: 0
+                }
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_none_provider_skips_detection() {
+        let config = ClipboardConfig {
+            provider: Some("none".to_string()),
+            ..Default::default()
+        };
+        let provider = get_clipboard_provider(&config).unwrap();
+        assert_eq!(provider.name(), "none");
+        assert_eq!(provider.info().kind, ClipboardKind::None);
+    }
+
+    #[test]
+    fn test_provider_not_found() {
+        let config = ClipboardConfig::default();
+        for name in ["helix-missing", "xclip"] {
+            if name == "xclip" && exists("xclip") {
+                continue;
+            }
+            let err = get_clipboard_provider_by_name(name, &config)
+                .map(|_| ())
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref(),
+                Some(ClipboardError::NotFound(_))
+            ));
         }
     }
+
+    #[test]
+    fn test_poll_new_buffer() {
+        use provider::poll_new_buffer;
+
+        let mut buffers = vec![Some("buffer1"), Some("helix"), Some("helix")].into_iter();
+        let newest = || Ok(buffers.next().flatten().map(str::to_owned));
+        assert_eq!(
+            poll_new_buffer(Some("helix"), Duration::from_secs(5), newest).unwrap(),
+            Some("buffer1".to_string())
+        );
+
+        let mut polls = 0;
+        let newest = || {
+            polls += 1;
+            Ok(if polls < 3 {
+                None
+            } else {
+                Some("buffer0".to_string())
+            })
+        };
+        assert_eq!(
+            poll_new_buffer(None, Duration::from_secs(5), newest).unwrap(),
+            Some("buffer0".to_string())
+        );
+        assert_eq!(polls, 3);
+
+        let newest = || Ok(Some("helix".to_string()));
+        assert_eq!(
+            poll_new_buffer(Some("helix"), Duration::ZERO, newest).unwrap(),
+            None
+        );
+        assert!(
+            poll_new_buffer(None, Duration::ZERO, || Err(anyhow::anyhow!("no server"))).is_err()
+        );
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        use provider::{decode, encode};
+
+        let utf16 = encode("hé", encoding_rs::UTF_16LE).unwrap();
+        assert_eq!(utf16, b"h\0\xe9\0");
+        assert_eq!(decode(&utf16, encoding_rs::UTF_16LE).unwrap(), "hé");
+        assert_eq!(
+            decode(b"\xff\xfeh\0\xe9\0", encoding_rs::UTF_16LE).unwrap(),
+            "hé"
+        );
+        assert_eq!(encode("hé", encoding_rs::UTF_16BE).unwrap(), b"\0h\0\xe9");
+
+        let latin1 = encoding_rs::WINDOWS_1252;
+        assert_eq!(encode("hé", latin1).unwrap(), b"h\xe9");
+        assert_eq!(decode(b"h\xe9", latin1).unwrap(), "hé");
+        assert_eq!(
+            encode("h€ü", encoding_rs::SHIFT_JIS)
+                .unwrap_err()
+                .to_string(),
+            "clipboard contents can't be encoded as Shift_JIS"
+        );
+        assert_eq!(
+            decode(b"h\xe9", encoding_rs::UTF_8)
+                .unwrap_err()
+                .to_string(),
+            "clipboard contents aren't valid UTF-8"
+        );
+    }
+
+    #[test]
+    fn test_custom_encoding() {
+        let config: ClipboardConfig = toml::from_str(
+            r#"
+            custom = { paste = { command = "paste" }, copy = { command = "copy" }, encoding = "utf-16le" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.custom.unwrap().encoding, encoding_rs::UTF_16LE);
+
+        let config: ClipboardConfig = toml::from_str(
+            r#"custom = { paste = { command = "paste" }, copy = { command = "copy" } }"#,
+        )
+        .unwrap();
+        assert_eq!(config.custom.unwrap().encoding, encoding_rs::UTF_8);
+
+        assert!(toml::from_str::<ClipboardConfig>(
+            r#"custom = { paste = { command = "paste" }, copy = { command = "copy" }, encoding = "klingon" }"#
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_encoding() {
+        let mut provider = command_provider! {
+            ClipboardConfig::default();
+            paste => "printf", "h\\000\\351\\000";
+            copy => "true";
+        };
+        provider.encoding = encoding_rs::UTF_16LE;
+        assert_eq!(
+            provider.get_contents(ClipboardType::Clipboard).unwrap(),
+            "hé"
+        );
+    }
+
+    #[test]
+    fn test_command_provider_builder() {
+        let provider = CommandProvider::builder()
+            .paste("wl-paste", vec!["--no-newline".to_owned()])
+            .copy("wl-copy", Vec::new())
+            .build()
+            .unwrap();
+        assert_eq!(provider.name(), "wl-paste+wl-copy");
+        assert!(!provider.supports_primary());
+
+        let config = ClipboardConfig {
+            timeout: Duration::from_secs(3),
+            retries: 0,
+            ..Default::default()
+        };
+        let provider = CommandProvider::builder()
+            .config(&config)
+            .paste("xsel", vec!["-o".to_owned()])
+            .copy("xsel", vec!["-i".to_owned()])
+            .primary_paste("xsel", vec!["-o".to_owned(), "-p".to_owned()])
+            .primary_copy("xsel", vec!["-i".to_owned(), "-p".to_owned()])
+            .normalize_crlf(true)
+            .build_command_provider()
+            .unwrap();
+        assert!(provider.supports_primary());
+        assert_eq!(provider.timeout, Duration::from_secs(3));
+        assert_eq!(provider.retries, 0);
+        assert!(provider.normalize_crlf);
+
+        let err = CommandProvider::builder()
+            .paste("xsel", Vec::new())
+            .build()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "clipboard provider has no copy command");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_history() {
+        let provider = CommandProvider::builder()
+            .paste("true", Vec::new())
+            .copy("true", Vec::new())
+            .history_list(
+                "sh",
+                vec![
+                    "-c".to_string(),
+                    "printf '2\\tnew\\n\\n1\\told\\n'".to_string(),
+                ],
+            )
+            .history_decode("cut", vec!["-f2".to_string()])
+            .build()
+            .unwrap();
+        let entries = provider.list_history().unwrap()().unwrap();
+        assert_eq!(entries, ["2\tnew", "1\told"]);
+        assert_eq!(provider.decode_history_entry(&entries[1]).unwrap(), "old\n");
+        assert_eq!(
+            provider.history_entry_reader(&entries[0])().unwrap(),
+            "new\n"
+        );
+        assert!(provider.info().binaries.contains(&"cut".to_string()));
+
+        // entries are pasted as listed without a decode command
+        let config = ClipboardConfig {
+            history_list: Some(CommandConfig::new("echo", vec!["helix".to_string()])),
+            ..Default::default()
+        };
+        let provider = CommandProvider::builder()
+            .config(&config)
+            .paste("true", Vec::new())
+            .copy("true", Vec::new())
+            .build()
+            .unwrap();
+        let entries = provider.list_history().unwrap()().unwrap();
+        assert_eq!(entries, ["helix"]);
+        assert_eq!(provider.decode_history_entry("helix").unwrap(), "helix");
+
+        assert!(NopProvider::new().list_history().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secondary() {
+        let mut provider = NopProvider::new();
+        assert!(!provider.supports_secondary());
+        provider
+            .set_contents("helix".to_string(), ClipboardType::Secondary)
+            .unwrap();
+        assert_eq!(provider.get_contents(ClipboardType::Secondary).unwrap(), "");
+        assert_eq!(provider.get_contents(ClipboardType::Clipboard).unwrap(), "");
+
+        let provider = command_provider! {
+            ClipboardConfig::default();
+            paste => "echo", "-n", "clipboard";
+            copy => "true";
+            primary_paste => "echo", "-n", "primary";
+            primary_copy => "true";
+            secondary_paste => "echo", "-n", "secondary";
+            secondary_copy => "true";
+        };
+        assert!(provider.supports_secondary());
+        assert_eq!(
+            provider.get_contents(ClipboardType::Secondary).unwrap(),
+            "secondary"
+        );
+
+        let provider = build_provider("xsel", &ClipboardConfig::default()).unwrap();
+        assert!(provider.supports_secondary());
+        let provider = build_provider("wl-clipboard", &ClipboardConfig::default()).unwrap();
+        assert!(!provider.supports_secondary());
+    }
+
+    #[test]
+    fn test_command_provider_info() {
+        let provider = command_provider! {
+            ClipboardConfig::default();
+            paste => "wl-paste", "--no-newline";
+            copy => "wl-copy", "--type", "text/plain";
+            primary_paste => "wl-paste", "-p", "--no-newline";
+            primary_copy => "wl-copy", "-p", "--type", "text/plain";
+        };
+        assert_eq!(
+            provider.info(),
+            ClipboardInfo {
+                name: "wl-paste+wl-copy".to_string(),
+                kind: ClipboardKind::Command,
+                supports_primary: true,
+                binaries: vec!["wl-paste".to_string(), "wl-copy".to_string()],
+            }
+        );
+        assert_eq!(provider.name(), "wl-paste+wl-copy");
+    }
 }
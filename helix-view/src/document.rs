@@ -7,18 +7,21 @@ use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
 
 use helix_core::{
+    diagnostic::Severity,
+    diff::{DiffStat, LineDiff},
     history::History,
     indent::{auto_detect_indent_style, IndentStyle},
     line_ending::auto_detect_line_ending,
     syntax::{self, LanguageConfiguration},
-    ChangeSet, Diagnostic, LineEnding, Rope, RopeBuilder, Selection, State, Syntax, Transaction,
-    DEFAULT_LINE_ENDING,
+    Assoc, ChangeSet, Diagnostic, LineEnding, Operation, Rope, RopeBuilder, Selection, State,
+    Syntax, Transaction, DEFAULT_LINE_ENDING,
 };
 use helix_lsp::util::LspFormatting;
 
-use crate::{DocumentId, Theme, ViewId};
+use crate::{vcs::DiffRequest, DocumentId, Theme, ViewId};
 
 /// 8kB of buffer space for encoding and decoding `Rope`s.
 const BUF_SIZE: usize = 8192;
@@ -68,6 +71,37 @@ impl<'de> Deserialize<'de> for Mode {
     }
 }
 
+/// A debugger breakpoint set on a line of a [`Document`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub line: usize,
+    /// Expression that has to be true for the debugger to stop here.
+    pub condition: Option<String>,
+}
+
+/// A mark set on a line of a [`Document`], shown in the mark gutter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    pub line: usize,
+    /// Single-cell character the mark is named and shown by.
+    pub name: char,
+}
+
+/// The first line of a symbol the language server reported, shown in the symbol gutter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub line: usize,
+    pub kind: lsp::SymbolKind,
+}
+
+/// A successful save of a document, returned by [`Document::save`] and passed back to
+/// [`Document::set_saved`] once the file is written.
+#[derive(Debug, Clone, Copy)]
+pub struct SavedEvent {
+    pub doc_id: DocumentId,
+    generation: u64,
+}
+
 pub struct Document {
     pub(crate) id: DocumentId,
     text: Rope,
@@ -107,7 +141,45 @@ pub struct Document {
     pub(crate) modified_since_accessed: bool,
 
     diagnostics: Vec<Diagnostic>,
+    /// Highest severity of `diagnostics`, kept up to date for the diagnostic gutter's width.
+    max_diagnostic_severity: Option<Severity>,
     language_server: Option<Arc<helix_lsp::Client>>,
+
+    /// Version of the text the diff gutter compares against, usually the last commit.
+    diff_base: Option<Rope>,
+    /// Lines that differ from `diff_base`, sorted by line.
+    line_diffs: Vec<(usize, LineDiff)>,
+    /// Summary of `line_diffs`.
+    diff_stat: DiffStat,
+    /// Where to request `line_diffs` from, they're computed in place if unset.
+    diff_requests: Option<UnboundedSender<DiffRequest>>,
+
+    /// Lines added or changed since the last successful save, sorted.
+    unsaved_lines: Vec<usize>,
+    /// Generation of the save in flight and the lines changed since it was issued, sorted.
+    pending_save: Option<(u64, Vec<usize>)>,
+    /// Number of saves issued, identifies the save a [`SavedEvent`] belongs to.
+    save_generation: u64,
+
+    /// Commit that last changed each line, `None` while it's loaded.
+    blame: Option<Vec<String>>,
+
+    /// Number of digits of the line count, kept up to date for the line number gutter.
+    line_number_digits: usize,
+
+    /// Sorted by line, with at most one breakpoint per line.
+    breakpoints: Vec<Breakpoint>,
+    /// Sorted by line, with at most one mark per line.
+    marks: Vec<Mark>,
+    /// Sorted by line, with only the outermost symbol of each line.
+    symbols: Vec<Symbol>,
+    /// Version of the document `symbols` were requested for.
+    symbols_version: Option<i32>,
+    /// Sorted lines with matches of the search that is being typed, `None` while there is none.
+    search_matches: Option<Vec<usize>>,
+    /// Line the debugger is currently stopped at, if it is stopped in this document. Nothing sets
+    /// it yet, as there is no debugger integration.
+    pub debug_active_line: Option<usize>,
 }
 
 use std::{fmt, mem};
@@ -326,6 +398,7 @@ impl Document {
         let encoding = encoding.unwrap_or(encoding_rs::UTF_8);
         let changes = ChangeSet::new(&text);
         let old_state = None;
+        let line_number_digits = crate::gutter::digits10(text.len_lines());
 
         Self {
             id: DocumentId::default(),
@@ -342,12 +415,28 @@ impl Document {
             changes,
             old_state,
             diagnostics: Vec::new(),
+            max_diagnostic_severity: None,
             version: 0,
             history: Cell::new(History::default()),
             savepoint: None,
             last_saved_revision: 0,
             modified_since_accessed: false,
             language_server: None,
+            diff_base: None,
+            line_diffs: Vec::new(),
+            diff_stat: DiffStat::default(),
+            diff_requests: None,
+            unsaved_lines: Vec::new(),
+            pending_save: None,
+            save_generation: 0,
+            blame: None,
+            line_number_digits,
+            breakpoints: Vec::new(),
+            marks: Vec::new(),
+            symbols: Vec::new(),
+            symbols_version: None,
+            search_matches: None,
+            debug_active_line: None,
         }
     }
 
@@ -422,14 +511,14 @@ impl Document {
         }
     }
 
-    pub fn save(&mut self) -> impl Future<Output = Result<(), anyhow::Error>> {
+    pub fn save(&mut self) -> impl Future<Output = Result<SavedEvent, anyhow::Error>> {
         self.save_impl::<futures_util::future::Ready<_>>(None)
     }
 
     pub fn format_and_save(
         &mut self,
         formatting: Option<impl Future<Output = LspFormatting>>,
-    ) -> impl Future<Output = anyhow::Result<()>> {
+    ) -> impl Future<Output = anyhow::Result<SavedEvent>> {
         self.save_impl(formatting)
     }
 
@@ -442,7 +531,7 @@ impl Document {
     fn save_impl<F: Future<Output = LspFormatting>>(
         &mut self,
         formatting: Option<F>,
-    ) -> impl Future<Output = Result<(), anyhow::Error>> {
+    ) -> impl Future<Output = Result<SavedEvent, anyhow::Error>> {
         // we clone and move text + path into the future so that we asynchronously save the current
         // state without blocking any further edits.
 
@@ -453,7 +542,13 @@ impl Document {
         let language_server = self.language_server.clone();
 
         // mark changes up to now as saved
+        self.save_generation += 1;
+        self.pending_save = Some((self.save_generation, Vec::new()));
         self.reset_modified();
+        let saved = SavedEvent {
+            doc_id: self.id,
+            generation: self.save_generation,
+        };
 
         let encoding = self.encoding;
 
@@ -483,7 +578,7 @@ impl Document {
 
             if let Some(language_server) = language_server {
                 if !language_server.is_initialized() {
-                    return Ok(());
+                    return Ok(saved);
                 }
                 if let Some(notification) =
                     language_server.text_document_did_save(identifier, &text)
@@ -492,7 +587,7 @@ impl Document {
                 }
             }
 
-            Ok(())
+            Ok(saved)
         }
     }
 
@@ -668,13 +763,55 @@ impl Document {
 
             // map state.diagnostics over changes::map_pos too
             for diagnostic in &mut self.diagnostics {
-                use helix_core::Assoc;
                 let changes = transaction.changes();
                 diagnostic.range.start = changes.map_pos(diagnostic.range.start, Assoc::After);
                 diagnostic.range.end = changes.map_pos(diagnostic.range.end, Assoc::After);
                 diagnostic.line = self.text.char_to_line(diagnostic.range.start);
             }
 
+            // keep breakpoints, marks and symbols on the lines they were set on
+            let changes = transaction.changes();
+            remap_lines(
+                &mut self.breakpoints,
+                |breakpoint| &mut breakpoint.line,
+                changes,
+                &old_doc,
+                &self.text,
+            );
+            remap_lines(
+                &mut self.marks,
+                |mark| &mut mark.line,
+                changes,
+                &old_doc,
+                &self.text,
+            );
+            remap_lines(
+                &mut self.symbols,
+                |symbol| &mut symbol.line,
+                changes,
+                &old_doc,
+                &self.text,
+            );
+
+            // keep the line diffs roughly in place until they're recomputed
+            remap_lines(
+                &mut self.line_diffs,
+                |(line, _)| line,
+                changes,
+                &old_doc,
+                &self.text,
+            );
+            self.diff_stat = DiffStat::new(&self.line_diffs);
+            self.update_line_diffs();
+            mark_changed_lines(&mut self.unsaved_lines, changes, &old_doc, &self.text);
+            if let Some((_, lines)) = &mut self.pending_save {
+                mark_changed_lines(lines, changes, &old_doc, &self.text);
+            }
+            if let Some(blame) = &mut self.blame {
+                remap_blame(blame, changes, &old_doc, &self.text);
+            }
+            self.line_number_digits = crate::gutter::digits10(self.text.len_lines());
+
             // emit lsp notification
             if let Some(language_server) = self.language_server() {
                 let notify = language_server.text_document_did_change(
@@ -727,6 +864,7 @@ impl Document {
         if success {
             // reset changeset to fix len
             self.changes = ChangeSet::new(self.text());
+            self.update_unsaved_lines();
         }
         success
     }
@@ -744,6 +882,7 @@ impl Document {
         if success {
             // reset changeset to fix len
             self.changes = ChangeSet::new(self.text());
+            self.update_unsaved_lines();
         }
         success
     }
@@ -770,6 +909,7 @@ impl Document {
         if success {
             // reset changeset to fix len
             self.changes = ChangeSet::new(self.text());
+            self.update_unsaved_lines();
         }
         success
     }
@@ -786,6 +926,7 @@ impl Document {
         if success {
             // reset changeset to fix len
             self.changes = ChangeSet::new(self.text());
+            self.update_unsaved_lines();
         }
         success
     }
@@ -829,6 +970,30 @@ impl Document {
         let current_revision = history.current_revision();
         self.history.set(history);
         self.last_saved_revision = current_revision;
+        match &mut self.pending_save {
+            // the lines are saved once the save succeeds, see `Document::set_saved`
+            Some((_, lines)) => lines.clear(),
+            None => self.unsaved_lines.clear(),
+        }
+    }
+
+    /// Marks the lines written by the save `saved` belongs to as saved. Lines changed while
+    /// the save was in flight stay unsaved.
+    pub fn set_saved(&mut self, saved: SavedEvent) {
+        if let Some((generation, lines)) = self.pending_save.take() {
+            if generation == saved.generation {
+                self.unsaved_lines = lines;
+            } else {
+                self.pending_save = Some((generation, lines));
+            }
+        }
+    }
+
+    /// Clears the unsaved lines after undoing or redoing back to the last saved revision.
+    fn update_unsaved_lines(&mut self) {
+        if !self.is_modified() {
+            self.reset_modified();
+        }
     }
 
     /// Current editing mode for the [`Document`].
@@ -942,12 +1107,264 @@ impl Document {
         &self.diagnostics
     }
 
+    /// Highest severity of the diagnostics, counting diagnostics without a severity as warnings.
+    /// `None` if there are no diagnostics.
+    #[inline]
+    pub fn max_diagnostic_severity(&self) -> Option<Severity> {
+        self.max_diagnostic_severity
+    }
+
     pub fn set_diagnostics(&mut self, diagnostics: Vec<Diagnostic>) {
+        self.max_diagnostic_severity = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.severity.unwrap_or(Severity::Warning))
+            .max();
         self.diagnostics = diagnostics;
         // sort by range
         self.diagnostics
             .sort_unstable_by_key(|diagnostic| diagnostic.range);
     }
+
+    /// Marks the diagnostics on `line` with the given `message` as having code actions.
+    pub fn mark_code_actions(&mut self, line: usize, message: &str) {
+        for diagnostic in &mut self.diagnostics {
+            if diagnostic.line == line && diagnostic.message == message {
+                diagnostic.has_code_actions = true;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn diff_base(&self) -> Option<&Rope> {
+        self.diff_base.as_ref()
+    }
+
+    /// Sets the text the diff gutter compares against, or disables diffing with `None`.
+    pub fn set_diff_base(&mut self, diff_base: Option<Rope>) {
+        self.diff_base = diff_base;
+        self.update_line_diffs();
+    }
+
+    /// Sets where the line diffs are requested from, instead of computing them in place on
+    /// every change.
+    pub(crate) fn set_diff_requests(&mut self, diff_requests: UnboundedSender<DiffRequest>) {
+        self.diff_requests = Some(diff_requests);
+    }
+
+    /// Sets the line diffs of the document at `version`, ignored if the document changed since.
+    pub fn set_line_diffs(&mut self, version: i32, line_diffs: Vec<(usize, LineDiff)>) {
+        if version == self.version && self.diff_base.is_some() {
+            self.line_diffs = line_diffs;
+            self.diff_stat = DiffStat::new(&self.line_diffs);
+        }
+    }
+
+    /// Lines that differ from the diff base, sorted by line.
+    #[inline]
+    pub fn line_diffs(&self) -> &[(usize, LineDiff)] {
+        &self.line_diffs
+    }
+
+    /// Number of lines that differ from the diff base, updated along with
+    /// [`Document::line_diffs`].
+    #[inline]
+    pub fn diff_stat(&self) -> DiffStat {
+        self.diff_stat
+    }
+
+    /// Hash of the commit that last changed each line, as of when the file was last opened,
+    /// saved or reloaded. Empty for uncommitted lines and lines changed since, `None` if the
+    /// blame isn't loaded (yet).
+    #[inline]
+    pub fn blame(&self) -> Option<&[String]> {
+        self.blame.as_deref()
+    }
+
+    pub fn set_blame(&mut self, blame: Option<Vec<String>>) {
+        self.blame = blame;
+    }
+
+    /// Lines added or changed since the last save, sorted.
+    #[inline]
+    pub fn unsaved_lines(&self) -> &[usize] {
+        &self.unsaved_lines
+    }
+
+    /// Number of digits needed to show the number of the last line.
+    #[inline]
+    pub fn line_number_digits(&self) -> usize {
+        self.line_number_digits
+    }
+
+    /// Breakpoints sorted by line.
+    #[inline]
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn set_breakpoints(&mut self, mut breakpoints: Vec<Breakpoint>) {
+        let len_lines = self.text.len_lines();
+        breakpoints.retain(|breakpoint| breakpoint.line < len_lines);
+        breakpoints.sort_unstable_by_key(|breakpoint| breakpoint.line);
+        breakpoints.dedup_by_key(|breakpoint| breakpoint.line);
+        self.breakpoints = breakpoints;
+    }
+
+    /// Marks sorted by line.
+    #[inline]
+    pub fn marks(&self) -> &[Mark] {
+        &self.marks
+    }
+
+    pub fn set_marks(&mut self, mut marks: Vec<Mark>) {
+        let len_lines = self.text.len_lines();
+        marks.retain(|mark| mark.line < len_lines);
+        marks.sort_unstable_by_key(|mark| mark.line);
+        marks.dedup_by_key(|mark| mark.line);
+        self.marks = marks;
+    }
+
+    /// Symbols sorted by line, at most one per line.
+    #[inline]
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Version of the document the symbols were requested for, `None` if they never were.
+    pub fn symbols_version(&self) -> Option<i32> {
+        self.symbols_version
+    }
+
+    /// Sets the symbols of the document at `version`. Symbols listed first win if several start
+    /// on the same line, so outer symbols should come before the symbols nested in them.
+    pub fn set_symbols(&mut self, version: i32, mut symbols: Vec<Symbol>) {
+        let len_lines = self.text.len_lines();
+        symbols.retain(|symbol| symbol.line < len_lines);
+        symbols.sort_by_key(|symbol| symbol.line);
+        symbols.dedup_by_key(|symbol| symbol.line);
+        self.symbols = symbols;
+        self.symbols_version = Some(version);
+    }
+
+    /// Lines in view with matches of the active search, sorted, or `None` if no search is
+    /// active.
+    #[inline]
+    pub fn search_matches(&self) -> Option<&[usize]> {
+        self.search_matches.as_deref()
+    }
+
+    /// Sets the lines with matches of the active search, `None` once the search is dismissed.
+    pub fn set_search_matches(&mut self, lines: Option<Vec<usize>>) {
+        self.search_matches = lines;
+    }
+
+    fn update_line_diffs(&mut self) {
+        let diff_base = match &self.diff_base {
+            Some(diff_base) => diff_base,
+            None => {
+                self.line_diffs.clear();
+                self.diff_stat = DiffStat::default();
+                return;
+            }
+        };
+        match &self.diff_requests {
+            Some(diff_requests) => {
+                let _ = diff_requests.send(DiffRequest {
+                    doc: self.id,
+                    version: self.version,
+                    base: diff_base.clone(),
+                    text: self.text.clone(),
+                });
+            }
+            None => {
+                self.line_diffs = helix_core::diff::compare_lines(diff_base, &self.text);
+                self.diff_stat = DiffStat::new(&self.line_diffs);
+            }
+        }
+    }
+}
+
+/// Moves the lines of `items`, sorted by the line `line_of` returns, from `old_text` to where
+/// `changes` put them in `new_text`. Items whose lines end up on the same line, because the
+/// lines between them were deleted, are merged into the first one.
+fn remap_lines<T>(
+    items: &mut Vec<T>,
+    line_of: impl Fn(&mut T) -> &mut usize,
+    changes: &ChangeSet,
+    old_text: &Rope,
+    new_text: &Rope,
+) {
+    for item in items.iter_mut() {
+        let line = line_of(item);
+        let pos = old_text.line_to_char(*line);
+        *line = new_text.char_to_line(changes.map_pos(pos, Assoc::After));
+    }
+    items.dedup_by(|item, previous| line_of(item) == line_of(previous));
+}
+
+/// Moves the sorted `lines` from `old_text` to where `changes` put them in `new_text`, and adds
+/// the lines `changes` inserted text into or deleted text from. Deleting whole lines doesn't
+/// mark the line after them.
+fn mark_changed_lines(
+    lines: &mut Vec<usize>,
+    changes: &ChangeSet,
+    old_text: &Rope,
+    new_text: &Rope,
+) {
+    let is_line_start = |text: &Rope, pos: usize| text.line_to_char(text.char_to_line(pos)) == pos;
+
+    remap_lines(lines, |line| line, changes, old_text, new_text);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for operation in changes.changes() {
+        match operation {
+            Operation::Retain(n) => {
+                old_pos += n;
+                new_pos += n;
+            }
+            Operation::Delete(n) => {
+                if !is_line_start(old_text, old_pos) || !is_line_start(old_text, old_pos + n) {
+                    lines.push(new_text.char_to_line(new_pos));
+                }
+                old_pos += n;
+            }
+            Operation::Insert(text) => {
+                let len = text.chars().count();
+                let start = new_text.char_to_line(new_pos);
+                let end = new_text.char_to_line(new_pos + len);
+                // inserting whole lines leaves the line after them as is
+                if is_line_start(new_text, new_pos) && text.ends_with('\n') {
+                    lines.extend(start..end);
+                } else {
+                    lines.extend(start..=end);
+                }
+                new_pos += len;
+            }
+        }
+    }
+    lines.sort_unstable();
+    lines.dedup();
+}
+
+/// Moves the commit hashes of `blame` from the lines of `old_text` to where `changes` put them
+/// in `new_text`. Lines `changes` touched aren't committed anymore and get an empty hash.
+fn remap_blame(blame: &mut Vec<String>, changes: &ChangeSet, old_text: &Rope, new_text: &Rope) {
+    let mut hashes: Vec<_> = std::mem::take(blame).into_iter().enumerate().collect();
+    hashes.truncate(old_text.len_lines());
+    remap_lines(&mut hashes, |(line, _)| line, changes, old_text, new_text);
+    blame.resize(new_text.len_lines(), String::new());
+    for (line, hash) in hashes {
+        if let Some(entry) = blame.get_mut(line) {
+            *entry = hash;
+        }
+    }
+
+    let mut changed = Vec::new();
+    mark_changed_lines(&mut changed, changes, old_text, new_text);
+    for line in changed {
+        if let Some(entry) = blame.get_mut(line) {
+            entry.clear();
+        }
+    }
 }
 
 impl Default for Document {
@@ -961,6 +1378,75 @@ impl Default for Document {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_remap_lines() {
+        let old = Rope::from("a\nb\nc\nd\n");
+        // inserts a line above b and deletes c
+        let transaction =
+            Transaction::change(&old, [(2, 2, Some("x\n".into())), (4, 6, None)].into_iter());
+        let mut new = old.clone();
+        transaction.apply(&mut new);
+
+        let mut lines = vec![0, 1, 2, 3];
+        remap_lines(&mut lines, |line| line, transaction.changes(), &old, &new);
+        // c's line merged into d's
+        assert_eq!(lines, vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn test_remap_blame() {
+        let old = Rope::from("a\nb\nc\n");
+        // changes a, inserts a line above c
+        let transaction = Transaction::change(
+            &old,
+            [(0, 1, Some("A".into())), (4, 4, Some("x\n".into()))].into_iter(),
+        );
+        let mut new = old.clone();
+        transaction.apply(&mut new);
+
+        let mut blame = vec!["1".to_string(), "2".to_string(), "3".to_string()];
+        remap_blame(&mut blame, transaction.changes(), &old, &new);
+        assert_eq!(blame, vec!["", "2", "", "3", ""]);
+    }
+
+    #[test]
+    fn unsaved_lines() {
+        let mut doc = Document::from(Rope::from("a\nb\nc\n"), None);
+        let view = ViewId::default();
+        doc.set_selection(view, Selection::single(0, 0));
+        assert!(doc.unsaved_lines().is_empty());
+
+        let transaction = Transaction::change(
+            doc.text(),
+            vec![(2, 3, Some("B".into())), (6, 6, Some("d\n".into()))].into_iter(),
+        );
+        doc.apply(&transaction, view);
+        assert_eq!(doc.unsaved_lines(), &[1, 3]);
+
+        // deleting a whole line only moves the lines after it
+        let transaction = Transaction::change(doc.text(), [(0, 2, None)].into_iter());
+        doc.apply(&transaction, view);
+        assert_eq!(doc.unsaved_lines(), &[0, 2]);
+
+        // the lines stay unsaved until the save succeeds
+        doc.save_generation += 1;
+        doc.pending_save = Some((doc.save_generation, Vec::new()));
+        doc.reset_modified();
+        assert_eq!(doc.unsaved_lines(), &[0, 2]);
+        let transaction = Transaction::change(doc.text(), [(4, 4, Some("e".into()))].into_iter());
+        doc.apply(&transaction, view);
+        doc.set_saved(SavedEvent {
+            doc_id: doc.id(),
+            generation: doc.save_generation,
+        });
+        assert_eq!(doc.unsaved_lines(), &[2]);
+
+        // undoing back to the saved revision
+        doc.append_changes_to_history(view);
+        doc.undo(view);
+        assert!(doc.unsaved_lines().is_empty());
+    }
+
     #[test]
     fn changeset_to_changes_ignore_line_endings() {
         use helix_lsp::{lsp, Client, OffsetEncoding};
@@ -1,10 +1,12 @@
 use crate::{
-    clipboard::{get_clipboard_provider, ClipboardProvider},
+    clipboard::{get_clipboard_provider, ClipboardConfig, ClipboardProvider, NopProvider},
     document::SCRATCH_BUFFER_NAME,
     graphics::{CursorKind, Rect},
+    gutter::{Gutter, GutterKind, GutterRegistry},
     input::KeyEvent,
     theme::{self, Theme},
     tree::{self, Tree},
+    vcs::{self, DiffRequest, DiffResult},
     Document, DocumentId, View, ViewId,
 };
 
@@ -18,6 +20,7 @@ use std::{
     sync::Arc,
 };
 
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::time::{sleep, Duration, Instant, Sleep};
 
 use anyhow::{bail, Error};
@@ -25,11 +28,11 @@ use anyhow::{bail, Error};
 pub use helix_core::diagnostic::Severity;
 pub use helix_core::register::Registers;
 use helix_core::syntax;
-use helix_core::{Position, Selection};
+use helix_core::{Position, Rope, Selection};
 
 use serde::Deserialize;
 
-fn deserialize_duration_millis<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+pub(crate) fn deserialize_duration_millis<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
@@ -77,6 +80,218 @@ impl Default for FilePickerConfig {
     }
 }
 
+/// Settings of individual gutters, read from the `[editor.gutter]` section of the config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct GutterConfig {
+    /// Width of the `spacer` gutter. Defaults to 1.
+    pub spacer_width: usize,
+    /// Character drawn by the `separator` gutter. Has to be one cell wide. Defaults to `│`.
+    #[serde(deserialize_with = "deserialize_gutter_separator")]
+    pub separator: char,
+    /// Width of the `diagnostics` gutter. If it's wider than 1, lines with several diagnostics
+    /// show their number instead of a symbol if it fits. Defaults to 1.
+    pub diagnostics_width: usize,
+    /// Size the `line-numbers` gutter to the largest visible line number instead of the
+    /// document's line count. Defaults to false.
+    pub line_numbers_fit_view: bool,
+    /// Smallest width of the `line-numbers` gutter, so that the text doesn't shift when files
+    /// of different lengths are opened. Defaults to 5. Set it to 0 to size the gutter to the
+    /// line numbers.
+    pub line_numbers_min_width: usize,
+    /// Color the line numbers of lines with a cursor by the highest severity of the diagnostics
+    /// on the line. Defaults to false.
+    pub line_numbers_diagnostic_color: bool,
+    /// Radix the line numbers are shown in. Defaults to decimal.
+    pub line_numbers_radix: LineNumberRadix,
+    /// Side of the `line-numbers` gutter the line numbers are aligned to. Defaults to right.
+    pub line_numbers_align: LineNumberAlign,
+    /// Dim the line numbers of lines further than this from the line of the primary cursor.
+    /// Defaults to `None`, not dimming any.
+    pub line_numbers_dim_distance: Option<usize>,
+    /// Separator put between groups of three decimal or four hexadecimal digits of line
+    /// numbers, e.g. `,` or a thin space. Has to be one cell wide. Defaults to `None`, not
+    /// grouping digits.
+    #[serde(deserialize_with = "deserialize_group_separator")]
+    pub line_numbers_group_separator: Option<char>,
+    /// Shown by the `line-numbers` gutter on the empty line after a trailing line ending. At most
+    /// one cell wide, empty to show nothing. Defaults to `~`.
+    #[serde(deserialize_with = "deserialize_end_of_buffer_marker")]
+    pub end_of_buffer_marker: String,
+    /// Width of the `blame` gutter. One column less of the commit hash is shown, to leave a
+    /// space after it. Defaults to 8.
+    pub blame_width: usize,
+    /// Ask the language server for the quick fixes of a document's diagnostics whenever they are
+    /// published, in a single request, and mark the diagnostics that have some with the `action`
+    /// diagnostic symbol. Defaults to false.
+    pub diagnostics_code_actions: bool,
+    /// Show the symbols of the two highest severities side by side on lines with diagnostics of
+    /// different severities, if the `diagnostics` gutter is at least 2 wide. Defaults to false.
+    pub diagnostics_stacked: bool,
+    /// Leave lines out of the `diagnostics` gutter whose diagnostics are all less severe than
+    /// this. The diagnostics are still shown elsewhere. Defaults to `hint`, showing all of them.
+    pub diagnostics_min_severity: Severity,
+    /// Sources of diagnostics, e.g. `rustc`, whose diagnostics the `diagnostics` gutter shows
+    /// rather than those of sources listed later or not at all, however severe they are. Among
+    /// the diagnostics of one source the most severe one is shown. Defaults to no sources,
+    /// showing the most severe diagnostic.
+    pub diagnostics_source_priority: Vec<String>,
+}
+
+impl Default for GutterConfig {
+    fn default() -> Self {
+        Self {
+            spacer_width: 1,
+            separator: '│',
+            diagnostics_width: 1,
+            line_numbers_fit_view: false,
+            line_numbers_min_width: 5,
+            line_numbers_diagnostic_color: false,
+            line_numbers_radix: LineNumberRadix::Decimal,
+            line_numbers_align: LineNumberAlign::Right,
+            line_numbers_dim_distance: None,
+            line_numbers_group_separator: None,
+            end_of_buffer_marker: "~".to_owned(),
+            blame_width: 8,
+            diagnostics_code_actions: false,
+            diagnostics_stacked: false,
+            diagnostics_min_severity: Severity::Hint,
+            diagnostics_source_priority: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineNumberRadix {
+    Decimal,
+    Hexadecimal,
+}
+
+impl LineNumberRadix {
+    pub fn base(self) -> usize {
+        match self {
+            Self::Decimal => 10,
+            Self::Hexadecimal => 16,
+        }
+    }
+
+    /// Number of digits between the separators of
+    /// [`GutterConfig::line_numbers_group_separator`].
+    pub fn group_len(self) -> usize {
+        match self {
+            Self::Decimal => 3,
+            Self::Hexadecimal => 4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineNumberAlign {
+    Right,
+    Left,
+}
+
+/// Symbols the diagnostic gutter shows for each severity. Symbols have to be one cell wide to
+/// fit the gutter, wider ones are rejected when loading the config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct DiagnosticSymbols {
+    #[serde(deserialize_with = "deserialize_gutter_symbol")]
+    pub error: String,
+    #[serde(deserialize_with = "deserialize_gutter_symbol")]
+    pub warning: String,
+    #[serde(deserialize_with = "deserialize_gutter_symbol")]
+    pub info: String,
+    #[serde(deserialize_with = "deserialize_gutter_symbol")]
+    pub hint: String,
+    /// Shown instead of the severity symbol for diagnostics with quick fixes, see
+    /// [`GutterConfig::diagnostics_code_actions`].
+    #[serde(deserialize_with = "deserialize_gutter_symbol")]
+    pub action: String,
+}
+
+fn deserialize_gutter_symbol<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use helix_core::unicode::width::UnicodeWidthStr;
+
+    let symbol = String::deserialize(deserializer)?;
+    let width = symbol.width();
+    if width != 1 {
+        return Err(serde::de::Error::custom(format!(
+            "gutter symbol {:?} is {} cells wide, but has to be 1 cell wide",
+            symbol, width
+        )));
+    }
+    Ok(symbol)
+}
+
+fn deserialize_gutter_separator<'de, D>(deserializer: D) -> Result<char, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use helix_core::unicode::width::UnicodeWidthChar;
+
+    let separator = char::deserialize(deserializer)?;
+    if separator.width() != Some(1) {
+        return Err(serde::de::Error::custom(format!(
+            "gutter separator {:?} has to be 1 cell wide",
+            separator
+        )));
+    }
+    Ok(separator)
+}
+
+fn deserialize_group_separator<'de, D>(deserializer: D) -> Result<Option<char>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use helix_core::unicode::width::UnicodeWidthChar;
+
+    let separator = Option::<char>::deserialize(deserializer)?;
+    if let Some(separator) = separator {
+        if separator.width() != Some(1) {
+            return Err(serde::de::Error::custom(format!(
+                "line number group separator {:?} has to be 1 cell wide",
+                separator
+            )));
+        }
+    }
+    Ok(separator)
+}
+
+fn deserialize_end_of_buffer_marker<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use helix_core::unicode::width::UnicodeWidthStr;
+
+    let marker = String::deserialize(deserializer)?;
+    let width = marker.width();
+    if width > 1 {
+        return Err(serde::de::Error::custom(format!(
+            "end of buffer marker {:?} is {} cells wide, but can be at most 1 cell wide",
+            marker, width
+        )));
+    }
+    Ok(marker)
+}
+
+impl Default for DiagnosticSymbols {
+    fn default() -> Self {
+        Self {
+            error: "●".to_owned(),
+            warning: "●".to_owned(),
+            info: "●".to_owned(),
+            hint: "●".to_owned(),
+            action: "◆".to_owned(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
 pub struct Config {
@@ -107,6 +322,11 @@ pub struct Config {
     pub file_picker: FilePickerConfig,
     /// Set to `true` to override automatic detection of terminal truecolor support in the event of a false negative. Defaults to `false`.
     pub true_color: bool,
+    pub clipboard: ClipboardConfig,
+    /// Gutters to show, from left to right. Defaults to diagnostics, line numbers and diff.
+    pub gutters: Vec<String>,
+    pub gutter: GutterConfig,
+    pub diagnostic_symbols: DiagnosticSymbols,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
@@ -117,6 +337,9 @@ pub enum LineNumber {
 
     /// Show relative line number to the primary cursor
     Relative,
+
+    /// Show relative line number to the primary cursor, including `0` on the cursor line
+    RelativeZero,
 }
 
 impl Default for Config {
@@ -140,6 +363,10 @@ impl Default for Config {
             auto_info: true,
             file_picker: FilePickerConfig::default(),
             true_color: false,
+            clipboard: ClipboardConfig::default(),
+            gutters: vec!["diagnostics".to_owned(), "line-numbers".to_owned()],
+            gutter: GutterConfig::default(),
+            diagnostic_symbols: DiagnosticSymbols::default(),
         }
     }
 }
@@ -167,6 +394,7 @@ pub struct Editor {
     pub macro_recording: Option<(char, Vec<KeyEvent>)>,
     pub theme: Theme,
     pub language_servers: helix_lsp::Registry,
+    /// Detected once on startup, see [`Editor::refresh_clipboard_provider`].
     pub clipboard_provider: Box<dyn ClipboardProvider>,
 
     pub syn_loader: Arc<syntax::Loader>,
@@ -175,10 +403,24 @@ pub struct Editor {
     pub status_msg: Option<(String, Severity)>,
 
     pub config: Config,
+    /// Gutters of new views, resolved from `config.gutters` and extended by
+    /// [`Editor::register_gutter`].
+    pub gutters: GutterRegistry,
 
     pub idle_timer: Pin<Box<Sleep>>,
     pub last_motion: Option<Motion>,
 
+    /// Blame of opened documents along with the version they were requested at, loaded in the
+    /// background for the blame gutter.
+    pub blame_receiver: UnboundedReceiver<(DocumentId, i32, Option<Vec<String>>)>,
+    blame_sender: UnboundedSender<(DocumentId, i32, Option<Vec<String>>)>,
+    /// Diff bases of opened documents, loaded in the background.
+    pub diff_base_receiver: UnboundedReceiver<(DocumentId, Option<Rope>)>,
+    diff_base_sender: UnboundedSender<(DocumentId, Option<Rope>)>,
+    /// Line diffs computed in the background, see [`vcs::diff_worker`].
+    pub line_diff_receiver: UnboundedReceiver<DiffResult>,
+    diff_requests: UnboundedSender<DiffRequest>,
+
     pub exit_code: i32,
 }
 
@@ -202,6 +444,33 @@ impl Editor {
         // HAXX: offset the render area height by 1 to account for prompt/commandline
         area.height -= 1;
 
+        let (clipboard_provider, mut status_msg) = match get_clipboard_provider(&config.clipboard) {
+            Ok(provider) => (provider, None),
+            Err(err) => {
+                log::error!("Failed to set up the clipboard provider: {}", err);
+                let provider: Box<dyn ClipboardProvider> = Box::new(NopProvider::new());
+                (provider, Some((err.to_string(), Severity::Error)))
+            }
+        };
+
+        let mut gutters = GutterRegistry::default();
+        for name in &config.gutters {
+            match Gutter::from_name(name) {
+                Some(gutter) => gutters.push(gutter),
+                None => {
+                    let msg = format!("Unknown gutter {}", name);
+                    log::warn!("{}", msg);
+                    status_msg.get_or_insert((msg, Severity::Warning));
+                }
+            }
+        }
+
+        let (blame_sender, blame_receiver) = unbounded_channel();
+        let (diff_base_sender, diff_base_receiver) = unbounded_channel();
+        let (diff_requests, requests) = unbounded_channel();
+        let (line_diffs, line_diff_receiver) = unbounded_channel();
+        tokio::spawn(vcs::diff_worker(requests, line_diffs));
+
         Self {
             tree: Tree::new(area),
             next_document_id: DocumentId::default(),
@@ -214,11 +483,18 @@ impl Editor {
             syn_loader,
             theme_loader,
             registers: Registers::default(),
-            clipboard_provider: get_clipboard_provider(),
-            status_msg: None,
+            clipboard_provider,
+            status_msg,
             idle_timer: Box::pin(sleep(config.idle_timeout)),
             last_motion: None,
+            blame_receiver,
+            blame_sender,
+            diff_base_receiver,
+            diff_base_sender,
+            line_diff_receiver,
+            diff_requests,
             config,
+            gutters,
             exit_code: 0,
         }
     }
@@ -248,6 +524,64 @@ impl Editor {
         self.status_msg = Some((error, Severity::Error));
     }
 
+    /// Name of the clipboard provider in use, e.g. `xclip` or `none`.
+    pub fn clipboard_provider_name(&self) -> String {
+        self.clipboard_provider.name().into_owned()
+    }
+
+    /// Whether the clipboard provider in use has a primary selection.
+    pub fn clipboard_supports_primary(&self) -> bool {
+        self.clipboard_provider.supports_primary()
+    }
+
+    /// Detects the clipboard provider again, e.g. after clipboard tools were installed.
+    /// Keeps the current provider if detection fails.
+    pub fn refresh_clipboard_provider(&mut self) -> anyhow::Result<()> {
+        self.clipboard_provider = get_clipboard_provider(&self.config.clipboard)?;
+        Ok(())
+    }
+
+    /// Replaces the clipboard provider and returns the previous one, so that it can be put back
+    /// later.
+    pub fn replace_clipboard_provider(
+        &mut self,
+        provider: Box<dyn ClipboardProvider>,
+    ) -> Box<dyn ClipboardProvider> {
+        std::mem::replace(&mut self.clipboard_provider, provider)
+    }
+
+    /// Runs `f` with `provider` as the clipboard provider, e.g. a
+    /// [`FileProvider`](crate::clipboard::FileProvider) in tests, and restores the
+    /// previous provider afterwards. Returns what `f` returned and `provider`, to check what
+    /// was copied.
+    pub fn with_clipboard_provider<T>(
+        &mut self,
+        provider: Box<dyn ClipboardProvider>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> (T, Box<dyn ClipboardProvider>) {
+        let previous = self.replace_clipboard_provider(provider);
+        let result = f(self);
+        (result, self.replace_clipboard_provider(previous))
+    }
+
+    /// Shows `gutter` to the right of the other gutters in all views, including ones opened
+    /// later. Replaces a gutter of the same name in place.
+    pub fn register_gutter(&mut self, gutter: impl Into<GutterKind>) {
+        let gutter = gutter.into();
+        for (view, _) in self.tree.views_mut() {
+            view.gutters.register(gutter.clone());
+        }
+        self.gutters.register(gutter);
+    }
+
+    /// Removes the gutter called `name` from all views.
+    pub fn unregister_gutter(&mut self, name: &str) {
+        for (view, _) in self.tree.views_mut() {
+            view.gutters.unregister(name);
+        }
+        self.gutters.unregister(name);
+    }
+
     pub fn set_theme(&mut self, theme: Theme) {
         // `ui.selection` is the only scope required to be able to render a theme.
         if theme.find_scope_index("ui.selection").is_none() {
@@ -318,7 +652,7 @@ impl Editor {
     fn _refresh(&mut self) {
         for (view, _) in self.tree.views_mut() {
             let doc = &self.documents[&view.doc];
-            view.ensure_cursor_in_view(doc, self.config.scrolloff)
+            view.ensure_cursor_in_view(doc, &self.theme, self.config.scrolloff)
         }
     }
 
@@ -339,7 +673,7 @@ impl Editor {
             .primary()
             .cursor(doc.text().slice(..));
         let line = doc.text().char_to_line(pos);
-        view.offset.row = line.saturating_sub(view.inner_area().height as usize / 2);
+        view.offset.row = line.saturating_sub(view.inner_height() / 2);
     }
 
     pub fn switch(&mut self, id: DocumentId, action: Action) {
@@ -402,7 +736,7 @@ impl Editor {
                 return;
             }
             Action::HorizontalSplit | Action::VerticalSplit => {
-                let view = View::new(id);
+                let view = View::new(id, self.gutters.clone(), self.config.gutter.clone());
                 let view_id = self.tree.split(
                     view,
                     match action {
@@ -427,6 +761,7 @@ impl Editor {
         self.next_document_id =
             DocumentId(unsafe { NonZeroUsize::new_unchecked(self.next_document_id.0.get() + 1) });
         doc.id = id;
+        doc.set_diff_requests(self.diff_requests.clone());
         self.documents.insert(id, doc);
         id
     }
@@ -457,13 +792,38 @@ impl Editor {
 
             let _ = Self::launch_language_server(&mut self.language_servers, &mut doc);
 
-            self.new_document(doc)
+            let id = self.new_document(doc);
+            let sender = self.diff_base_sender.clone();
+            tokio::spawn(async move {
+                let _ = sender.send((id, vcs::diff_base(path).await));
+            });
+            self.refresh_blame(id);
+            id
         };
 
         self.switch(id, action);
         Ok(id)
     }
 
+    /// Loads the blame of a document in the background if the blame gutter is used, after
+    /// the document's file changed.
+    pub fn refresh_blame(&self, doc_id: DocumentId) {
+        if !self.gutters.contains("blame") {
+            return;
+        }
+        let doc = match self.documents.get(&doc_id) {
+            Some(doc) => doc,
+            None => return,
+        };
+        if let Some(path) = doc.path().cloned() {
+            let sender = self.blame_sender.clone();
+            let version = doc.version();
+            tokio::spawn(async move {
+                let _ = sender.send((doc_id, version, vcs::blame(path).await));
+            });
+        }
+    }
+
     pub fn close(&mut self, id: ViewId) {
         let view = self.tree.get(self.tree.focus);
         // remove selection
@@ -524,7 +884,7 @@ impl Editor {
                 .map(|(&doc_id, _)| doc_id)
                 .next()
                 .unwrap_or_else(|| self.new_document(Document::default()));
-            let view = View::new(doc_id);
+            let view = View::new(doc_id, self.gutters.clone(), self.config.gutter.clone());
             let view_id = self.tree.insert(view);
             let doc = self.documents.get_mut(&doc_id).unwrap();
             doc.selections.insert(view_id, Selection::point(0));
@@ -568,7 +928,7 @@ impl Editor {
     pub fn ensure_cursor_in_view(&mut self, id: ViewId) {
         let view = self.tree.get_mut(id);
         let doc = &self.documents[&view.doc];
-        view.ensure_cursor_in_view(doc, self.config.scrolloff)
+        view.ensure_cursor_in_view(doc, &self.theme, self.config.scrolloff)
     }
 
     #[inline]
@@ -608,7 +968,7 @@ impl Editor {
             .primary()
             .cursor(doc.text().slice(..));
         if let Some(mut pos) = view.screen_coords_at_pos(doc, doc.text().slice(..), cursor) {
-            let inner = view.inner_area();
+            let inner = view.inner_area(doc, &self.theme);
             pos.col += inner.x as usize;
             pos.row += inner.y as usize;
             (Some(pos), CursorKind::Hidden)
@@ -1,39 +1,996 @@
+use std::cmp::Reverse;
 use std::fmt::Write;
+use std::sync::Arc;
 
-use crate::{editor::Config, graphics::Style, Document, Theme, View};
+use helix_core::{
+    diagnostic::{Diagnostic, DiagnosticTag, Severity},
+    unicode::width::UnicodeWidthStr,
+    Range, Selection,
+};
+use helix_lsp::lsp::SymbolKind;
 
-pub type GutterFn<'doc> = Box<dyn Fn(usize, bool, &mut String) -> Option<Style> + 'doc>;
-pub type Gutter =
+use crate::{
+    editor::{Config, LineNumberAlign, LineNumberRadix},
+    graphics::{Modifier, Style},
+    Document, Editor, Theme, View,
+};
+
+/// Writes the gutter contents of a row to the [`GutterText`] and returns their style. Takes the
+/// line of the row, whether a cursor is on that line and whether the row is a wrapped
+/// continuation of the line rather than its first row.
+pub type GutterFn<'doc> = Box<dyn Fn(usize, bool, bool, &mut GutterText) -> Option<Style> + 'doc>;
+pub type GutterRender =
     for<'doc> fn(&'doc Document, &View, &Theme, &Config, bool, usize) -> GutterFn<'doc>;
 
+/// The contents of a gutter row. Text is written with [`std::fmt::Write`] in the style returned
+/// by the [`GutterFn`], until [`GutterText::set_style`] patches another style over it.
+#[derive(Debug, Default)]
+pub struct GutterText {
+    text: String,
+    /// Byte offsets into `text` at which a style starts, in ascending order.
+    styles: Vec<(usize, Style)>,
+}
+
+impl GutterText {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            text: String::with_capacity(capacity),
+            styles: Vec::new(),
+        }
+    }
+
+    /// Patches `style` over the style of the row for the text written after this call.
+    pub fn set_style(&mut self, style: Style) {
+        self.styles.push((self.text.len(), style));
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.text.push(c);
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.text.reserve(additional);
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.styles.clear();
+    }
+
+    /// Splits the text into its differently styled parts, with their styles patched over `base`.
+    pub fn spans(&self, base: Style) -> impl Iterator<Item = (&str, Style)> {
+        let starts = std::iter::once((0, base)).chain(
+            self.styles
+                .iter()
+                .map(move |&(offset, style)| (offset, base.patch(style))),
+        );
+        let ends = self
+            .styles
+            .iter()
+            .map(|&(offset, _)| offset)
+            .chain(std::iter::once(self.text.len()));
+        starts
+            .zip(ends)
+            .filter(|&((start, _), end)| end > start)
+            .map(move |((start, style), end)| (&self.text[start..end], style))
+    }
+}
+
+impl Write for GutterText {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.text.push_str(s);
+        Ok(())
+    }
+}
+
+/// A column next to the text of a view.
+#[derive(Clone, Copy)]
+pub struct Gutter {
+    /// Name used to pick the gutter in the `gutters` config option.
+    pub name: &'static str,
+    /// Builds the closure that writes the gutter contents of a line and returns their style.
+    /// Lines for which it returns `None` are left empty.
+    pub render: GutterRender,
+    /// Number of cells the gutter takes up, which may depend on themed symbols. Gutters with a
+    /// width of 0 are not rendered.
+    pub width: fn(&View, &Document, &Theme) -> usize,
+    /// Called with the clicked line when the gutter is clicked, after focusing the view.
+    pub on_click: Option<fn(&mut Editor, usize)>,
+}
+
+/// Collapses to zero width while the document has no diagnostics it would show.
+pub const DIAGNOSTIC_GUTTER: Gutter = Gutter {
+    name: "diagnostics",
+    render: diagnostic,
+    width: |view, doc, _| match doc.max_diagnostic_severity() {
+        Some(severity) if severity >= view.gutter_config.diagnostics_min_severity => {
+            view.gutter_config.diagnostics_width
+        }
+        _ => 0,
+    },
+    on_click: Some(jump_to_diagnostic),
+};
+
+pub const LINE_NUMBER_GUTTER: Gutter = Gutter {
+    name: "line-numbers",
+    render: line_number,
+    width: |view, doc, _| {
+        let radix = view.gutter_config.line_numbers_radix;
+        let base = radix.base();
+        let digits = if view.gutter_config.line_numbers_fit_view {
+            digits_in_base(view.last_line(doc) + 1, base)
+        } else if base == 10 {
+            doc.line_number_digits()
+        } else {
+            digits_in_base(doc.text().len_lines(), base)
+        };
+        let width = match view.gutter_config.line_numbers_group_separator {
+            Some(_) => digits + (digits - 1) / radix.group_len(),
+            None => digits,
+        };
+        width.max(view.gutter_config.line_numbers_min_width)
+    },
+    on_click: None,
+};
+
+pub const DIFF_GUTTER: Gutter = Gutter {
+    name: "diff",
+    render: diff,
+    width: |_, _, _| 1,
+    on_click: None,
+};
+
+/// Marks the lines changed since the document was last saved.
+pub const MODIFIED_GUTTER: Gutter = Gutter {
+    name: "modified",
+    render: modified,
+    width: |_, _, _| 1,
+    on_click: None,
+};
+
+pub const BREAKPOINT_GUTTER: Gutter = Gutter {
+    name: "breakpoints",
+    render: breakpoint,
+    width: |_, _, _| 1,
+    on_click: None,
+};
+
+pub const MARK_GUTTER: Gutter = Gutter {
+    name: "marks",
+    render: mark,
+    width: |_, _, _| 1,
+    on_click: None,
+};
+
+/// Marks the lines with cursors other than the primary one. Collapses to zero width while there
+/// is only one cursor.
+pub const CURSOR_GUTTER: Gutter = Gutter {
+    name: "cursors",
+    render: cursor,
+    width: |view, doc, _| {
+        if doc.selection(view.id).len() > 1 {
+            1
+        } else {
+            0
+        }
+    },
+    on_click: None,
+};
+
+/// Marks the lines with matches of the search being typed. Collapses to zero width while no
+/// search is active.
+pub const SEARCH_GUTTER: Gutter = Gutter {
+    name: "search",
+    render: search,
+    width: |_, doc, _| {
+        if doc.search_matches().is_some() {
+            1
+        } else {
+            0
+        }
+    },
+    on_click: None,
+};
+
+/// Marks the lines the view's jumplist points to in the document. Collapses to zero width while
+/// no jump points to the document.
+pub const JUMP_GUTTER: Gutter = Gutter {
+    name: "jumps",
+    render: jump,
+    width: |view, doc, _| {
+        if view.jumps.iter().any(|(id, _)| *id == doc.id()) {
+            1
+        } else {
+            0
+        }
+    },
+    on_click: None,
+};
+
+/// Shows the kind of the symbol starting on each line, as reported by the language server.
+pub const SYMBOL_GUTTER: Gutter = Gutter {
+    name: "symbols",
+    render: symbol,
+    width: |_, _, _| 1,
+    on_click: None,
+};
+
+/// Marks the folds set with [`View::set_folds`]. Nothing creates folds until the editor can fold
+/// text, so it's left out of [`GUTTERS`] and can only be added to a view directly.
+pub const FOLD_GUTTER: Gutter = Gutter {
+    name: "folds",
+    render: fold,
+    width: |_, _, _| 1,
+    on_click: None,
+};
+
+pub const BLAME_GUTTER: Gutter = Gutter {
+    name: "blame",
+    render: blame,
+    width: |view, _, _| view.gutter_config.blame_width,
+    on_click: None,
+};
+
+/// Empty column to put space between other gutters.
+pub const SPACER_GUTTER: Gutter = Gutter {
+    name: "spacer",
+    render: spacer,
+    width: |view, _, _| view.gutter_config.spacer_width,
+    on_click: None,
+};
+
+/// Line between two other gutters.
+pub const SEPARATOR_GUTTER: Gutter = Gutter {
+    name: "separator",
+    render: separator,
+    width: |_, _, _| 1,
+    on_click: None,
+};
+
+/// All gutters that can be enabled through the `gutters` config option.
+pub const GUTTERS: &[Gutter] = &[
+    DIAGNOSTIC_GUTTER,
+    LINE_NUMBER_GUTTER,
+    DIFF_GUTTER,
+    MODIFIED_GUTTER,
+    BREAKPOINT_GUTTER,
+    MARK_GUTTER,
+    CURSOR_GUTTER,
+    SEARCH_GUTTER,
+    JUMP_GUTTER,
+    SYMBOL_GUTTER,
+    BLAME_GUTTER,
+    SPACER_GUTTER,
+    SEPARATOR_GUTTER,
+];
+
+impl Gutter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        GUTTERS.iter().find(|gutter| gutter.name == name).copied()
+    }
+
+    /// Renders the first row of `line` like the editor does for a focused `view`, for testing
+    /// gutters in isolation. See [`GutterKind::render_line`].
+    pub fn render_line(
+        &self,
+        doc: &Document,
+        view: &View,
+        theme: &Theme,
+        config: &Config,
+        line: usize,
+    ) -> (String, Option<Style>) {
+        GutterKind::from(*self).render_line(doc, view, theme, config, line)
+    }
+}
+
+impl std::fmt::Debug for Gutter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Gutter").field(&self.name).finish()
+    }
+}
+
+/// A gutter registered at runtime, e.g. by a plugin. Unlike a [`Gutter`] it can hold state,
+/// which it has to synchronize itself as it is shared between all views.
+///
+/// For every view it is shown in, the editor asks for the [`width`](Self::width) of the gutter
+/// on each render and for each mouse click. A width of 0 hides the gutter and `render` is not
+/// called. Otherwise [`render`](Self::render) is called once per render with that width, and
+/// the returned [`GutterFn`] once for each visible row, top to bottom. Text it writes past the
+/// width is cut off, and rows it returns `None` for are left empty.
+pub trait DynamicGutter: Send + Sync {
+    /// Name of the gutter, unique among registered gutters.
+    fn name(&self) -> &str;
+
+    /// Number of cells the gutter takes up in `view` with the current `theme`.
+    fn width(&self, view: &View, doc: &Document, theme: &Theme) -> usize;
+
+    /// Builds the closure that writes the gutter contents of a line and returns their style.
+    fn render<'doc>(
+        &'doc self,
+        doc: &'doc Document,
+        view: &View,
+        theme: &Theme,
+        config: &Config,
+        is_focused: bool,
+        width: usize,
+    ) -> GutterFn<'doc>;
+
+    /// Called with the clicked line when the gutter is clicked, after focusing the view. Does
+    /// nothing by default.
+    fn on_click(&self, _editor: &mut Editor, _line: usize) {}
+}
+
+/// A built-in [`Gutter`] or one registered at runtime.
+#[derive(Clone)]
+pub enum GutterKind {
+    Static(Gutter),
+    Dynamic(Arc<dyn DynamicGutter>),
+}
+
+impl GutterKind {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Static(gutter) => gutter.name,
+            Self::Dynamic(gutter) => gutter.name(),
+        }
+    }
+
+    pub fn width(&self, view: &View, doc: &Document, theme: &Theme) -> usize {
+        match self {
+            Self::Static(gutter) => (gutter.width)(view, doc, theme),
+            Self::Dynamic(gutter) => gutter.width(view, doc, theme),
+        }
+    }
+
+    pub fn render<'doc>(
+        &'doc self,
+        doc: &'doc Document,
+        view: &View,
+        theme: &Theme,
+        config: &Config,
+        is_focused: bool,
+        width: usize,
+    ) -> GutterFn<'doc> {
+        match self {
+            Self::Static(gutter) => (gutter.render)(doc, view, theme, config, is_focused, width),
+            Self::Dynamic(gutter) => gutter.render(doc, view, theme, config, is_focused, width),
+        }
+    }
+
+    pub fn on_click(&self, editor: &mut Editor, line: usize) {
+        match self {
+            Self::Static(gutter) => {
+                if let Some(on_click) = gutter.on_click {
+                    on_click(editor, line);
+                }
+            }
+            Self::Dynamic(gutter) => gutter.on_click(editor, line),
+        }
+    }
+
+    /// Renders the first row of `line` like the editor does for a focused `view`, for testing
+    /// gutters in isolation. Returns the written text and its style, which is `None` if the
+    /// gutter leaves the line empty. The line counts as selected if a cursor is on it.
+    pub fn render_line(
+        &self,
+        doc: &Document,
+        view: &View,
+        theme: &Theme,
+        config: &Config,
+        line: usize,
+    ) -> (String, Option<Style>) {
+        let text = doc.text().slice(..);
+        let selected = doc
+            .selection(view.id)
+            .iter()
+            .any(|range| range.cursor_line(text) == line);
+        let width = self.width(view, doc, theme);
+        let render = self.render(doc, view, theme, config, true, width);
+        let mut out = GutterText::default();
+        let style = render(line, selected, false, &mut out);
+        (out.text, style)
+    }
+}
+
+impl From<Gutter> for GutterKind {
+    fn from(gutter: Gutter) -> Self {
+        Self::Static(gutter)
+    }
+}
+
+impl<T: DynamicGutter + 'static> From<Arc<T>> for GutterKind {
+    fn from(gutter: Arc<T>) -> Self {
+        Self::Dynamic(gutter)
+    }
+}
+
+impl std::fmt::Debug for GutterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Static(gutter) => gutter.fmt(f),
+            Self::Dynamic(gutter) => f
+                .debug_tuple("DynamicGutter")
+                .field(&gutter.name())
+                .finish(),
+        }
+    }
+}
+
+/// The gutters of a view, from left to right.
+#[derive(Debug, Clone, Default)]
+pub struct GutterRegistry {
+    gutters: Vec<GutterKind>,
+}
+
+impl GutterRegistry {
+    /// Adds `gutter` to the right of the other gutters, even if a gutter of the same name is
+    /// shown already, like a second separator.
+    pub fn push(&mut self, gutter: impl Into<GutterKind>) {
+        self.gutters.push(gutter.into());
+    }
+
+    /// Adds `gutter` to the right of the other gutters, or replaces the gutter of the same name.
+    pub fn register(&mut self, gutter: impl Into<GutterKind>) {
+        let gutter = gutter.into();
+        match self
+            .gutters
+            .iter_mut()
+            .find(|registered| registered.name() == gutter.name())
+        {
+            Some(registered) => *registered = gutter,
+            None => self.gutters.push(gutter),
+        }
+    }
+
+    /// Removes the first gutter called `name` and returns it.
+    pub fn unregister(&mut self, name: &str) -> Option<GutterKind> {
+        let index = self
+            .gutters
+            .iter()
+            .position(|gutter| gutter.name() == name)?;
+        Some(self.gutters.remove(index))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.gutters.iter().any(|gutter| gutter.name() == name)
+    }
+
+    pub fn as_slice(&self) -> &[GutterKind] {
+        &self.gutters
+    }
+}
+
+impl From<Vec<Gutter>> for GutterRegistry {
+    fn from(gutters: Vec<Gutter>) -> Self {
+        Self {
+            gutters: gutters.into_iter().map(GutterKind::from).collect(),
+        }
+    }
+}
+
 pub fn diagnostic<'doc>(
     doc: &'doc Document,
-    _view: &View,
+    view: &View,
     theme: &Theme,
-    _config: &Config,
+    config: &Config,
     _is_focused: bool,
-    _width: usize,
+    width: usize,
 ) -> GutterFn<'doc> {
     let warning = theme.get("warning");
     let error = theme.get("error");
     let info = theme.get("info");
     let hint = theme.get("hint");
+    let action = theme.get("ui.gutter.action");
+    let unnecessary = theme.try_get("ui.gutter.unnecessary");
+    let deprecated = theme.try_get("ui.gutter.deprecated");
     let diagnostics = doc.diagnostics();
+    let code_actions = view.gutter_config.diagnostics_code_actions;
+    let stacked = view.gutter_config.diagnostics_stacked && width > 1;
+    let min_severity = view.gutter_config.diagnostics_min_severity;
+    let source_priority = view.gutter_config.diagnostics_source_priority.clone();
 
-    Box::new(move |line: usize, _selected: bool, out: &mut String| {
-        use helix_core::diagnostic::Severity;
-        if let Ok(index) = diagnostics.binary_search_by_key(&line, |d| d.line) {
-            let diagnostic = &diagnostics[index];
-            write!(out, "●").unwrap();
-            return Some(match diagnostic.severity {
-                Some(Severity::Error) => error,
-                Some(Severity::Warning) | None => warning,
-                Some(Severity::Info) => info,
-                Some(Severity::Hint) => hint,
-            });
-        }
-        None
-    })
+    let symbols = &config.diagnostic_symbols;
+    let error_symbol = symbols.error.clone();
+    let warning_symbol = symbols.warning.clone();
+    let info_symbol = symbols.info.clone();
+    let hint_symbol = symbols.hint.clone();
+    let action_symbol = symbols.action.clone();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            let shown = || {
+                line_diagnostics(diagnostics, line)
+                    .filter(|diagnostic| severity_of(diagnostic) >= min_severity)
+            };
+            // sources listed earlier win over more severe diagnostics of other sources
+            let priority = |diagnostic: &Diagnostic| {
+                diagnostic
+                    .source
+                    .as_ref()
+                    .and_then(|source| source_priority.iter().position(|other| other == source))
+                    .unwrap_or(source_priority.len())
+            };
+            let severity = shown()
+                .min_by_key(|&diagnostic| (priority(diagnostic), Reverse(severity_of(diagnostic))))
+                .map(severity_of)?;
+            // the diagnostics of a severity are styled by their tag if they all have it
+            let tag_style = |severity, tag| {
+                shown()
+                    .filter(|&diagnostic| severity_of(diagnostic) == severity)
+                    .all(|diagnostic| diagnostic.tags.contains(&tag))
+            };
+            let symbol_and_style = |severity| {
+                let (symbol, style) = match severity {
+                    Severity::Error => (&error_symbol, error),
+                    Severity::Warning => (&warning_symbol, warning),
+                    Severity::Info => (&info_symbol, info),
+                    Severity::Hint => (&hint_symbol, hint),
+                };
+                let style = deprecated
+                    .filter(|_| tag_style(severity, DiagnosticTag::Deprecated))
+                    .or_else(|| {
+                        unnecessary.filter(|_| tag_style(severity, DiagnosticTag::Unnecessary))
+                    })
+                    .unwrap_or(style);
+                (symbol, style)
+            };
+            let (symbol, style) = symbol_and_style(severity);
+            if stacked {
+                let second = shown()
+                    .map(severity_of)
+                    .filter(|&other| other != severity)
+                    .max();
+                if let Some(second) = second {
+                    let (second_symbol, second_style) = symbol_and_style(second);
+                    write!(out, "{:1$}{2}", "", width - 2, symbol).unwrap();
+                    out.set_style(second_style);
+                    write!(out, "{}", second_symbol).unwrap();
+                    return Some(style);
+                }
+            }
+            let count = shown().count();
+            // a single column always shows the symbol, to keep the default look
+            if width > 1 && count > 1 && digits10(count) <= width {
+                write!(out, "{:>1$}", count, width).unwrap();
+                return Some(style);
+            }
+            // symbols are one cell wide, but may consist of several chars
+            let padding = width.saturating_sub(1);
+            if code_actions && shown().any(|diagnostic| diagnostic.has_code_actions) {
+                write!(out, "{:1$}{2}", "", padding, action_symbol).unwrap();
+                return Some(action);
+            }
+            write!(out, "{:1$}{2}", "", padding, symbol).unwrap();
+            Some(style)
+        },
+    )
+}
+
+/// Returns the diagnostics on `line`. `diagnostics` has to be sorted by line.
+fn line_diagnostics(diagnostics: &[Diagnostic], line: usize) -> impl Iterator<Item = &Diagnostic> {
+    let start = diagnostics.partition_point(|diagnostic| diagnostic.line < line);
+    diagnostics[start..]
+        .iter()
+        .take_while(move |diagnostic| diagnostic.line == line)
+}
+
+/// Returns the severity of `diagnostic`, counting diagnostics without a severity as warnings.
+fn severity_of(diagnostic: &Diagnostic) -> Severity {
+    diagnostic.severity.unwrap_or(Severity::Warning)
+}
+
+/// Selects the first diagnostic on `line` of the focused document and shows its message.
+fn jump_to_diagnostic(editor: &mut Editor, line: usize) {
+    let (view, doc) = crate::current!(editor);
+    let diagnostics = doc.diagnostics();
+    let start = diagnostics.partition_point(|diagnostic| diagnostic.line < line);
+    let diagnostic = match diagnostics
+        .get(start)
+        .filter(|diagnostic| diagnostic.line == line)
+    {
+        Some(diagnostic) => diagnostic,
+        None => return,
+    };
+    let range = diagnostic.range;
+    let message = diagnostic.message.clone();
+    doc.set_selection(view.id, Selection::single(range.start, range.end));
+    editor.set_status(message);
+}
+
+/// Returns the highest severity of the diagnostics on `line`, see [`severity_of`].
+/// `diagnostics` has to be sorted by line.
+fn line_severity(diagnostics: &[Diagnostic], line: usize) -> Option<Severity> {
+    line_diagnostics(diagnostics, line).map(severity_of).max()
+}
+
+pub fn spacer<'doc>(
+    _doc: &'doc Document,
+    _view: &View,
+    _theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    Box::new(|_line: usize, _selected: bool, _is_wrapped: bool, _out: &mut GutterText| None)
+}
+
+pub fn separator<'doc>(
+    _doc: &'doc Document,
+    view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.separator");
+    let separator = view.gutter_config.separator;
+
+    Box::new(
+        move |_line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            out.push(separator);
+            Some(style)
+        },
+    )
+}
+
+/// Shows the start of the hash of the commit that last changed each line. Lines stay blank while
+/// the blame is loaded.
+pub fn blame<'doc>(
+    doc: &'doc Document,
+    _view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.blame");
+    let blame = doc.blame().unwrap_or_default();
+    let len = width.saturating_sub(1);
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            let hash = blame.get(line)?;
+            if hash.is_empty() {
+                return None;
+            }
+            write!(out, "{}", &hash[..len.min(hash.len())]).unwrap();
+            Some(style)
+        },
+    )
+}
+
+pub fn diff<'doc>(
+    doc: &'doc Document,
+    _view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let added = theme.get("diff.plus");
+    let modified = theme.get("diff.delta");
+    let deleted = theme.get("diff.minus");
+    let line_diffs = doc.line_diffs();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            use helix_core::diff::LineDiff;
+            let index = line_diffs
+                .binary_search_by_key(&line, |&(line, _)| line)
+                .ok()?;
+            let (symbol, style) = match line_diffs[index].1 {
+                LineDiff::Added => ("▍", added),
+                LineDiff::Modified => ("▍", modified),
+                LineDiff::Deleted => ("▔", deleted),
+            };
+            write!(out, "{}", symbol).unwrap();
+            Some(style)
+        },
+    )
+}
+
+pub fn modified<'doc>(
+    doc: &'doc Document,
+    _view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.modified");
+    let unsaved_lines = doc.unsaved_lines();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            unsaved_lines.binary_search(&line).ok()?;
+            write!(out, "▍").unwrap();
+            Some(style)
+        },
+    )
+}
+
+pub fn breakpoint<'doc>(
+    doc: &'doc Document,
+    _view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let breakpoint_style = theme.get("ui.debug.breakpoint");
+    let active = theme.get("ui.debug.active");
+    let breakpoints = doc.breakpoints();
+    let active_line = doc.debug_active_line;
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            if active_line == Some(line) {
+                write!(out, "▶").unwrap();
+                return Some(active);
+            }
+            let index = breakpoints
+                .binary_search_by_key(&line, |breakpoint| breakpoint.line)
+                .ok()?;
+            let symbol = match breakpoints[index].condition {
+                Some(_) => "◆",
+                None => "●",
+            };
+            write!(out, "{}", symbol).unwrap();
+            Some(breakpoint_style)
+        },
+    )
+}
+
+pub fn fold<'doc>(
+    _doc: &'doc Document,
+    view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.fold");
+    let folds = view.folds().to_vec();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            let hidden = folds
+                .iter()
+                .take_while(|fold| fold.start < line)
+                .any(|fold| fold.collapsed && line <= fold.end);
+            if hidden {
+                return None;
+            }
+            let start = folds.partition_point(|fold| fold.start < line);
+            let fold = folds.get(start).filter(|fold| fold.start == line)?;
+            let symbol = if fold.collapsed { "▸" } else { "▾" };
+            write!(out, "{}", symbol).unwrap();
+            Some(style)
+        },
+    )
+}
+
+pub fn mark<'doc>(
+    doc: &'doc Document,
+    _view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.mark");
+    let marks = doc.marks();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            let index = marks.binary_search_by_key(&line, |mark| mark.line).ok()?;
+            out.push(marks[index].name);
+            Some(style)
+        },
+    )
+}
+
+pub fn cursor<'doc>(
+    doc: &'doc Document,
+    view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme
+        .try_get("ui.gutter.cursor")
+        .unwrap_or_else(|| theme.get("ui.cursor"));
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+    let primary = selection.primary_index();
+    let mut lines: Vec<_> = selection
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| index != primary)
+        .map(|(_, range)| range.cursor_line(text))
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            lines.binary_search(&line).ok()?;
+            out.push('▸');
+            Some(style)
+        },
+    )
+}
+
+/// Glyphs and theme scope names of symbol kinds.
+const SYMBOL_KINDS: &[(SymbolKind, char, &str)] = &[
+    (SymbolKind::FILE, 'F', "file"),
+    (SymbolKind::MODULE, 'M', "module"),
+    (SymbolKind::NAMESPACE, 'N', "namespace"),
+    (SymbolKind::PACKAGE, 'P', "package"),
+    (SymbolKind::CLASS, 'C', "class"),
+    (SymbolKind::METHOD, 'm', "method"),
+    (SymbolKind::PROPERTY, 'p', "property"),
+    (SymbolKind::FIELD, '.', "field"),
+    (SymbolKind::CONSTRUCTOR, 'c', "constructor"),
+    (SymbolKind::ENUM, 'E', "enum"),
+    (SymbolKind::INTERFACE, 'I', "interface"),
+    (SymbolKind::FUNCTION, 'f', "function"),
+    (SymbolKind::VARIABLE, 'v', "variable"),
+    (SymbolKind::CONSTANT, 'K', "constant"),
+    (SymbolKind::STRING, 's', "string"),
+    (SymbolKind::NUMBER, 'n', "number"),
+    (SymbolKind::BOOLEAN, 'b', "boolean"),
+    (SymbolKind::ARRAY, 'a', "array"),
+    (SymbolKind::OBJECT, 'o', "object"),
+    (SymbolKind::KEY, 'k', "key"),
+    (SymbolKind::NULL, '0', "null"),
+    (SymbolKind::ENUM_MEMBER, 'e', "enum-member"),
+    (SymbolKind::STRUCT, 'S', "struct"),
+    (SymbolKind::EVENT, '!', "event"),
+    (SymbolKind::OPERATOR, '+', "operator"),
+    (SymbolKind::TYPE_PARAMETER, 'T', "type-parameter"),
+];
+
+pub fn search<'doc>(
+    doc: &'doc Document,
+    _view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.search");
+    let lines = doc.search_matches().unwrap_or_default();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            lines.binary_search(&line).ok()?;
+            out.push('•');
+            Some(style)
+        },
+    )
+}
+
+pub fn jump<'doc>(
+    doc: &'doc Document,
+    view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.jump");
+    let text = doc.text().slice(..);
+    // jumps aren't mapped through edits, so they may point past the end of the document
+    let mut lines: Vec<_> = view
+        .jumps
+        .iter()
+        .filter(|(id, _)| *id == doc.id())
+        .map(|(_, selection)| {
+            let range = selection.primary();
+            let len = text.len_chars();
+            let range = Range::new(range.anchor.min(len), range.head.min(len));
+            text.char_to_line(range.cursor(text))
+        })
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            lines.binary_search(&line).ok()?;
+            out.push('›');
+            Some(style)
+        },
+    )
+}
+
+/// Styled with `ui.gutter.symbol.<kind>`, e.g. `ui.gutter.symbol.function`, falling back to
+/// `ui.gutter.symbol`.
+pub fn symbol<'doc>(
+    doc: &'doc Document,
+    _view: &View,
+    theme: &Theme,
+    _config: &Config,
+    _is_focused: bool,
+    _width: usize,
+) -> GutterFn<'doc> {
+    let style = theme.get("ui.gutter.symbol");
+    let kinds: Vec<_> = SYMBOL_KINDS
+        .iter()
+        .map(|&(kind, glyph, name)| {
+            let scope = format!("ui.gutter.symbol.{}", name);
+            (kind, glyph, theme.try_get(&scope).unwrap_or(style))
+        })
+        .collect();
+    let symbols = doc.symbols();
+
+    Box::new(
+        move |line: usize, _selected: bool, _is_wrapped: bool, out: &mut GutterText| {
+            let index = symbols
+                .binary_search_by_key(&line, |symbol| symbol.line)
+                .ok()?;
+            let kind = symbols[index].kind;
+            let (glyph, style) = kinds
+                .iter()
+                .find(|&&(other, _, _)| other == kind)
+                .map_or(('?', style), |&(_, glyph, style)| (glyph, style));
+            out.push(glyph);
+            Some(style)
+        },
+    )
+}
+
+/// Picks the style of a line number instead of `ui.linenr` and `ui.linenr.selected`, given
+/// the line and whether it is selected, or returns `None` to style it as usual.
+pub type LineNumberStyleFn = Arc<dyn Fn(&Document, usize, bool) -> Option<Style> + Send + Sync>;
+
+/// The `line-numbers` gutter with line numbers styled by a [`LineNumberStyleFn`], e.g. to
+/// highlight lines matching a pattern. Registering it with [`Editor::register_gutter`] replaces
+/// the built-in `line-numbers` gutter.
+pub struct StyledLineNumbers {
+    style: LineNumberStyleFn,
+}
+
+impl StyledLineNumbers {
+    pub fn new(style: LineNumberStyleFn) -> Self {
+        Self { style }
+    }
+}
+
+impl DynamicGutter for StyledLineNumbers {
+    fn name(&self) -> &str {
+        LINE_NUMBER_GUTTER.name
+    }
+
+    fn width(&self, view: &View, doc: &Document, theme: &Theme) -> usize {
+        (LINE_NUMBER_GUTTER.width)(view, doc, theme)
+    }
+
+    fn render<'doc>(
+        &'doc self,
+        doc: &'doc Document,
+        view: &View,
+        theme: &Theme,
+        config: &Config,
+        is_focused: bool,
+        width: usize,
+    ) -> GutterFn<'doc> {
+        line_number_styled(
+            doc,
+            view,
+            theme,
+            config,
+            is_focused,
+            width,
+            Some(&self.style),
+        )
+    }
 }
 
 pub fn line_number<'doc>(
@@ -43,47 +1000,189 @@ pub fn line_number<'doc>(
     config: &Config,
     is_focused: bool,
     width: usize,
+) -> GutterFn<'doc> {
+    line_number_styled(doc, view, theme, config, is_focused, width, None)
+}
+
+fn line_number_styled<'doc>(
+    doc: &'doc Document,
+    view: &View,
+    theme: &Theme,
+    config: &Config,
+    is_focused: bool,
+    width: usize,
+    style_override: Option<&'doc LineNumberStyleFn>,
 ) -> GutterFn<'doc> {
     let text = doc.text().slice(..);
-    let last_line = view.last_line(doc);
-    // Whether to draw the line number for the last line of the
-    // document or not.  We only draw it if it's not an empty line.
-    let draw_last = text.line_to_byte(last_line) < text.len_bytes();
+    // The empty line after a trailing line ending isn't a real line, so it gets a `~` instead of
+    // a number. The only line of an empty document is still numbered.
+    let last_line = text.len_lines() - 1;
+    let draw_last = last_line == 0 || text.line_to_byte(last_line) < text.len_bytes();
 
     let linenr = theme.get("ui.linenr");
     let linenr_select: Style = theme.try_get("ui.linenr.selected").unwrap_or(linenr);
+    let linenr_dim = theme
+        .try_get("ui.linenr.dim")
+        .unwrap_or_else(|| linenr.add_modifier(Modifier::DIM));
+    let dim_distance = view.gutter_config.line_numbers_dim_distance;
 
     let current_line = doc
         .text()
         .char_to_line(doc.selection(view.id).primary().cursor(text));
 
+    let diagnostics = doc.diagnostics();
+    let color_by_severity = view.gutter_config.line_numbers_diagnostic_color;
+    let warning = theme.get("warning");
+    let error = theme.get("error");
+    let info = theme.get("info");
+    let hint = theme.get("hint");
+
     let config = config.line_number;
+    let radix = view.gutter_config.line_numbers_radix;
+    let align = view.gutter_config.line_numbers_align;
+    let group_separator = view.gutter_config.line_numbers_group_separator;
+    let end_of_buffer_marker = view.gutter_config.end_of_buffer_marker.clone();
+    // Pads by display width rather than chars, markers can be empty or several chars wide.
+    let write_marker = move |out: &mut GutterText, marker: &str| {
+        let padding = width.saturating_sub(marker.width());
+        match align {
+            LineNumberAlign::Right => write!(out, "{:padding$}{}", "", marker),
+            LineNumberAlign::Left => write!(out, "{}{:padding$}", marker, ""),
+        }
+        .unwrap();
+    };
 
-    Box::new(move |line: usize, selected: bool, out: &mut String| {
-        if line == last_line && !draw_last {
-            write!(out, "{:>1$}", '~', width).unwrap();
-            Some(linenr)
-        } else {
-            use crate::editor::LineNumber;
-            let line = match config {
-                LineNumber::Absolute => line + 1,
-                LineNumber::Relative => {
-                    if current_line == line {
-                        line + 1
-                    } else {
-                        abs_diff(current_line, line)
+    Box::new(
+        move |line: usize, selected: bool, is_wrapped: bool, out: &mut GutterText| {
+            if is_wrapped {
+                write_marker(out, "↪");
+                Some(linenr)
+            } else if line == last_line && !draw_last {
+                write_marker(out, &end_of_buffer_marker);
+                Some(linenr)
+            } else {
+                use crate::editor::LineNumber;
+                let number = match config {
+                    LineNumber::Absolute => line + 1,
+                    LineNumber::Relative => {
+                        if current_line == line {
+                            line + 1
+                        } else {
+                            abs_diff(current_line, line)
+                        }
+                    }
+                    LineNumber::RelativeZero => abs_diff(current_line, line),
+                };
+                let style = if let Some(style) = style_override
+                    .and_then(|style_override| style_override(doc, line, selected && is_focused))
+                {
+                    style
+                } else if selected && is_focused {
+                    match color_by_severity
+                        .then(|| line_severity(diagnostics, line))
+                        .flatten()
+                    {
+                        Some(Severity::Error) => linenr_select.patch(error),
+                        Some(Severity::Warning) => linenr_select.patch(warning),
+                        Some(Severity::Info) => linenr_select.patch(info),
+                        Some(Severity::Hint) => linenr_select.patch(hint),
+                        None => linenr_select,
+                    }
+                } else if matches!(dim_distance, Some(distance) if abs_diff(current_line, line) > distance)
+                {
+                    linenr_dim
+                } else {
+                    linenr
+                };
+                if let Some(separator) = group_separator {
+                    let digits = match radix {
+                        LineNumberRadix::Decimal => number.to_string(),
+                        LineNumberRadix::Hexadecimal => format!("{:x}", number),
+                    };
+                    let number = group_digits(&digits, separator, radix.group_len());
+                    match align {
+                        LineNumberAlign::Right => write!(out, "{:>1$}", number, width),
+                        LineNumberAlign::Left => write!(out, "{:<1$}", number, width),
                     }
+                    .unwrap();
+                    return Some(style);
                 }
-            };
-            let style = if selected && is_focused {
-                linenr_select
-            } else {
-                linenr
-            };
-            write!(out, "{:>1$}", line, width).unwrap();
-            Some(style)
+                match (radix, align) {
+                    (LineNumberRadix::Decimal, LineNumberAlign::Right) => {
+                        write!(out, "{:>1$}", number, width)
+                    }
+                    (LineNumberRadix::Decimal, LineNumberAlign::Left) => {
+                        write!(out, "{:<1$}", number, width)
+                    }
+                    (LineNumberRadix::Hexadecimal, LineNumberAlign::Right) => {
+                        write!(out, "{:>1$x}", number, width)
+                    }
+                    (LineNumberRadix::Hexadecimal, LineNumberAlign::Left) => {
+                        write!(out, "{:<1$x}", number, width)
+                    }
+                }
+                .unwrap();
+                Some(style)
+            }
+        },
+    )
+}
+
+/// Puts `separator` between groups of `group_len` digits, counted from the right.
+///
+/// ```
+/// use helix_view::gutter::group_digits;
+///
+/// assert_eq!(group_digits("1234567", ',', 3), "1,234,567");
+/// assert_eq!(group_digits("123", ',', 3), "123");
+/// ```
+pub fn group_digits(digits: &str, separator: char, group_len: usize) -> String {
+    let len = digits.chars().count();
+    let mut grouped = String::with_capacity(digits.len() + len / group_len * separator.len_utf8());
+    // digits left in the current group, the first group may be shorter
+    let mut remaining = match len % group_len {
+        0 => group_len,
+        n => n,
+    };
+    for digit in digits.chars() {
+        if remaining == 0 {
+            grouped.push(separator);
+            remaining = group_len;
         }
-    })
+        grouped.push(digit);
+        remaining -= 1;
+    }
+    grouped
+}
+
+/// Number of decimal digits of `n`. Zero has one digit.
+///
+/// ```
+/// use helix_view::gutter::digits10;
+///
+/// assert_eq!(digits10(0), 1);
+/// assert_eq!(digits10(9), 1);
+/// assert_eq!(digits10(10), 2);
+/// ```
+pub fn digits10(n: usize) -> usize {
+    digits_in_base(n, 10)
+}
+
+/// Number of digits of `n` written in `base`. Zero has one digit.
+///
+/// # Panics
+///
+/// Panics if `base` is less than 2.
+///
+/// ```
+/// use helix_view::gutter::digits_in_base;
+///
+/// assert_eq!(digits_in_base(15, 16), 1);
+/// assert_eq!(digits_in_base(16, 16), 2);
+/// ```
+pub fn digits_in_base(n: usize, base: usize) -> usize {
+    assert!(base >= 2, "base must be at least 2, got {}", base);
+    std::iter::successors(Some(n), |&n| if n >= base { Some(n / base) } else { None }).count()
 }
 
 #[inline(always)]
@@ -94,3 +1193,990 @@ const fn abs_diff(a: usize, b: usize) -> usize {
         b - a
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helix_core::diagnostic::Range;
+
+    fn diagnostic(line: usize, severity: Option<Severity>) -> Diagnostic {
+        Diagnostic {
+            range: Range { start: 0, end: 0 },
+            line,
+            message: String::new(),
+            severity,
+            tags: Vec::new(),
+            has_code_actions: false,
+            source: None,
+        }
+    }
+
+    /// A document with `text` and a view of it showing `gutters`, with the cursor at the start.
+    /// The line numbers are sized to the document rather than padded to the minimum width.
+    fn fixture(text: &str, gutters: impl Into<GutterRegistry>) -> (Document, View) {
+        use crate::{editor::GutterConfig, DocumentId};
+        use helix_core::{Rope, Selection};
+
+        let mut doc = Document::from(Rope::from(text), None);
+        let gutter_config = GutterConfig {
+            line_numbers_min_width: 0,
+            ..Default::default()
+        };
+        let view = View::new(DocumentId::default(), gutters, gutter_config);
+        doc.set_selection(view.id, Selection::point(0));
+        (doc, view)
+    }
+
+    #[test]
+    fn test_line_severity() {
+        let diagnostics = [
+            diagnostic(0, Some(Severity::Info)),
+            diagnostic(1, Some(Severity::Hint)),
+            diagnostic(1, Some(Severity::Error)),
+            diagnostic(1, Some(Severity::Warning)),
+            diagnostic(2, Some(Severity::Hint)),
+            diagnostic(2, None),
+            diagnostic(4, Some(Severity::Hint)),
+        ];
+
+        assert_eq!(line_severity(&diagnostics, 0), Some(Severity::Info));
+        assert_eq!(line_severity(&diagnostics, 1), Some(Severity::Error));
+        assert_eq!(line_severity(&diagnostics, 2), Some(Severity::Warning));
+        assert_eq!(line_severity(&diagnostics, 3), None);
+        assert_eq!(line_severity(&diagnostics, 4), Some(Severity::Hint));
+        assert_eq!(line_severity(&diagnostics, 5), None);
+    }
+
+    #[test]
+    fn test_diagnostic_symbols_width() {
+        use crate::editor::DiagnosticSymbols;
+
+        let symbols: DiagnosticSymbols =
+            toml::from_str("error = \"✗\"\nhint = \"e\\u0301\"").unwrap();
+        assert_eq!(symbols.error, "✗");
+        assert_eq!(symbols.hint, "e\u{301}");
+        assert_eq!(symbols.warning, "●");
+
+        let err = toml::from_str::<DiagnosticSymbols>("error = \"🔥\"").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("gutter symbol \"🔥\" is 2 cells wide, but has to be 1 cell wide"));
+        assert!(toml::from_str::<DiagnosticSymbols>("error = \"EE\"").is_err());
+        assert!(toml::from_str::<DiagnosticSymbols>("error = \"\"").is_err());
+    }
+
+    #[test]
+    fn test_digits10() {
+        assert_eq!(digits10(0), 1);
+        assert_eq!(digits10(9), 1);
+        assert_eq!(digits10(10), 2);
+        assert_eq!(digits10(99), 2);
+        assert_eq!(digits10(100), 3);
+        assert_eq!(digits10(usize::MAX), usize::MAX.to_string().len());
+    }
+
+    #[test]
+    fn test_digits_in_base() {
+        assert_eq!(digits_in_base(0, 16), 1);
+        assert_eq!(digits_in_base(0xff, 16), 2);
+        assert_eq!(digits_in_base(0x100, 16), 3);
+        assert_eq!(digits_in_base(usize::MAX, 16), usize::BITS as usize / 4);
+        assert_eq!(digits_in_base(8, 2), 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_digits_in_base_1() {
+        digits_in_base(5, 1);
+    }
+
+    #[test]
+    fn test_line_number_hexadecimal() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+
+        let (doc, mut view) = fixture(&"line\n".repeat(300), vec![LINE_NUMBER_GUTTER]);
+        view.gutter_config.line_numbers_radix = LineNumberRadix::Hexadecimal;
+        view.area = Rect::new(0, 0, 40, 300);
+        let mut config = Config::default();
+        let render = |config: &Config, line| {
+            LINE_NUMBER_GUTTER
+                .render_line(&doc, &view, &DEFAULT_THEME, config, line)
+                .0
+        };
+
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 3);
+        assert_eq!(render(&config, 254), " ff");
+        assert_eq!(render(&config, 255), "100");
+
+        config.line_number = crate::editor::LineNumber::Relative;
+        assert_eq!(render(&config, 26), " 1a");
+    }
+
+    #[test]
+    fn test_line_number_dim() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+        use helix_core::Selection;
+
+        let (mut doc, mut view) = fixture(&"a\n".repeat(10), vec![LINE_NUMBER_GUTTER]);
+        view.area = Rect::new(0, 0, 40, 20);
+        // the cursor is on line 4
+        doc.set_selection(view.id, Selection::point(8));
+        let config = Config::default();
+        let style = |view: &View, line| {
+            LINE_NUMBER_GUTTER
+                .render_line(&doc, view, &DEFAULT_THEME, &config, line)
+                .1
+                .unwrap()
+        };
+        let linenr = DEFAULT_THEME.get("ui.linenr");
+        assert_eq!(style(&view, 0), linenr);
+
+        view.gutter_config.line_numbers_dim_distance = Some(2);
+        let dim = linenr.add_modifier(Modifier::DIM);
+        assert_eq!(style(&view, 1), dim);
+        assert_eq!(style(&view, 2), linenr);
+        assert_eq!(style(&view, 4), DEFAULT_THEME.get("ui.linenr.selected"));
+        assert_eq!(style(&view, 6), linenr);
+        assert_eq!(style(&view, 7), dim);
+    }
+
+    #[test]
+    fn test_line_number_last_line() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+
+        let render = |text: &str, height: u16, line: usize| {
+            let (doc, mut view) = fixture(text, vec![LINE_NUMBER_GUTTER]);
+            view.area = Rect::new(0, 0, 40, height);
+            LINE_NUMBER_GUTTER
+                .render_line(&doc, &view, &DEFAULT_THEME, &Config::default(), line)
+                .0
+        };
+
+        // ending with a newline
+        assert_eq!(render("a\nb\n", 10, 1), "2");
+        assert_eq!(render("a\nb\n", 10, 2), "~");
+        // not ending with a newline, also when the view is too small to show all lines
+        assert_eq!(render("a\nb", 10, 1), "2");
+        assert_eq!(render("a\nb", 2, 1), "2");
+        assert_eq!(render("a\nb", 2, 0), "1");
+        // empty
+        assert_eq!(render("", 10, 0), "1");
+        assert_eq!(render("\n", 10, 0), "1");
+        assert_eq!(render("\n", 10, 1), "~");
+    }
+
+    #[test]
+    fn test_end_of_buffer_marker() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+
+        let render = |marker: &str, align: LineNumberAlign| {
+            let (doc, mut view) = fixture(&"line\n".repeat(20), vec![LINE_NUMBER_GUTTER]);
+            view.gutter_config.end_of_buffer_marker = marker.to_owned();
+            view.gutter_config.line_numbers_align = align;
+            view.area = Rect::new(0, 0, 40, 30);
+            LINE_NUMBER_GUTTER
+                .render_line(&doc, &view, &DEFAULT_THEME, &Config::default(), 20)
+                .0
+        };
+
+        assert_eq!(render("~", LineNumberAlign::Right), " ~");
+        assert_eq!(render("·", LineNumberAlign::Left), "· ");
+        assert_eq!(render("", LineNumberAlign::Right), "  ");
+        // combining characters don't take up a cell
+        assert_eq!(render("e\u{301}", LineNumberAlign::Right), " e\u{301}");
+    }
+
+    #[test]
+    fn test_line_number_align() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+
+        let (doc, mut view) = fixture(&"line\n".repeat(1000), vec![LINE_NUMBER_GUTTER]);
+        view.area = Rect::new(0, 0, 40, 1100);
+        let mut config = Config::default();
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 4);
+
+        let render = |view: &View, config: &Config, line| {
+            LINE_NUMBER_GUTTER
+                .render_line(&doc, view, &DEFAULT_THEME, config, line)
+                .0
+        };
+        assert_eq!(render(&view, &config, 41), "  42");
+        assert_eq!(render(&view, &config, 1000), "   ~");
+        config.line_number = crate::editor::LineNumber::Relative;
+        assert_eq!(render(&view, &config, 3), "   3");
+
+        view.gutter_config.line_numbers_align = LineNumberAlign::Left;
+        config.line_number = crate::editor::LineNumber::Absolute;
+        assert_eq!(render(&view, &config, 41), "42  ");
+        assert_eq!(render(&view, &config, 1000), "~   ");
+        config.line_number = crate::editor::LineNumber::Relative;
+        assert_eq!(render(&view, &config, 3), "3   ");
+    }
+
+    #[test]
+    fn test_line_number_width_fit_view() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+
+        let (doc, mut view) = fixture(&"line\n".repeat(200), vec![LINE_NUMBER_GUTTER]);
+        view.area = Rect::new(0, 0, 40, 11);
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 3);
+
+        view.gutter_config.line_numbers_fit_view = true;
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 2);
+        view.offset.row = 95;
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 3);
+    }
+
+    #[test]
+    fn test_line_number_min_width() {
+        use crate::{editor::GutterConfig, theme::DEFAULT_THEME};
+
+        let (doc, mut view) = fixture(&"line\n".repeat(20), vec![LINE_NUMBER_GUTTER]);
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 2);
+
+        view.gutter_config.line_numbers_min_width = 4;
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 4);
+        let config = Config::default();
+        let render = |line| {
+            LINE_NUMBER_GUTTER
+                .render_line(&doc, &view, &DEFAULT_THEME, &config, line)
+                .0
+        };
+        assert_eq!(render(0), "   1");
+        assert_eq!(render(19), "  20");
+        assert_eq!(render(20), "   ~");
+
+        // longer line numbers still fit
+        view.gutter_config.line_numbers_min_width = 1;
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 2);
+
+        view.gutter_config = GutterConfig::default();
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 5);
+    }
+
+    #[test]
+    fn test_line_number_group_separator() {
+        use crate::{
+            editor::{GutterConfig, LineNumber},
+            graphics::Rect,
+            theme::DEFAULT_THEME,
+        };
+
+        let (doc, mut view) = fixture(&"line\n".repeat(1500), vec![LINE_NUMBER_GUTTER]);
+        view.gutter_config.line_numbers_group_separator = Some(',');
+        view.area = Rect::new(0, 0, 40, 10);
+        assert_eq!((LINE_NUMBER_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 5);
+
+        let render = |line, line_number| {
+            let config = Config {
+                line_number,
+                ..Config::default()
+            };
+            LINE_NUMBER_GUTTER
+                .render_line(&doc, &view, &DEFAULT_THEME, &config, line)
+                .0
+        };
+        assert_eq!(render(0, LineNumber::Absolute), "    1");
+        assert_eq!(render(1233, LineNumber::Absolute), "1,234");
+        assert_eq!(render(0, LineNumber::Relative), "    1");
+        assert_eq!(render(1499, LineNumber::Relative), "1,499");
+
+        assert!(
+            toml::from_str::<GutterConfig>("line-numbers-group-separator = \"\u{2009}\"").is_ok()
+        );
+        assert!(
+            toml::from_str::<GutterConfig>("line-numbers-group-separator = \"\u{4e00}\"").is_err()
+        );
+    }
+
+    #[test]
+    fn test_line_number_wrapped() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+
+        let (doc, mut view) = fixture("a\nb\nc\n", vec![LINE_NUMBER_GUTTER]);
+        view.area = Rect::new(0, 0, 40, 10);
+        let config = Config::default();
+        let render = line_number(&doc, &view, &DEFAULT_THEME, &config, true, 3);
+
+        let mut out = GutterText::default();
+        render(1, false, false, &mut out);
+        assert_eq!(out.as_str(), "  2");
+        out.clear();
+        render(1, false, true, &mut out);
+        assert_eq!(out.as_str(), "  ↪");
+    }
+
+    #[test]
+    fn test_diagnostic_width() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, view) = fixture("a\nb\n", vec![DIAGNOSTIC_GUTTER]);
+        assert_eq!((DIAGNOSTIC_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 0);
+
+        doc.set_diagnostics(vec![diagnostic(1, Some(Severity::Error))]);
+        assert_eq!((DIAGNOSTIC_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 1);
+    }
+
+    #[test]
+    fn test_diagnostic_tags() {
+        use crate::Theme;
+
+        let theme: Theme = toml::from_str(
+            r#"
+            "warning" = "yellow"
+            "ui.gutter.unnecessary" = "gray"
+            "#,
+        )
+        .unwrap();
+        let tagged = |line, severity| Diagnostic {
+            tags: vec![DiagnosticTag::Unnecessary],
+            ..diagnostic(line, Some(severity))
+        };
+
+        let (mut doc, view) = fixture(&"a\n".repeat(3), vec![DIAGNOSTIC_GUTTER]);
+        doc.set_diagnostics(vec![
+            tagged(0, Severity::Warning),
+            tagged(1, Severity::Warning),
+            diagnostic(1, Some(Severity::Warning)),
+            tagged(2, Severity::Hint),
+            diagnostic(2, Some(Severity::Warning)),
+        ]);
+        let config = Config::default();
+        let render = |line| DIAGNOSTIC_GUTTER.render_line(&doc, &view, &theme, &config, line);
+        assert_eq!(render(0).1, theme.try_get("ui.gutter.unnecessary"));
+        // only some of the most severe diagnostics are unnecessary
+        assert_eq!(render(1).1, theme.try_get("warning"));
+        assert_eq!(render(2).1, theme.try_get("warning"));
+
+        // themes without the scope keep the severity style
+        assert_eq!(
+            DIAGNOSTIC_GUTTER
+                .render_line(&doc, &view, &crate::theme::DEFAULT_THEME, &config, 0)
+                .1,
+            Some(crate::theme::DEFAULT_THEME.get("warning"))
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_min_severity() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, mut view) = fixture(&"a\n".repeat(3), vec![DIAGNOSTIC_GUTTER]);
+        view.gutter_config.diagnostics_width = 2;
+        view.gutter_config.diagnostics_min_severity = Severity::Info;
+        doc.set_diagnostics(vec![diagnostic(0, Some(Severity::Hint))]);
+        assert_eq!((DIAGNOSTIC_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 0);
+
+        doc.set_diagnostics(vec![
+            diagnostic(0, Some(Severity::Hint)),
+            diagnostic(1, Some(Severity::Hint)),
+            diagnostic(1, Some(Severity::Hint)),
+            diagnostic(1, Some(Severity::Info)),
+            diagnostic(2, Some(Severity::Info)),
+            diagnostic(2, None),
+        ]);
+        assert_eq!((DIAGNOSTIC_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 2);
+        let config = Config::default();
+        let render =
+            |line| DIAGNOSTIC_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+        assert_eq!(render(0), (String::new(), None));
+        assert_eq!(
+            render(1),
+            (" ●".to_string(), Some(DEFAULT_THEME.get("info")))
+        );
+        assert_eq!(
+            render(2),
+            (" 2".to_string(), Some(DEFAULT_THEME.get("warning")))
+        );
+        // the hints are still part of the document
+        assert_eq!(doc.diagnostics().len(), 6);
+    }
+
+    #[test]
+    fn test_diagnostic_code_actions() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, mut view) = fixture("a\nb\n", vec![DIAGNOSTIC_GUTTER]);
+        doc.set_diagnostics(vec![
+            diagnostic(0, Some(Severity::Error)),
+            diagnostic(1, Some(Severity::Error)),
+        ]);
+        doc.mark_code_actions(1, "");
+
+        let config = Config::default();
+        let render = super::diagnostic(&doc, &view, &DEFAULT_THEME, &config, true, 1);
+        let mut out = GutterText::default();
+        render(1, false, false, &mut out);
+        assert_eq!(out.as_str(), "●");
+
+        view.gutter_config.diagnostics_code_actions = true;
+        let render = super::diagnostic(&doc, &view, &DEFAULT_THEME, &config, true, 1);
+        out.clear();
+        render(0, false, false, &mut out);
+        assert_eq!(out.as_str(), "●");
+        out.clear();
+        render(1, false, false, &mut out);
+        assert_eq!(out.as_str(), "◆");
+    }
+
+    #[test]
+    fn test_diagnostic_stacked() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, mut view) = fixture(&"a\n".repeat(2), vec![DIAGNOSTIC_GUTTER]);
+        view.gutter_config.diagnostics_stacked = true;
+        doc.set_diagnostics(vec![
+            diagnostic(0, Some(Severity::Hint)),
+            diagnostic(0, Some(Severity::Error)),
+            diagnostic(0, None),
+            diagnostic(1, Some(Severity::Info)),
+            diagnostic(1, Some(Severity::Info)),
+        ]);
+        let config = Config::default();
+        let render = |width, line| {
+            let render = super::diagnostic(&doc, &view, &DEFAULT_THEME, &config, true, width);
+            let mut out = GutterText::default();
+            let style = render(line, false, false, &mut out).unwrap();
+            out.spans(style)
+                .map(|(text, style)| (text.to_string(), style))
+                .collect::<Vec<_>>()
+        };
+        let error = DEFAULT_THEME.get("error");
+        let warning = error.patch(DEFAULT_THEME.get("warning"));
+
+        assert_eq!(render(1, 0), vec![("●".to_string(), error)]);
+        assert_eq!(
+            render(2, 0),
+            vec![("●".to_string(), error), ("●".to_string(), warning)]
+        );
+        assert_eq!(
+            render(3, 0),
+            vec![(" ●".to_string(), error), ("●".to_string(), warning)]
+        );
+        // a single severity is shown like without stacking
+        assert_eq!(
+            render(2, 1),
+            vec![(" 2".to_string(), DEFAULT_THEME.get("info"))]
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_source_priority() {
+        use crate::theme::DEFAULT_THEME;
+
+        let from = |line, severity, source: &str| Diagnostic {
+            source: Some(source.to_string()),
+            ..diagnostic(line, Some(severity))
+        };
+        let (mut doc, mut view) = fixture(&"a\n".repeat(3), vec![DIAGNOSTIC_GUTTER]);
+        doc.set_diagnostics(vec![
+            from(0, Severity::Error, "clippy"),
+            from(0, Severity::Hint, "rustc"),
+            from(0, Severity::Warning, "rustc"),
+            from(1, Severity::Error, "clippy"),
+            diagnostic(1, Some(Severity::Hint)),
+            from(2, Severity::Error, "other"),
+            from(2, Severity::Info, "clippy"),
+        ]);
+        let config = Config::default();
+        let render = |view: &View, line| {
+            DIAGNOSTIC_GUTTER.render_line(&doc, view, &DEFAULT_THEME, &config, line)
+        };
+        let error = Some(DEFAULT_THEME.get("error"));
+        let warning = Some(DEFAULT_THEME.get("warning"));
+        let info = Some(DEFAULT_THEME.get("info"));
+
+        // by severity only
+        assert_eq!(render(&view, 0), ("●".to_string(), error));
+
+        view.gutter_config.diagnostics_source_priority =
+            vec!["rustc".to_string(), "clippy".to_string()];
+        assert_eq!(render(&view, 0), ("●".to_string(), warning));
+        // listed sources win over diagnostics without a source
+        assert_eq!(render(&view, 1), ("●".to_string(), error));
+        assert_eq!(render(&view, 2), ("●".to_string(), info));
+    }
+
+    #[test]
+    fn test_gutter_text_spans() {
+        use crate::graphics::{Color, Modifier};
+
+        let base = Style::default().fg(Color::Red);
+        let mut out = GutterText::default();
+        out.set_style(Style::default().bg(Color::Blue));
+        write!(out, "ab").unwrap();
+        out.set_style(Style::default().add_modifier(Modifier::BOLD));
+        out.set_style(Style::default().fg(Color::Green));
+        out.push('c');
+        assert_eq!(
+            out.spans(base).collect::<Vec<_>>(),
+            vec![("ab", base.bg(Color::Blue)), ("c", base.fg(Color::Green)),]
+        );
+
+        out.clear();
+        write!(out, "d").unwrap();
+        assert_eq!(out.spans(base).collect::<Vec<_>>(), vec![("d", base)]);
+        assert!(GutterText::default().spans(base).next().is_none());
+    }
+
+    #[test]
+    fn test_diagnostic_count() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, mut view) = fixture(&"a\n".repeat(3), vec![DIAGNOSTIC_GUTTER]);
+        let mut diagnostics = vec![
+            diagnostic(0, Some(Severity::Warning)),
+            diagnostic(1, Some(Severity::Hint)),
+        ];
+        diagnostics.extend((0..12).map(|_| diagnostic(1, Some(Severity::Error))));
+        diagnostics.push(diagnostic(2, Some(Severity::Info)));
+        diagnostics.push(diagnostic(2, None));
+        doc.set_diagnostics(diagnostics);
+        let config = Config::default();
+        let render = |view: &View, line| {
+            let width = (DIAGNOSTIC_GUTTER.width)(view, &doc, &DEFAULT_THEME);
+            let render = super::diagnostic(&doc, view, &DEFAULT_THEME, &config, true, width);
+            let mut out = GutterText::default();
+            let style = render(line, false, false, &mut out);
+            (out.text, style)
+        };
+
+        assert_eq!(
+            render(&view, 1),
+            ("●".to_string(), Some(DEFAULT_THEME.get("error")))
+        );
+        assert_eq!(
+            render(&view, 2),
+            ("●".to_string(), Some(DEFAULT_THEME.get("warning")))
+        );
+
+        view.gutter_config.diagnostics_width = 2;
+        assert_eq!((DIAGNOSTIC_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 2);
+        assert_eq!(
+            render(&view, 0),
+            (" ●".to_string(), Some(DEFAULT_THEME.get("warning")))
+        );
+        assert_eq!(
+            render(&view, 1),
+            ("13".to_string(), Some(DEFAULT_THEME.get("error")))
+        );
+        assert_eq!(
+            render(&view, 2),
+            (" 2".to_string(), Some(DEFAULT_THEME.get("warning")))
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        use crate::{theme::DEFAULT_THEME, view::Fold};
+
+        let (doc, mut view) = fixture(&"a\n".repeat(10), vec![FOLD_GUTTER]);
+        view.set_folds(vec![
+            Fold {
+                start: 4,
+                end: 6,
+                collapsed: false,
+            },
+            Fold {
+                start: 1,
+                end: 5,
+                collapsed: true,
+            },
+            Fold {
+                start: 2,
+                end: 3,
+                collapsed: false,
+            },
+            Fold {
+                start: 7,
+                end: 8,
+                collapsed: false,
+            },
+        ]);
+        let config = Config::default();
+
+        let markers: Vec<_> = (0..10)
+            .map(|line| {
+                FOLD_GUTTER
+                    .render_line(&doc, &view, &DEFAULT_THEME, &config, line)
+                    .0
+            })
+            .collect();
+        assert_eq!(markers, ["", "▸", "", "", "", "", "", "▾", "", ""]);
+    }
+
+    #[test]
+    fn test_blame() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, view) = fixture("a\nb\nc\n", vec![BLAME_GUTTER]);
+        let config = Config::default();
+        assert_eq!((BLAME_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 8);
+        assert_eq!(
+            BLAME_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, 0),
+            (String::new(), None)
+        );
+
+        doc.set_blame(Some(vec!["0123456789abcdef".to_string(), String::new()]));
+        let render = |line| BLAME_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+        assert_eq!(
+            render(0),
+            (
+                "0123456".to_string(),
+                Some(DEFAULT_THEME.get("ui.gutter.blame"))
+            )
+        );
+        assert_eq!(render(1), (String::new(), None));
+        assert_eq!(render(2), (String::new(), None));
+    }
+
+    #[test]
+    fn test_mark() {
+        use crate::{document::Mark, theme::DEFAULT_THEME};
+        use helix_core::{Selection, Transaction};
+
+        let (mut doc, view) = fixture(&"a\n".repeat(5), vec![MARK_GUTTER]);
+        doc.set_marks(vec![
+            Mark { line: 3, name: 'b' },
+            Mark { line: 1, name: 'a' },
+            Mark { line: 9, name: 'c' },
+        ]);
+        assert_eq!(doc.marks().len(), 2);
+
+        // marks stay on their lines when lines are inserted above
+        let transaction = Transaction::insert(doc.text(), &Selection::point(0), "x\n".into());
+        doc.apply(&transaction, view.id);
+
+        let config = Config::default();
+        let render = |line| MARK_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+        assert_eq!(render(1), (String::new(), None));
+        assert_eq!(
+            render(2),
+            ("a".to_string(), Some(DEFAULT_THEME.get("ui.gutter.mark")))
+        );
+        assert_eq!(
+            render(4),
+            ("b".to_string(), Some(DEFAULT_THEME.get("ui.gutter.mark")))
+        );
+    }
+
+    #[test]
+    fn test_cursor() {
+        use crate::theme::DEFAULT_THEME;
+        use helix_core::{Range, Selection};
+
+        let (mut doc, view) = fixture(&"a\n".repeat(5), vec![CURSOR_GUTTER]);
+        assert_eq!((CURSOR_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 0);
+
+        // the primary cursor is on line 2, the others on lines 0 and 3
+        let ranges = [Range::point(0), Range::point(4), Range::new(6, 8)];
+        doc.set_selection(view.id, Selection::new(ranges.into_iter().collect(), 1));
+        assert_eq!((CURSOR_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 1);
+
+        let config = Config::default();
+        let render = |line| CURSOR_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+        let style = Some(DEFAULT_THEME.get("ui.cursor"));
+        assert_eq!(render(0), ("▸".to_string(), style));
+        assert_eq!(render(1), (String::new(), None));
+        assert_eq!(render(2), (String::new(), None));
+        assert_eq!(render(3), ("▸".to_string(), style));
+    }
+
+    #[test]
+    fn test_search() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, view) = fixture(&"a\n".repeat(5), vec![SEARCH_GUTTER]);
+        assert_eq!((SEARCH_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 0);
+
+        doc.set_search_matches(Some(vec![1, 3]));
+        assert_eq!((SEARCH_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 1);
+        let config = Config::default();
+        let render = |line| SEARCH_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+        let style = Some(DEFAULT_THEME.get("ui.gutter.search"));
+        assert_eq!(render(0), (String::new(), None));
+        assert_eq!(render(1), ("•".to_string(), style));
+        assert_eq!(render(3), ("•".to_string(), style));
+
+        // an active search without matches keeps the column
+        doc.set_search_matches(Some(Vec::new()));
+        assert_eq!((SEARCH_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 1);
+        doc.set_search_matches(None);
+        assert_eq!((SEARCH_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 0);
+    }
+
+    #[test]
+    fn test_jump() {
+        use crate::theme::DEFAULT_THEME;
+        use helix_core::Selection;
+
+        let (doc, mut view) = fixture(&"a\n".repeat(5), vec![JUMP_GUTTER]);
+        // the jumplist starts out with the position the view was opened at
+        assert_eq!((JUMP_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 1);
+        view.jumps.remove(&doc.id());
+        assert_eq!((JUMP_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 0);
+
+        view.jumps.push((doc.id(), Selection::point(2)));
+        view.jumps.push((doc.id(), Selection::point(7)));
+        // stale jumps past the end of the document end up on the last line
+        view.jumps.push((doc.id(), Selection::point(100)));
+        view.jumps.push((doc.id(), Selection::single(4, 100)));
+        assert_eq!((JUMP_GUTTER.width)(&view, &doc, &DEFAULT_THEME), 1);
+
+        let config = Config::default();
+        let render = |line| JUMP_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+        let style = Some(DEFAULT_THEME.get("ui.gutter.jump"));
+        assert_eq!(render(0), (String::new(), None));
+        assert_eq!(render(1), ("›".to_string(), style));
+        assert_eq!(render(3), ("›".to_string(), style));
+        assert_eq!(render(5), ("›".to_string(), style));
+    }
+
+    #[test]
+    fn test_dynamic_gutter() {
+        use crate::theme::DEFAULT_THEME;
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Numbers the rows in the order they are rendered.
+        #[derive(Default)]
+        struct Counter {
+            renders: AtomicUsize,
+        }
+
+        impl DynamicGutter for Counter {
+            fn name(&self) -> &str {
+                "counter"
+            }
+
+            fn width(&self, _view: &View, _doc: &Document, _theme: &Theme) -> usize {
+                2
+            }
+
+            fn render<'doc>(
+                &'doc self,
+                _doc: &'doc Document,
+                _view: &View,
+                _theme: &Theme,
+                _config: &Config,
+                _is_focused: bool,
+                _width: usize,
+            ) -> GutterFn<'doc> {
+                Box::new(move |_line, _selected, _wrapped, out| {
+                    let count = self.renders.fetch_add(1, Ordering::Relaxed);
+                    write!(out, "{}", count).unwrap();
+                    Some(Style::default())
+                })
+            }
+        }
+
+        let mut gutters = GutterRegistry::from(vec![LINE_NUMBER_GUTTER]);
+        let counter = Arc::new(Counter::default());
+        gutters.register(counter.clone());
+        let (doc, view) = fixture("a\n", gutters);
+        let names: Vec<_> = view.gutters().iter().map(GutterKind::name).collect();
+        assert_eq!(names, ["line-numbers", "counter"]);
+        assert_eq!(view.gutter_offset(&doc, &DEFAULT_THEME), 1 + 2 + 1);
+
+        let config = Config::default();
+        let render =
+            |gutter: &GutterKind| gutter.render_line(&doc, &view, &DEFAULT_THEME, &config, 0);
+        assert_eq!(render(&view.gutters()[1]).0, "0");
+        assert_eq!(render(&view.gutters()[1]).0, "1");
+        assert_eq!(counter.renders.load(Ordering::Relaxed), 2);
+        // static gutters render the same through the adapter
+        assert_eq!(
+            render(&view.gutters()[0]),
+            LINE_NUMBER_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, 0)
+        );
+
+        // registering a gutter of the same name replaces it in place
+        let mut gutters = view.gutters.clone();
+        gutters.register(Arc::new(Counter::default()));
+        gutters.register(SEPARATOR_GUTTER);
+        let names: Vec<_> = gutters.as_slice().iter().map(GutterKind::name).collect();
+        assert_eq!(names, ["line-numbers", "counter", "separator"]);
+        assert_eq!(render(&gutters.as_slice()[1]).0, "0");
+
+        assert_eq!(gutters.unregister("counter").unwrap().name(), "counter");
+        assert!(!gutters.contains("counter"));
+        assert!(gutters.unregister("counter").is_none());
+    }
+
+    #[test]
+    fn test_separator() {
+        use crate::{editor::GutterConfig, theme::DEFAULT_THEME};
+
+        let (doc, mut view) = fixture(
+            "a\n",
+            vec![
+                LINE_NUMBER_GUTTER,
+                SEPARATOR_GUTTER,
+                SPACER_GUTTER,
+                SEPARATOR_GUTTER,
+            ],
+        );
+        // the separators count towards the width of the gutters
+        assert_eq!(view.gutter_offset(&doc, &DEFAULT_THEME), 5);
+
+        let config = Config::default();
+        let style = Some(DEFAULT_THEME.get("ui.gutter.separator"));
+        assert_eq!(
+            SEPARATOR_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, 0),
+            ("│".to_string(), style)
+        );
+        view.gutter_config.separator = '|';
+        assert_eq!(
+            SEPARATOR_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, 0),
+            ("|".to_string(), style)
+        );
+
+        assert!(toml::from_str::<GutterConfig>("separator = \"┃\"").is_ok());
+        let err = toml::from_str::<GutterConfig>("separator = \"\u{4e00}\"").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("gutter separator '一' has to be 1 cell wide"));
+        assert!(toml::from_str::<GutterConfig>("separator = \"\\u0301\"").is_err());
+    }
+
+    #[test]
+    fn test_symbol() {
+        use crate::{document::Symbol, theme::DEFAULT_THEME};
+        use helix_core::{Selection, Transaction};
+
+        let (mut doc, view) = fixture(&"a\n".repeat(5), vec![SYMBOL_GUTTER]);
+        assert_eq!(doc.symbols_version(), None);
+        let symbol = |line, kind| Symbol { line, kind };
+        doc.set_symbols(
+            doc.version(),
+            vec![
+                symbol(3, SymbolKind::STRUCT),
+                symbol(1, SymbolKind::MODULE),
+                symbol(1, SymbolKind::FUNCTION),
+                symbol(3, SymbolKind::FIELD),
+                symbol(9, SymbolKind::FUNCTION),
+            ],
+        );
+        assert_eq!(doc.symbols_version(), Some(doc.version()));
+        assert_eq!(doc.symbols().len(), 2);
+
+        // symbols stay on their lines when lines are inserted above
+        let transaction = Transaction::insert(doc.text(), &Selection::point(0), "x\n".into());
+        doc.apply(&transaction, view.id);
+
+        let config = Config::default();
+        let render = |line| SYMBOL_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+        let style = Some(DEFAULT_THEME.get("ui.gutter.symbol"));
+        assert_eq!(render(1), (String::new(), None));
+        assert_eq!(render(2), ("M".to_string(), style));
+        assert_eq!(render(3), (String::new(), None));
+        assert_eq!(render(4), ("S".to_string(), style));
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        use crate::{document::Breakpoint, theme::DEFAULT_THEME};
+
+        let (mut doc, view) = fixture(&"a\n".repeat(5), vec![BREAKPOINT_GUTTER]);
+        doc.set_breakpoints(vec![
+            Breakpoint {
+                line: 1,
+                condition: None,
+            },
+            Breakpoint {
+                line: 3,
+                condition: Some("x > 1".to_string()),
+            },
+        ]);
+        doc.debug_active_line = Some(3);
+        let config = Config::default();
+        let render =
+            |line| BREAKPOINT_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, line);
+
+        assert_eq!(
+            render(1),
+            (
+                "●".to_string(),
+                Some(DEFAULT_THEME.get("ui.debug.breakpoint"))
+            )
+        );
+        assert_eq!(render(2), (String::new(), None));
+        assert_eq!(
+            render(3),
+            ("▶".to_string(), Some(DEFAULT_THEME.get("ui.debug.active")))
+        );
+    }
+
+    #[test]
+    fn test_line_number_diagnostic_color() {
+        use crate::{graphics::Rect, theme::DEFAULT_THEME};
+        use helix_core::Selection;
+
+        let (mut doc, mut view) = fixture("a\nb\nc\n", vec![LINE_NUMBER_GUTTER]);
+        view.gutter_config.line_numbers_diagnostic_color = true;
+        view.area = Rect::new(0, 0, 40, 10);
+        doc.set_selection(view.id, Selection::point(2));
+        doc.set_diagnostics(vec![diagnostic(1, Some(Severity::Error))]);
+        let config = Config::default();
+
+        let linenr = DEFAULT_THEME.get("ui.linenr");
+        let selected = DEFAULT_THEME
+            .try_get("ui.linenr.selected")
+            .unwrap_or(linenr);
+        assert_eq!(
+            LINE_NUMBER_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, 1),
+            (
+                "2".to_string(),
+                Some(selected.patch(DEFAULT_THEME.get("error")))
+            )
+        );
+        assert_eq!(
+            LINE_NUMBER_GUTTER.render_line(&doc, &view, &DEFAULT_THEME, &config, 0),
+            ("1".to_string(), Some(linenr))
+        );
+    }
+
+    #[test]
+    fn test_line_number_style() {
+        use crate::theme::DEFAULT_THEME;
+
+        let (mut doc, mut view) = fixture("a\nTODO\nc\n", vec![LINE_NUMBER_GUTTER]);
+        view.gutter_config.line_numbers_diagnostic_color = true;
+        doc.set_diagnostics(vec![diagnostic(0, Some(Severity::Warning))]);
+        let config = Config::default();
+        let todo = Style::default().fg(crate::graphics::Color::Yellow);
+        let gutter = GutterKind::from(Arc::new(StyledLineNumbers::new(Arc::new(
+            move |doc: &Document, line, _selected| {
+                let line = doc.text().line(line);
+                line.to_string().contains("TODO").then_some(todo)
+            },
+        ))));
+        assert_eq!(gutter.name(), "line-numbers");
+        let style = |line| {
+            gutter
+                .render_line(&doc, &view, &DEFAULT_THEME, &config, line)
+                .1
+                .unwrap()
+        };
+        let linenr = DEFAULT_THEME.get("ui.linenr");
+        let selected = DEFAULT_THEME
+            .try_get("ui.linenr.selected")
+            .unwrap_or(linenr);
+
+        assert_eq!(style(1), todo);
+        assert_eq!(style(2), linenr);
+        // lines the style leaves alone are styled as usual
+        assert_eq!(style(0), selected.patch(DEFAULT_THEME.get("warning")));
+
+        // the style also applies to selected lines
+        let gutter = GutterKind::from(Arc::new(StyledLineNumbers::new(Arc::new(
+            move |_: &Document, _, selected| selected.then_some(todo),
+        ))));
+        assert_eq!(
+            gutter
+                .render_line(&doc, &view, &DEFAULT_THEME, &config, 0)
+                .1,
+            Some(todo)
+        );
+    }
+}
@@ -11,6 +11,7 @@ pub mod input;
 pub mod keyboard;
 pub mod theme;
 pub mod tree;
+pub mod vcs;
 pub mod view;
 
 use std::num::NonZeroUsize;
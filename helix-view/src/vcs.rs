@@ -0,0 +1,161 @@
+//! Access to the version control system a file is tracked by.
+
+use crate::DocumentId;
+use helix_core::diff::{compare_lines, LineDiff};
+use helix_core::Rope;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// How long the diff worker waits for further edits before diffing a document.
+const DIFF_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Returns the contents `file` has in the latest git commit, which the document is diffed
+/// against for the diff gutter. Returns `None` if the file isn't tracked by git or git isn't
+/// available.
+pub async fn diff_base(file: PathBuf) -> Option<Rope> {
+    let dir = file.parent()?;
+    let name = file.file_name()?.to_str()?;
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("HEAD:./{}", name))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok().map(Rope::from)
+}
+
+/// A document's text to diff against its diff base, see [`diff_worker`].
+#[derive(Debug)]
+pub struct DiffRequest {
+    pub doc: DocumentId,
+    /// Version of the document `text` belongs to.
+    pub version: i32,
+    pub base: Rope,
+    pub text: Rope,
+}
+
+/// Line diffs of a document at a version, as sent by [`diff_worker`].
+pub type DiffResult = (DocumentId, i32, Vec<(usize, LineDiff)>);
+
+/// Diffs the documents of `requests` against their diff base and sends the line diffs to
+/// `results`. Requests are debounced, so only the latest text of each document gets diffed
+/// while it's being edited.
+pub async fn diff_worker(
+    mut requests: UnboundedReceiver<DiffRequest>,
+    results: UnboundedSender<DiffResult>,
+) {
+    while let Some(request) = requests.recv().await {
+        let mut pending = HashMap::new();
+        pending.insert(request.doc, request);
+        while let Ok(Some(request)) = tokio::time::timeout(DIFF_DEBOUNCE, requests.recv()).await {
+            pending.insert(request.doc, request);
+        }
+
+        for request in pending.into_values() {
+            let diff = tokio::task::spawn_blocking(move || {
+                let lines = compare_lines(&request.base, &request.text);
+                (request.doc, request.version, lines)
+            });
+            if let Ok(result) = diff.await {
+                if results.send(result).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the hash of the commit that last changed each line of `file`, as reported by
+/// `git blame`. Lines that aren't committed yet have an empty hash. Returns `None` if the file
+/// isn't tracked by git or git isn't available.
+pub async fn blame(file: PathBuf) -> Option<Vec<String>> {
+    let dir = file.parent()?;
+    let name = file.file_name()?;
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("blame")
+        .arg("--porcelain")
+        .arg("--")
+        .arg(name)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_blame(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the output of `git blame --porcelain` into the commit hash of each line.
+fn parse_blame(output: &str) -> Vec<String> {
+    let mut hashes = Vec::new();
+    let mut hash = "";
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            // the line's content ends each entry
+            let uncommitted = hash.bytes().all(|b| b == b'0');
+            hashes.push(if uncommitted { "" } else { hash }.to_string());
+        } else if let Some((first, _)) = line.split_once(' ') {
+            if first.len() == 40 && first.bytes().all(|b| b.is_ascii_hexdigit()) {
+                hash = first;
+            }
+        }
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_diff_worker_coalesces_requests() {
+        let (request_sender, requests) = tokio::sync::mpsc::unbounded_channel();
+        let (results, mut result_receiver) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(diff_worker(requests, results));
+
+        let base = Rope::from("a\nb\n");
+        for (version, text) in [(1, "a\nB\n"), (2, "a\nb\nc\n")] {
+            request_sender
+                .send(DiffRequest {
+                    doc: DocumentId::default(),
+                    version,
+                    base: base.clone(),
+                    text: Rope::from(text),
+                })
+                .unwrap();
+        }
+
+        let (doc, version, lines) = result_receiver.recv().await.unwrap();
+        assert_eq!(doc, DocumentId::default());
+        assert_eq!(version, 2);
+        assert_eq!(lines, vec![(2, LineDiff::Added)]);
+        drop(request_sender);
+        assert!(result_receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_parse_blame() {
+        let a = "a".repeat(40);
+        let zero = "0".repeat(40);
+        let output = format!(
+            "{a} 1 1 2\nauthor A\nsummary first\nfilename f\n\tone\n\
+             {a} 2 2\n\ttwo\n\
+             {zero} 3 3 1\nauthor Not Committed Yet\nfilename f\n\tthree\n"
+        );
+        assert_eq!(parse_blame(&output), vec![a.clone(), a, String::new()]);
+    }
+}
@@ -1,9 +1,10 @@
 use std::borrow::Cow;
 
 use crate::{
+    editor::GutterConfig,
     graphics::Rect,
-    gutter::{self, Gutter},
-    Document, DocumentId, ViewId,
+    gutter::{GutterKind, GutterRegistry},
+    Document, DocumentId, Theme, ViewId,
 };
 use helix_core::{
     graphemes::{grapheme_width, RopeGraphemes},
@@ -62,9 +63,19 @@ impl JumpList {
     pub fn remove(&mut self, doc_id: &DocumentId) {
         self.jumps.retain(|(other_id, _)| other_id != doc_id);
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Jump> {
+        self.jumps.iter()
+    }
 }
 
-const GUTTERS: &[(Gutter, usize)] = &[(gutter::diagnostic, 1), (gutter::line_number, 5)];
+/// A region of lines that can be folded, spanning `start..=end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub collapsed: bool,
+}
 
 #[derive(Debug)]
 pub struct View {
@@ -80,10 +91,18 @@ pub struct View {
     // uses two docs because we want to be able to swap between the
     // two last modified docs which we need to manually keep track of
     pub last_modified_docs: [Option<DocumentId>; 2],
+    /// Gutters to the left of the text, from left to right.
+    pub gutters: GutterRegistry,
+    pub gutter_config: GutterConfig,
+    folds: Vec<Fold>,
 }
 
 impl View {
-    pub fn new(doc: DocumentId) -> Self {
+    pub fn new(
+        doc: DocumentId,
+        gutters: impl Into<GutterRegistry>,
+        gutter_config: GutterConfig,
+    ) -> Self {
         Self {
             id: ViewId::default(),
             doc,
@@ -92,28 +111,82 @@ impl View {
             jumps: JumpList::new((doc, Selection::point(0))), // TODO: use actual sel
             last_accessed_doc: None,
             last_modified_docs: [None, None],
+            gutters: gutters.into(),
+            gutter_config,
+            folds: Vec::new(),
         }
     }
 
-    pub fn gutters(&self) -> &[(Gutter, usize)] {
-        GUTTERS
+    pub fn gutters(&self) -> &[GutterKind] {
+        self.gutters.as_slice()
     }
 
-    pub fn inner_area(&self) -> Rect {
-        // TODO: cache this
-        let offset = self
-            .gutters()
+    /// Folds sorted by their first line, outer folds before the folds nested in them.
+    #[inline]
+    pub fn folds(&self) -> &[Fold] {
+        &self.folds
+    }
+
+    pub fn set_folds(&mut self, mut folds: Vec<Fold>) {
+        folds.retain(|fold| fold.start <= fold.end);
+        folds.sort_unstable_by_key(|fold| (fold.start, std::cmp::Reverse(fold.end)));
+        self.folds = folds;
+    }
+
+    /// Number of columns taken up by the gutters, including the space before the text.
+    pub fn gutter_offset(&self, doc: &Document, theme: &Theme) -> u16 {
+        self.gutters()
             .iter()
-            .map(|(_, width)| *width as u16)
+            .map(|gutter| gutter.width(self, doc, theme) as u16)
             .sum::<u16>()
-            + 1; // +1 for some space between gutters and line
-        self.area.clip_left(offset).clip_bottom(1) // -1 for statusline
+            + 1 // +1 for some space between gutters and line
+    }
+
+    /// Returns the gutter at the given screen coordinates and the line shown there, if the
+    /// coordinates are within a gutter of this view.
+    pub fn gutter_at_screen_coords(
+        &self,
+        doc: &Document,
+        theme: &Theme,
+        row: u16,
+        column: u16,
+    ) -> Option<(&GutterKind, usize)> {
+        let area = self.area.clip_bottom(1); // -1 for statusline
+        if row < area.top() || row >= area.bottom() || column < area.left() {
+            return None;
+        }
+        let line = (row - area.y) as usize + self.offset.row;
+        if line >= doc.text().len_lines() {
+            return None;
+        }
+
+        let mut x = area.x;
+        for gutter in self.gutters() {
+            let width = gutter.width(self, doc, theme) as u16;
+            if column < x + width {
+                return Some((gutter, line));
+            }
+            x += width;
+        }
+        None
+    }
+
+    pub fn inner_area(&self, doc: &Document, theme: &Theme) -> Rect {
+        self.area
+            .clip_left(self.gutter_offset(doc, theme))
+            .clip_bottom(1) // -1 for statusline
+    }
+
+    /// Height of the text area, which unlike its width doesn't depend on the gutters.
+    pub fn inner_height(&self) -> usize {
+        self.area.clip_bottom(1).height as usize // -1 for statusline
     }
 
     //
     pub fn offset_coords_to_in_view(
         &self,
         doc: &Document,
+        theme: &Theme,
         scrolloff: usize,
     ) -> Option<(usize, usize)> {
         let cursor = doc
@@ -124,7 +197,7 @@ impl View {
         let Position { col, row: line } =
             visual_coords_at_pos(doc.text().slice(..), cursor, doc.tab_width());
 
-        let inner_area = self.inner_area();
+        let inner_area = self.inner_area(doc, theme);
         let last_line = (self.offset.row + inner_area.height as usize).saturating_sub(1);
 
         // - 1 so we have at least one gap in the middle.
@@ -160,24 +233,25 @@ impl View {
         }
     }
 
-    pub fn ensure_cursor_in_view(&mut self, doc: &Document, scrolloff: usize) {
-        if let Some((row, col)) = self.offset_coords_to_in_view(doc, scrolloff) {
+    pub fn ensure_cursor_in_view(&mut self, doc: &Document, theme: &Theme, scrolloff: usize) {
+        if let Some((row, col)) = self.offset_coords_to_in_view(doc, theme, scrolloff) {
             self.offset.row = row;
             self.offset.col = col;
         }
     }
 
-    pub fn is_cursor_in_view(&mut self, doc: &Document, scrolloff: usize) -> bool {
-        self.offset_coords_to_in_view(doc, scrolloff).is_none()
+    pub fn is_cursor_in_view(&mut self, doc: &Document, theme: &Theme, scrolloff: usize) -> bool {
+        self.offset_coords_to_in_view(doc, theme, scrolloff)
+            .is_none()
     }
 
     /// Calculates the last visible line on screen
     #[inline]
     pub fn last_line(&self, doc: &Document) -> usize {
-        let height = self.inner_area().height;
+        let height = self.inner_height();
         std::cmp::min(
             // Saturating subs to make it inclusive zero indexing.
-            (self.offset.row + height as usize).saturating_sub(1),
+            (self.offset.row + height).saturating_sub(1),
             doc.text().len_lines().saturating_sub(1),
         )
     }
@@ -221,12 +295,14 @@ impl View {
 
     pub fn text_pos_at_screen_coords(
         &self,
-        text: &RopeSlice,
+        doc: &Document,
+        theme: &Theme,
         row: u16,
         column: u16,
         tab_width: usize,
     ) -> Option<usize> {
-        let inner = self.inner_area();
+        let text = &doc.text().slice(..);
+        let inner = self.inner_area(doc, theme);
         // 1 for status
         if row < inner.top() || row >= inner.bottom() {
             return None;
@@ -267,8 +343,14 @@ impl View {
 
     /// Translates a screen position to position in the text document.
     /// Returns a usize typed position in bounds of the text if found in this view, None if out of view.
-    pub fn pos_at_screen_coords(&self, doc: &Document, row: u16, column: u16) -> Option<usize> {
-        self.text_pos_at_screen_coords(&doc.text().slice(..), row, column, doc.tab_width())
+    pub fn pos_at_screen_coords(
+        &self,
+        doc: &Document,
+        theme: &Theme,
+        row: u16,
+        column: u16,
+    ) -> Option<usize> {
+        self.text_pos_at_screen_coords(doc, theme, row, column, doc.tab_width())
     }
     // pub fn traverse<F>(&self, text: RopeSlice, start: usize, end: usize, fun: F)
     // where
@@ -295,118 +377,185 @@ impl View {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{gutter, theme::DEFAULT_THEME};
     use helix_core::Rope;
-    const OFFSET: u16 = 7; // 1 diagnostic + 5 linenr + 1 gutter
-                           // const OFFSET: u16 = GUTTERS.iter().map(|(_, width)| *width as u16).sum();
+    const OFFSET: u16 = 6; // 0 diagnostic (none in the doc) + 5 linenr + 1 gutter
 
     #[test]
     fn test_text_pos_at_screen_coords() {
-        let mut view = View::new(DocumentId::default());
+        let mut view = View::new(
+            DocumentId::default(),
+            vec![gutter::DIAGNOSTIC_GUTTER, gutter::LINE_NUMBER_GUTTER],
+            GutterConfig::default(),
+        );
         view.area = Rect::new(40, 40, 40, 40);
         let rope = Rope::from_str("abc\n\tdef");
-        let text = rope.slice(..);
+        let doc = Document::from(rope, None);
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 2, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 2, 4),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 41, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 41, 4),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 2, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 0, 2, 4),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 49, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 0, 49, 4),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 41, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 0, 41, 4),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 81, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 81, 4),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 78, 41, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 78, 41, 4),
+            None
+        );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 3, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 3, 4),
             Some(3)
         );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 80, 4), Some(3));
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 80, 4),
+            Some(3)
+        );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 1, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 41, 40 + OFFSET + 1, 4),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 4, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 41, 40 + OFFSET + 4, 4),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 7, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 41, 40 + OFFSET + 7, 4),
             Some(8)
         );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 41, 80, 4), Some(8));
+        assert_eq!(
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 41, 80, 4),
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn test_gutter_at_screen_coords() {
+        let mut view = View::new(
+            DocumentId::default(),
+            vec![
+                gutter::DIAGNOSTIC_GUTTER,
+                gutter::LINE_NUMBER_GUTTER,
+                gutter::DIFF_GUTTER,
+            ],
+            GutterConfig::default(),
+        );
+        view.area = Rect::new(40, 40, 40, 40);
+        let doc = Document::from(Rope::from_str("abc\n\tdef"), None);
+        let name = |row, column| {
+            view.gutter_at_screen_coords(&doc, &DEFAULT_THEME, row, column)
+                .map(|(gutter, line)| (gutter.name(), line))
+        };
+
+        assert_eq!(name(40, 40), Some(("line-numbers", 0)));
+        assert_eq!(name(41, 45), Some(("diff", 1)));
+        // the space between gutters and text
+        assert_eq!(name(40, 46), None);
+        // past the end of the document
+        assert_eq!(name(42, 40), None);
+        assert_eq!(name(39, 40), None);
+        assert_eq!(name(40, 39), None);
     }
 
     #[test]
     fn test_text_pos_at_screen_coords_cjk() {
-        let mut view = View::new(DocumentId::default());
+        let mut view = View::new(
+            DocumentId::default(),
+            vec![gutter::DIAGNOSTIC_GUTTER, gutter::LINE_NUMBER_GUTTER],
+            GutterConfig::default(),
+        );
         view.area = Rect::new(40, 40, 40, 40);
         let rope = Rope::from_str("Hi! こんにちは皆さん");
-        let text = rope.slice(..);
+        let doc = Document::from(rope, None);
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 0, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 0, 4),
             Some(0)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 5, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 5, 4),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 6, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 6, 4),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 7, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 7, 4),
             Some(6)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 8, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 8, 4),
             Some(6)
         );
     }
 
     #[test]
     fn test_text_pos_at_screen_coords_graphemes() {
-        let mut view = View::new(DocumentId::default());
+        let mut view = View::new(
+            DocumentId::default(),
+            vec![gutter::DIAGNOSTIC_GUTTER, gutter::LINE_NUMBER_GUTTER],
+            GutterConfig::default(),
+        );
         view.area = Rect::new(40, 40, 40, 40);
         let rope = Rope::from_str("Hèl̀l̀ò world!");
-        let text = rope.slice(..);
+        let doc = Document::from(rope, None);
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 0, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 0, 4),
             Some(0)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 1, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 1, 4),
             Some(1)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 2, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 2, 4),
             Some(3)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 3, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 3, 4),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 4, 4),
+            view.text_pos_at_screen_coords(&doc, &DEFAULT_THEME, 40, 40 + OFFSET + 4, 4),
             Some(7)
         );
     }